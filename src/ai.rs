@@ -1,21 +1,26 @@
-use std::rc::Rc;
+use std::{cell::RefCell, sync::Arc};
 
-use crate::terminal_borderpatrol::display::Player;
+use crate::{
+    borderpatrol::{BorderPatrol, BOARD_SIZE},
+    rng::{self, SeededRng},
+    terminal_borderpatrol::display::Player,
+};
 
 #[derive(Debug)]
 pub struct Line {
+    #[allow(dead_code)]
     value: usize,
 }
 
 #[derive(Debug)]
 pub struct Square {
     value: usize,
-    lines: [Option<Rc<Line>>; 4],
+    lines: [Option<Arc<Line>>; 4],
 }
 
 pub struct AdvancedPlayer {
     pub all_squares: Vec<Square>,
-    pub all_lines: Vec<Rc<Line>>,
+    pub all_lines: Vec<Arc<Line>>,
 }
 
 impl AdvancedPlayer {
@@ -44,7 +49,7 @@ impl AdvancedPlayer {
         }
 
         for i in 0..4 {
-            all_lines.push(Rc::new(Line {
+            all_lines.push(Arc::new(Line {
                 value: all_squares[0].value,
             }));
             all_squares[0].lines[i] = Some(all_lines[i].clone());
@@ -61,14 +66,14 @@ impl AdvancedPlayer {
 
     fn insert_lines(&mut self, square_id: usize, from_id: usize, side: usize) -> &mut Self {
         self.all_squares[square_id].lines[side] =
-            self.all_squares[from_id].lines[((side + 2) % 4)].clone();
+            self.all_squares[from_id].lines[(side + 2) % 4].clone();
 
         let row = square_id / 10;
         let col = square_id % 10;
 
         for i in 0..4 {
             if self.all_squares[square_id].lines[i].is_none() {
-                self.all_lines.push(Rc::new(Line {
+                self.all_lines.push(Arc::new(Line {
                     value: self.all_squares[square_id].value,
                 }));
                 self.all_squares[square_id].lines[i] = Some(self.all_lines.last().unwrap().clone());
@@ -95,7 +100,97 @@ impl AdvancedPlayer {
 impl Player for AdvancedPlayer {
     fn init(&mut self) {}
 
-    fn make_move(&self, border_patrol: &mut crate::borderpatrol::BorderPatrol) {
-        todo!()
+    /// Takes every box it can, otherwise draws a line that does not hand a box to the
+    /// opponent. If no such line is left it gives away as few boxes as possible.
+    fn choose_line(&self, border_patrol: &BorderPatrol) -> usize {
+        if let Some(line) = completing_line(border_patrol) {
+            return line;
+        }
+
+        if let Some(line) = safe_lines(border_patrol).next() {
+            return line;
+        }
+
+        legal_lines(border_patrol)
+            .min_by_key(|&line| boxes_given_away(border_patrol, line))
+            .unwrap()
+    }
+}
+
+pub fn legal_lines(border_patrol: &BorderPatrol) -> impl Iterator<Item = usize> + '_ {
+    (0..2 * BOARD_SIZE * BOARD_SIZE).filter(|&line| border_patrol.is_possible(line))
+}
+
+/// Lines after which neither adjacent box has three sides, so they do not hand out a box.
+pub fn safe_lines(border_patrol: &BorderPatrol) -> impl Iterator<Item = usize> + '_ {
+    legal_lines(border_patrol).filter(|&line| {
+        BorderPatrol::line_boxes(line)
+            .iter()
+            .all(|&(row, col)| border_patrol.box_sides(row, col) < 2)
+    })
+}
+
+/// Returns a line that completes a box, if there is one.
+pub fn completing_line(border_patrol: &BorderPatrol) -> Option<usize> {
+    legal_lines(border_patrol).find(|&line| {
+        BorderPatrol::line_boxes(line)
+            .iter()
+            .any(|&(row, col)| border_patrol.box_sides(row, col) == 3)
+    })
+}
+
+/// Number of boxes the opponent can take in a row after `line` is drawn.
+fn boxes_given_away(border_patrol: &BorderPatrol, line: usize) -> usize {
+    let mut border_patrol = border_patrol.clone();
+    border_patrol.set_line(line, border_patrol.game_info.turn);
+
+    let mut boxes = 0;
+    while let Some(line) = completing_line(&border_patrol) {
+        border_patrol.set_line(line, border_patrol.game_info.turn);
+        boxes += 1;
+    }
+    boxes
+}
+
+/// Completes a box whenever possible and plays randomly otherwise.
+pub struct GreedyPlayer {
+    rng: RefCell<SeededRng>,
+}
+
+impl GreedyPlayer {
+    pub fn new(seed: u64) -> Self {
+        GreedyPlayer {
+            rng: RefCell::new(rng::seeded(seed, rng::PLAYER)),
+        }
+    }
+}
+
+impl Player for GreedyPlayer {
+    fn init(&mut self) {}
+
+    fn choose_line(&self, border_patrol: &BorderPatrol) -> usize {
+        completing_line(border_patrol)
+            .unwrap_or_else(|| border_patrol.random_action(&mut *self.rng.borrow_mut()))
+    }
+}
+
+/// Draws a uniformly random legal line. The same seed always plays the same game.
+pub struct RandomPlayer {
+    rng: RefCell<SeededRng>,
+}
+
+impl RandomPlayer {
+    pub fn new(seed: u64) -> Self {
+        RandomPlayer {
+            rng: RefCell::new(rng::seeded(seed, rng::PLAYER)),
+        }
+    }
+}
+
+impl Player for RandomPlayer {
+    fn init(&mut self) {}
+
+    fn choose_line(&self, border_patrol: &BorderPatrol) -> usize {
+        border_patrol.random_action(&mut *self.rng.borrow_mut())
     }
 }
@@ -0,0 +1,683 @@
+#![allow(dead_code)]
+/*
+    An AlphaZero style agent. One network looks at the board from the side of the player to
+    move and predicts a policy over all lines together with the final score margin:
+
+        outputs 0..200   policy, one output per line
+        output  200      value, the final margin mapped from [-1, 1] to [0, 1]
+
+    All outputs share one activation, so the policy outputs are normalised over the legal
+    lines instead of going through a softmax. Moves are picked by a monte carlo tree
+    search guided by the network, and the network is trained on the visit counts of the
+    search and the outcome of the game.
+
+    A player keeps the turn after completing a box, so values in the tree are not simply
+    negated every ply but flipped whenever the player to move changes.
+*/
+use std::{fmt, time::Instant};
+
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng};
+
+use crate::{
+    borderpatrol::{BorderPatrol, BOARD_SIZE},
+    ml::HyperParameters,
+    model::{self, parse_value, ModelError, ModelMetadata, MODEL_FORMAT_VERSION},
+    network::{Activation, Network},
+    rng::{self, SeededRng},
+    terminal_borderpatrol::display::Player,
+};
+
+/// Name of the state encoding implemented by `encode`, stored in model files.
+pub const ENCODER: &str = "lines-margin";
+/// What `ENCODER` means, for consumers of exported networks.
+pub const ENCODER_DESCRIPTION: &str = "201 inputs: 1 for every drawn line and 0 for every free \
+line, numbered like the outputs, followed by the boxes of the player to move minus the boxes of \
+the opponent divided by 100.";
+/// What the outputs of the network mean, for consumers of exported networks.
+pub const OUTPUT_DESCRIPTION: &str = "200 policy outputs, one per line, where line \
+2 * (10 * row + column) is the bottom line of that box and the next one its right line, \
+normalised over the free lines, then the expected final margin mapped from [-1, 1] to [0, 1].";
+
+const LINES: usize = 2 * BOARD_SIZE * BOARD_SIZE;
+const BOXES: f64 = (BOARD_SIZE * BOARD_SIZE) as f64;
+
+#[derive(Debug, Clone)]
+pub struct AlphaZeroParameters {
+    /// Tree search simulations per move, each one evaluates the network once.
+    pub simulations: usize,
+    /// Weight of the network's policy against the values found by the search.
+    pub c_puct: f64,
+    /// Concentration of the dirichlet noise added to the root during self-play.
+    pub dirichlet_alpha: f64,
+    /// Share of the root priors replaced by noise during self-play.
+    pub noise_fraction: f64,
+    /// Self-play moves sampled in proportion to the visit counts, later moves are greedy.
+    pub temperature_moves: usize,
+    /// Self-play games per training run.
+    pub games: usize,
+    /// Passes over the positions of a game after it is finished.
+    pub epochs: u32,
+    /// Seeds network initialisation and self-play.
+    pub seed: u64,
+}
+
+impl Default for AlphaZeroParameters {
+    fn default() -> Self {
+        AlphaZeroParameters {
+            simulations: 50,
+            c_puct: 1.5,
+            dirichlet_alpha: 0.3,
+            noise_fraction: 0.25,
+            temperature_moves: 20,
+            games: 200,
+            epochs: 1,
+            seed: 0,
+        }
+    }
+}
+
+/// Measurements of a single self-play game.
+#[derive(Debug, Clone, Default)]
+pub struct SelfPlayStats {
+    /// Number of games finished in the run, including this one.
+    pub game: usize,
+    /// Number of lines drawn.
+    pub length: usize,
+    /// Boxes of player one minus boxes of player two.
+    pub margin: i32,
+    /// Error of the network on the positions of the game before training on them.
+    pub loss: f64,
+}
+
+impl fmt::Display for SelfPlayStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Game {:>5}  length {:>3}  margin {:>+4}  loss {:.4}",
+            self.game, self.length, self.margin, self.loss
+        )
+    }
+}
+
+pub struct AlphaZeroAgent {
+    pub params: AlphaZeroParameters,
+    pub nn: Network,
+    pub shape: Vec<u32>,
+    pub trained_games: usize,
+}
+
+impl AlphaZeroAgent {
+    pub fn new_with(
+        shape: &[u32],
+        params: AlphaZeroParameters,
+    ) -> Result<AlphaZeroAgent, ModelError> {
+        Self::new_with_activations(shape, Activation::Sigmoid, params)
+    }
+
+    /// The output layer always uses the sigmoid, the value is read from it as a probability.
+    pub fn new_with_activations(
+        shape: &[u32],
+        hidden: Activation,
+        params: AlphaZeroParameters,
+    ) -> Result<AlphaZeroAgent, ModelError> {
+        let size = LINES as u32 + 1;
+        if shape.len() < 2 || shape.first() != Some(&size) || shape.last() != Some(&size) {
+            return Err(ModelError::Format(format!(
+                "the network needs {size} inputs and outputs"
+            )));
+        }
+        let mut rng = rng::seeded(params.seed, rng::NETWORK_INIT);
+        Ok(AlphaZeroAgent {
+            nn: Network::random(shape, hidden, Activation::Sigmoid, &mut rng),
+            params,
+            shape: shape.to_vec(),
+            trained_games: 0,
+        })
+    }
+
+    pub fn from_file(file_path: &str) -> Result<AlphaZeroAgent, ModelError> {
+        let (metadata, network) = model::read(file_path)?;
+        let agent = Self::from_model(metadata, network)?;
+        println!("Read from {file_path}.");
+        Ok(agent)
+    }
+
+    pub fn from_model(
+        metadata: ModelMetadata,
+        network: Network,
+    ) -> Result<AlphaZeroAgent, ModelError> {
+        if metadata.agent != model::AGENT_ALPHAZERO {
+            return Err(ModelError::WrongAgent {
+                expected: model::AGENT_ALPHAZERO.to_owned(),
+                found: metadata.agent,
+            });
+        }
+        if metadata.board_size != BOARD_SIZE {
+            return Err(ModelError::BoardSizeMismatch {
+                expected: BOARD_SIZE,
+                found: metadata.board_size,
+            });
+        }
+        if metadata.encoder != ENCODER {
+            return Err(ModelError::UnknownEncoder(metadata.encoder));
+        }
+        model::check_shape(&network, LINES + 1, LINES + 1)?;
+
+        let mut params = AlphaZeroParameters {
+            games: metadata.hyper_parms.episodes,
+            seed: metadata.hyper_parms.seed,
+            ..Default::default()
+        };
+        for (key, value) in &metadata.extra {
+            match key.as_str() {
+                "simulations" => params.simulations = parse_value(key, value)?,
+                "c_puct" => params.c_puct = parse_value(key, value)?,
+                "dirichlet_alpha" => params.dirichlet_alpha = parse_value(key, value)?,
+                "noise_fraction" => params.noise_fraction = parse_value(key, value)?,
+                "temperature_moves" => params.temperature_moves = parse_value(key, value)?,
+                "epochs" => params.epochs = parse_value(key, value)?,
+                _ => {}
+            }
+        }
+
+        Ok(AlphaZeroAgent {
+            params,
+            nn: network,
+            shape: metadata.shape,
+            trained_games: metadata.trained_episodes,
+        })
+    }
+
+    pub fn metadata(&self) -> ModelMetadata {
+        let params = &self.params;
+        ModelMetadata {
+            version: MODEL_FORMAT_VERSION,
+            agent: model::AGENT_ALPHAZERO.to_owned(),
+            board_size: BOARD_SIZE,
+            encoder: ENCODER.to_owned(),
+            shape: self.shape.clone(),
+            hyper_parms: HyperParameters {
+                episodes: params.games,
+                seed: params.seed,
+                ..Default::default()
+            },
+            trained_episodes: self.trained_games,
+            created: model::now(),
+            extra: vec![
+                ("simulations".to_owned(), params.simulations.to_string()),
+                ("c_puct".to_owned(), params.c_puct.to_string()),
+                (
+                    "dirichlet_alpha".to_owned(),
+                    params.dirichlet_alpha.to_string(),
+                ),
+                (
+                    "noise_fraction".to_owned(),
+                    params.noise_fraction.to_string(),
+                ),
+                (
+                    "temperature_moves".to_owned(),
+                    params.temperature_moves.to_string(),
+                ),
+                ("epochs".to_owned(), params.epochs.to_string()),
+            ],
+        }
+    }
+
+    pub fn save(&self, file_path: &str) -> Result<(), ModelError> {
+        model::write(file_path, &self.metadata(), &self.nn)?;
+        println!("Wrote to {file_path}.");
+        Ok(())
+    }
+
+    /// Plays self-play games, training after each one, until `training.game` reaches the
+    /// configured number of games. Passing the state restored from a checkpoint continues
+    /// the run exactly where it stopped.
+    pub fn train_from(
+        &mut self,
+        training: &mut SelfPlayState,
+        observers: &mut [&mut dyn SelfPlayObserver],
+    ) -> Result<(), ModelError> {
+        while training.game < self.params.games {
+            let mut stats = self.self_play(&mut training.rng);
+            training.game += 1;
+            stats.game = training.game;
+            for observer in observers.iter_mut() {
+                observer.after_game(self, training, &stats)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Plays one game against itself and trains the network on its positions.
+    pub fn self_play(&mut self, rng: &mut SeededRng) -> SelfPlayStats {
+        let mut env = BorderPatrol::new();
+        let mut history: Vec<(Vec<f64>, Vec<f64>, usize)> = Vec::new();
+
+        while !env.game_info.is_decided() {
+            let visits = self.search(&env, Some(rng));
+            let total: f64 = visits.iter().sum();
+            let policy: Vec<f64> = visits.iter().map(|v| v / total).collect();
+
+            let line = if history.len() < self.params.temperature_moves {
+                WeightedIndex::new(&visits).unwrap().sample(rng)
+            } else {
+                most_visited(&visits)
+            };
+            history.push((encode(&env), policy, env.get_turn()));
+            env.step(line);
+        }
+
+        let examples: Vec<(Vec<f64>, Vec<f64>)> = history
+            .into_iter()
+            .map(|(input, mut target, player)| {
+                target.push((margin(&env, player) + 1.0) / 2.0);
+                (input, target)
+            })
+            .collect();
+        let loss = examples
+            .iter()
+            .map(|(input, target)| {
+                let output = self.nn.run(input);
+                output
+                    .iter()
+                    .zip(target)
+                    .map(|(o, t)| (o - t).powi(2))
+                    .sum::<f64>()
+            })
+            .sum::<f64>()
+            / examples.len() as f64;
+
+        self.nn.train(&examples, self.params.epochs);
+        self.trained_games += 1;
+
+        let score = env.game_info.score;
+        SelfPlayStats {
+            game: 0,
+            length: examples.len(),
+            margin: score[0] as i32 - score[1] as i32,
+            loss,
+        }
+    }
+
+    /// Returns the prior of every legal line and the predicted final margin for the player
+    /// to move.
+    fn evaluate(&self, env: &BorderPatrol) -> (Vec<(usize, f64)>, f64) {
+        let output = self.nn.run(&encode(env));
+        let legal: Vec<usize> = (0..LINES).filter(|&line| env.is_possible(line)).collect();
+        let total: f64 = legal.iter().map(|&line| output[line]).sum();
+
+        let priors = legal
+            .iter()
+            .map(|&line| {
+                let prior = if total > 0.0 {
+                    output[line] / total
+                } else {
+                    1.0 / legal.len() as f64
+                };
+                (line, prior)
+            })
+            .collect();
+        (priors, 2.0 * output[LINES] - 1.0)
+    }
+
+    /// Runs the configured number of simulations from `env` and returns the visit count of
+    /// every line. Noise is only added to the root when an rng is passed.
+    pub fn search(&self, env: &BorderPatrol, rng: Option<&mut SeededRng>) -> Vec<f64> {
+        self.search_until(env, rng, None)
+    }
+
+    /// Like `search`, but stops simulating once `deadline` has passed.
+    pub fn search_until(
+        &self,
+        env: &BorderPatrol,
+        rng: Option<&mut SeededRng>,
+        deadline: Option<Instant>,
+    ) -> Vec<f64> {
+        let mut tree = vec![Node::new(env.clone(), 0, 1.0)];
+        self.expand(&mut tree, 0);
+        if let Some(rng) = rng {
+            self.add_noise(&mut tree, rng);
+        }
+
+        for simulation in 0..self.params.simulations.max(1) {
+            if simulation > 0 && deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                break;
+            }
+            let mut path = vec![0];
+            let mut current = 0;
+            while !tree[current].children.is_empty() {
+                current = self.select(&tree, current);
+                path.push(current);
+            }
+
+            let leaf = &tree[current].state;
+            let value = if leaf.game_info.is_decided() {
+                margin(leaf, leaf.get_turn())
+            } else {
+                self.expand(&mut tree, current)
+            };
+            backup(&mut tree, &path, value);
+        }
+
+        let mut visits = vec![0.0; LINES];
+        for &child in &tree[0].children {
+            visits[tree[child].line] = tree[child].visits as f64;
+        }
+        visits
+    }
+
+    /// Adds a child for every legal line and returns the value of the node.
+    fn expand(&self, tree: &mut Vec<Node>, index: usize) -> f64 {
+        let (priors, value) = self.evaluate(&tree[index].state);
+        for (line, prior) in priors {
+            let mut state = tree[index].state.clone();
+            state.step(line);
+            tree.push(Node::new(state, line, prior));
+            let child = tree.len() - 1;
+            tree[index].children.push(child);
+        }
+        value
+    }
+
+    fn select(&self, tree: &[Node], index: usize) -> usize {
+        let parent_visits = (tree[index].visits.max(1) as f64).sqrt();
+        let score = |child: &Node| {
+            let q = if child.visits > 0 {
+                child.value_sum / child.visits as f64
+            } else {
+                0.0
+            };
+            q + self.params.c_puct * child.prior * parent_visits / (1 + child.visits) as f64
+        };
+
+        tree[index]
+            .children
+            .iter()
+            .copied()
+            .reduce(|best, child| {
+                if score(&tree[child]) > score(&tree[best]) {
+                    child
+                } else {
+                    best
+                }
+            })
+            .unwrap()
+    }
+
+    fn add_noise(&self, tree: &mut [Node], rng: &mut SeededRng) {
+        let children = tree[0].children.clone();
+        let Some(noise) = dirichlet(self.params.dirichlet_alpha, children.len(), rng) else {
+            return;
+        };
+
+        let fraction = self.params.noise_fraction;
+        for (child, noise) in children.into_iter().zip(noise) {
+            tree[child].prior = (1.0 - fraction) * tree[child].prior + fraction * noise;
+        }
+    }
+}
+
+/// Adds a simulation's `value`, seen by the player to move at its leaf, to every node on
+/// `path` from the side of the player who drew the node's line.
+fn backup(tree: &mut [Node], path: &[usize], value: f64) {
+    let leaf_turn = tree[*path.last().unwrap()].state.get_turn();
+    tree[path[0]].visits += 1;
+    for pair in path.windows(2) {
+        let (parent, child) = (pair[0], pair[1]);
+        let sign = if tree[parent].state.get_turn() == leaf_turn {
+            1.0
+        } else {
+            -1.0
+        };
+        tree[child].visits += 1;
+        tree[child].value_sum += sign * value;
+    }
+}
+
+struct Node {
+    state: BorderPatrol,
+    /// Line drawn to get here from the parent.
+    line: usize,
+    prior: f64,
+    visits: u32,
+    /// Sum of the values backed up through this node, seen by the player who drew `line`.
+    value_sum: f64,
+    children: Vec<usize>,
+}
+
+impl Node {
+    fn new(state: BorderPatrol, line: usize, prior: f64) -> Node {
+        Node {
+            state,
+            line,
+            prior,
+            visits: 0,
+            value_sum: 0.0,
+            children: Vec::new(),
+        }
+    }
+}
+
+/// Drawn lines followed by the current margin of the player to move.
+pub fn encode(env: &BorderPatrol) -> Vec<f64> {
+    let mut input: Vec<f64> = (0..LINES)
+        .map(|line| if env.is_possible(line) { 0.0 } else { 1.0 })
+        .collect();
+    input.push(margin(env, env.get_turn()));
+    input
+}
+
+/// Boxes of `player` minus boxes of the opponent, scaled to [-1, 1].
+fn margin(env: &BorderPatrol, player: usize) -> f64 {
+    let score = env.game_info.score;
+    (score[player] as f64 - score[1 - player] as f64) / BOXES
+}
+
+fn most_visited(visits: &[f64]) -> usize {
+    visits
+        .iter()
+        .enumerate()
+        .reduce(|best, x| if x.1 > best.1 { x } else { best })
+        .unwrap()
+        .0
+}
+
+/// Samples `n` weights that sum to 1 from a symmetric dirichlet distribution, `None` if all
+/// of the gamma samples underflowed.
+fn dirichlet(alpha: f64, n: usize, rng: &mut impl Rng) -> Option<Vec<f64>> {
+    let samples: Vec<f64> = (0..n).map(|_| gamma(alpha, rng)).collect();
+    let total: f64 = samples.iter().sum();
+    (total > 0.0).then(|| samples.into_iter().map(|x| x / total).collect())
+}
+
+/// Samples a gamma distribution with scale 1 using the method of Marsaglia and Tsang.
+fn gamma(alpha: f64, rng: &mut impl Rng) -> f64 {
+    if alpha < 1.0 {
+        let u: f64 = rng.gen();
+        return gamma(alpha + 1.0, rng) * u.powf(1.0 / alpha);
+    }
+
+    let d = alpha - 1.0 / 3.0;
+    let c = 1.0 / (9.0 * d).sqrt();
+    loop {
+        let x = normal(rng);
+        let v = (1.0 + c * x).powi(3);
+        if v <= 0.0 {
+            continue;
+        }
+        let u: f64 = rng.gen();
+        if u.ln() < 0.5 * x * x + d - d * v + d * v.ln() {
+            return d * v;
+        }
+    }
+}
+
+fn normal(rng: &mut impl Rng) -> f64 {
+    let u1: f64 = rng.gen_range(f64::MIN_POSITIVE..1.0);
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+/// Everything besides the agent itself that is needed to continue a self-play run.
+pub struct SelfPlayState {
+    /// Games finished in the current run.
+    pub game: usize,
+    pub rng: SeededRng,
+}
+
+impl SelfPlayState {
+    pub fn new(rng: SeededRng) -> SelfPlayState {
+        SelfPlayState { game: 0, rng }
+    }
+}
+
+/// Gets called after every self-play game, e.g. to write checkpoints or log metrics.
+pub trait SelfPlayObserver {
+    fn after_game(
+        &mut self,
+        agent: &mut AlphaZeroAgent,
+        training: &mut SelfPlayState,
+        stats: &SelfPlayStats,
+    ) -> Result<(), ModelError>;
+}
+
+impl Player for AlphaZeroAgent {
+    fn init(&mut self) {}
+
+    fn choose_line(&self, border_patrol: &BorderPatrol) -> usize {
+        most_visited(&self.search(border_patrol, None))
+    }
+
+    fn choose_line_by(&self, border_patrol: &BorderPatrol, deadline: Instant) -> usize {
+        most_visited(&self.search_until(border_patrol, None, Some(deadline)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agent(simulations: usize) -> AlphaZeroAgent {
+        let params = AlphaZeroParameters {
+            simulations,
+            ..Default::default()
+        };
+        AlphaZeroAgent::new_with(&[201, 8, 201], params).unwrap()
+    }
+
+    /// A position with a line that completes a box, so the mover keeps the turn, and a line
+    /// that passes the turn on.
+    fn position_with_box_line() -> (BorderPatrol, usize, usize) {
+        let mut rng = rng::seeded(0, rng::EXPLORATION);
+        let mut env = BorderPatrol::new();
+        loop {
+            let keeps_turn = |line: usize| {
+                let mut next = env.clone();
+                next.step(line);
+                next.get_turn() == env.get_turn()
+            };
+            let legal: Vec<usize> = (0..LINES).filter(|&line| env.is_possible(line)).collect();
+            let boxing = legal.iter().copied().find(|&line| keeps_turn(line));
+            let passing = legal.iter().copied().find(|&line| !keeps_turn(line));
+            if let (Some(boxing), Some(passing)) = (boxing, passing) {
+                return (env, boxing, passing);
+            }
+            env.step(env.random_action(&mut rng));
+        }
+    }
+
+    #[test]
+    fn new_rejects_networks_that_do_not_fit() {
+        let params = AlphaZeroParameters::default;
+        assert!(AlphaZeroAgent::new_with(&[201, 8, 201], params()).is_ok());
+        for shape in [&[200, 8, 201][..], &[201, 8, 200], &[201], &[]] {
+            assert!(matches!(
+                AlphaZeroAgent::new_with(shape, params()),
+                Err(ModelError::Format(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn backup_flips_the_value_only_when_the_turn_changes() {
+        let (env, boxing, passing) = position_with_box_line();
+        let child = |line| {
+            let mut state = env.clone();
+            state.step(line);
+            Node::new(state, line, 0.5)
+        };
+        let mut tree = vec![
+            Node::new(env.clone(), 0, 1.0),
+            child(boxing),
+            child(passing),
+        ];
+        let mut grandchild = tree[2].state.clone();
+        let reply = (0..LINES)
+            .find(|&line| {
+                let mut next = grandchild.clone();
+                next.step(line);
+                grandchild.is_possible(line) && next.get_turn() != grandchild.get_turn()
+            })
+            .unwrap();
+        grandchild.step(reply);
+        tree.push(Node::new(grandchild, reply, 0.5));
+
+        // The mover completed a box and moves again, so the leaf value is its own
+        backup(&mut tree, &[0, 1], 0.25);
+        assert_eq!(tree[1].value_sum, 0.25);
+
+        // Passing the turn makes the leaf value the opponent's
+        backup(&mut tree, &[0, 2], 0.25);
+        assert_eq!(tree[2].value_sum, -0.25);
+
+        // Two turn changes give the leaf back to the root player
+        backup(&mut tree, &[0, 2, 3], 0.5);
+        assert_eq!(tree[2].value_sum, 0.25);
+        assert_eq!(tree[3].value_sum, -0.5);
+        assert_eq!(
+            [
+                tree[0].visits,
+                tree[1].visits,
+                tree[2].visits,
+                tree[3].visits
+            ],
+            [3, 1, 2, 1]
+        );
+    }
+
+    #[test]
+    fn visit_counts_sum_to_the_simulations() {
+        let agent = agent(25);
+        let (env, _, _) = position_with_box_line();
+        let mut rng = rng::seeded(1, rng::EXPLORATION);
+        for visits in [
+            agent.search(&BorderPatrol::new(), None),
+            agent.search(&env, Some(&mut rng)),
+        ] {
+            assert_eq!(visits.iter().sum::<f64>(), 25.0);
+        }
+    }
+
+    #[test]
+    fn dirichlet_noise_sums_to_one() {
+        let mut rng = rng::seeded(2, rng::EXPLORATION);
+        for alpha in [0.03, 0.3, 1.0, 4.0] {
+            let weights = dirichlet(alpha, 200, &mut rng).unwrap();
+            assert!(weights.iter().all(|&w| w >= 0.0));
+            assert!((weights.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+
+            let mean = (0..20000).map(|_| gamma(alpha, &mut rng)).sum::<f64>() / 20000.0;
+            assert!(
+                (mean - alpha).abs() < 0.05 * alpha.max(1.0),
+                "mean {mean} for {alpha}"
+            );
+        }
+
+        let agent = agent(1);
+        let mut tree = vec![Node::new(BorderPatrol::new(), 0, 1.0)];
+        agent.expand(&mut tree, 0);
+        agent.add_noise(&mut tree, &mut rng);
+        let priors: f64 = tree[0].children.iter().map(|&c| tree[c].prior).sum();
+        assert!((priors - 1.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,132 @@
+#![allow(dead_code)]
+use std::{cmp::Ordering, fmt};
+
+use crate::{
+    ai::{AdvancedPlayer, GreedyPlayer, RandomPlayer},
+    alphazero::AlphaZeroAgent,
+    borderpatrol::BorderPatrol,
+    clock::{Clock, TimeControl},
+    ml::BorderPatrolAgent,
+    model::{self, ModelError},
+    records::GameRecord,
+    solver::SolverPlayer,
+    terminal_borderpatrol::display::Player,
+};
+
+/// Creates a player from `random`, `greedy`, `advanced`, `solver` or the path of a model file.
+pub fn load_player(spec: &str, seed: u64) -> Result<Box<dyn Player>, ModelError> {
+    Ok(match spec {
+        "random" => Box::new(RandomPlayer::new(seed)),
+        "greedy" => Box::new(GreedyPlayer::new(seed)),
+        "advanced" => Box::new(AdvancedPlayer::new()),
+        "solver" => Box::new(SolverPlayer::new()),
+        file_path => {
+            let (metadata, network) = model::read(file_path)?;
+            if metadata.agent == model::AGENT_ALPHAZERO {
+                Box::new(AlphaZeroAgent::from_model(metadata, network)?)
+            } else {
+                Box::new(BorderPatrolAgent::from_model(metadata, network)?)
+            }
+        }
+    })
+}
+
+/// Outcome of a match, counted from the side of the first player.
+#[derive(Debug, Clone, Default)]
+pub struct MatchResult {
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+    /// Sum of the box margins over all games.
+    pub margin: i64,
+}
+
+impl MatchResult {
+    pub fn games(&self) -> usize {
+        self.wins + self.draws + self.losses
+    }
+
+    /// Share of points won, counting a draw as half a win.
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + 0.5 * self.draws as f64) / self.games().max(1) as f64
+    }
+}
+
+impl fmt::Display for MatchResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} wins, {} draws, {} losses  score {:.3}  average margin {:+.1}",
+            self.wins,
+            self.draws,
+            self.losses,
+            self.score(),
+            self.margin as f64 / self.games().max(1) as f64
+        )
+    }
+}
+
+/// Plays one game until it is decided and returns its moves and the boxes of player one and
+/// player two, so a player at exactly half of the boxes does not end it early. On a clock the
+/// players get a deadline for every line and a player who draws a line too late loses on time.
+/// The player names of the record are left empty.
+pub fn play_game(
+    player_one: &dyn Player,
+    player_two: &dyn Player,
+    time: Option<TimeControl>,
+) -> GameRecord {
+    let mut env = BorderPatrol::new();
+    let mut clock = time.map(Clock::new);
+    let mut record = GameRecord::default();
+    while !env.game_info.is_decided() {
+        let turn = env.get_turn();
+        let player = if turn == 0 { player_one } else { player_two };
+        let line = match &mut clock {
+            Some(clock) => {
+                clock.start(turn);
+                let line = player.choose_line_by(&env, clock.deadline(turn));
+                if !clock.stop() {
+                    record.lost_on_time = Some(turn);
+                    break;
+                }
+                line
+            }
+            None => player.choose_line(&env),
+        };
+        env.step(line);
+        record.moves.push(line);
+    }
+    record.score = env.game_info.score;
+    record
+}
+
+/// Plays `games` games, the players take turns starting. Every finished game is passed to
+/// `on_game` together with its number.
+pub fn play_match(
+    first: &dyn Player,
+    second: &dyn Player,
+    games: usize,
+    time: Option<TimeControl>,
+    mut on_game: impl FnMut(usize, GameRecord),
+) -> MatchResult {
+    let mut result = MatchResult::default();
+    for game in 0..games {
+        let record = if game % 2 == 0 {
+            play_game(first, second, time)
+        } else {
+            play_game(second, first, time)
+        };
+        let side = game % 2;
+        let (mine, theirs) = (record.score[side], record.score[1 - side]);
+        let outcome = record.outcome(side);
+        on_game(game, record);
+
+        result.margin += mine as i64 - theirs as i64;
+        match outcome {
+            Ordering::Greater => result.wins += 1,
+            Ordering::Equal => result.draws += 1,
+            Ordering::Less => result.losses += 1,
+        }
+    }
+    result
+}
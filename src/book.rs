@@ -0,0 +1,525 @@
+#![allow(dead_code)]
+/*
+    Opening book. Positions are stored by a canonical hash of the drawn lines, the smallest
+    hash over the eight symmetries of the board, so mirrored and rotated openings share an
+    entry. Who drew a line does not matter for the rest of the game and is left out. The
+    moves of an entry are kept in the orientation that gave the canonical hash and are
+    turned back into the orientation of the position they are looked up for.
+
+    Move statistics count from the side of the player who drew the line. The file lists one
+    position per line, its hash followed by line=games/wins/draws/margin for every move:
+
+        borderpatrol-book 1
+        depth = 8
+        positions
+        00a3f0c2d9e1b774 14=120/64/3/+212 57=8/2/0/-31 ...
+*/
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+    time::Instant,
+};
+
+use rand::{seq::SliceRandom, Rng};
+
+use crate::{
+    ai,
+    borderpatrol::{BorderPatrol, BOARD_SIZE, BOTTOM, RIGHT},
+    model::{parse_value, ModelError},
+    records::{self, GameRecord},
+    terminal_borderpatrol::display::Player,
+};
+
+const BOOK_MAGIC: &str = "borderpatrol-book";
+const FORMAT_VERSION: u32 = 1;
+const POSITIONS_MARKER: &str = "positions";
+pub const DEFAULT_DEPTH: usize = 8;
+
+/// Results of the games in which a move was played in a position.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MoveStats {
+    pub games: u32,
+    pub wins: u32,
+    pub draws: u32,
+    /// Sum of the box margins of the games.
+    pub margin: i64,
+}
+
+impl MoveStats {
+    /// Share of points won, counting a draw as half a win.
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + 0.5 * self.draws as f64) / self.games.max(1) as f64
+    }
+
+    /// The score pulled towards one half by one imaginary win and loss, so a move seen
+    /// once does not outrank a move that held up over many games.
+    fn smoothed_score(&self) -> f64 {
+        (self.wins as f64 + 0.5 * self.draws as f64 + 1.0) / (self.games as f64 + 2.0)
+    }
+
+    fn add(&mut self, other: &MoveStats) {
+        self.games += other.games;
+        self.wins += other.wins;
+        self.draws += other.draws;
+        self.margin += other.margin;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OpeningBook {
+    /// Moves from the start that are recorded of every game.
+    pub depth: usize,
+    positions: HashMap<u64, BTreeMap<usize, MoveStats>>,
+}
+
+impl OpeningBook {
+    pub fn new(depth: usize) -> OpeningBook {
+        OpeningBook {
+            depth,
+            positions: HashMap::new(),
+        }
+    }
+
+    pub fn position_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Number of games the book was built from, as seen at the start position.
+    pub fn game_count(&self) -> u32 {
+        self.moves(&BorderPatrol::new())
+            .iter()
+            .map(|(_, stats)| stats.games)
+            .sum()
+    }
+
+    /// Adds the first `depth` moves of a finished game.
+    pub fn add_game(&mut self, record: &GameRecord) {
+        let mut env = BorderPatrol::new();
+        for &line in record.moves.iter().take(self.depth) {
+            let mover = env.get_turn();
+            let (mine, theirs) = (record.score[mover], record.score[1 - mover]);
+            let outcome = MoveStats {
+                games: 1,
+                wins: record.outcome(mover).is_gt() as u32,
+                draws: record.outcome(mover).is_eq() as u32,
+                margin: mine as i64 - theirs as i64,
+            };
+            let (hash, canonical) = canonical_move(&env, line);
+            self.positions
+                .entry(hash)
+                .or_default()
+                .entry(canonical)
+                .or_default()
+                .add(&outcome);
+            env.set_line(line, env.game_info.turn);
+        }
+    }
+
+    pub fn merge(&mut self, other: &OpeningBook) {
+        self.depth = self.depth.max(other.depth);
+        for (&hash, moves) in &other.positions {
+            let entry = self.positions.entry(hash).or_default();
+            for (&line, stats) in moves {
+                entry.entry(line).or_default().add(stats);
+            }
+        }
+    }
+
+    /// The moves the book knows in `env`, in the orientation of `env`, best first.
+    pub fn moves(&self, env: &BorderPatrol) -> Vec<(usize, MoveStats)> {
+        let (hash, symmetry) = canonical_hash(env);
+        let inverse = inverse_symmetry(symmetry);
+        let mut moves: Vec<(usize, MoveStats)> = match self.positions.get(&hash) {
+            Some(moves) => moves
+                .iter()
+                .map(|(&line, &stats)| (transform_line(inverse, line), stats))
+                .filter(|&(line, _)| env.is_possible(line))
+                .collect(),
+            None => vec![],
+        };
+        moves.sort_by(|a, b| {
+            b.1.smoothed_score()
+                .partial_cmp(&a.1.smoothed_score())
+                .unwrap_or(Ordering::Equal)
+                .then(b.1.games.cmp(&a.1.games))
+                .then(a.0.cmp(&b.0))
+        });
+        moves
+    }
+
+    pub fn best_line(&self, env: &BorderPatrol) -> Option<usize> {
+        self.moves(env).first().map(|&(line, _)| line)
+    }
+
+    pub fn write(&self, file_path: &Path) -> Result<(), ModelError> {
+        let io_err = |err| ModelError::Io(file_path.display().to_string(), err);
+        let mut file = BufWriter::new(File::create(file_path).map_err(io_err)?);
+        writeln!(file, "{BOOK_MAGIC} {FORMAT_VERSION}").map_err(io_err)?;
+        writeln!(file, "depth = {}", self.depth).map_err(io_err)?;
+        writeln!(file, "{POSITIONS_MARKER}").map_err(io_err)?;
+
+        let mut hashes: Vec<&u64> = self.positions.keys().collect();
+        hashes.sort();
+        for hash in hashes {
+            let moves: Vec<String> = self.positions[hash]
+                .iter()
+                .map(|(line, s)| {
+                    format!("{line}={}/{}/{}/{:+}", s.games, s.wins, s.draws, s.margin)
+                })
+                .collect();
+            writeln!(file, "{hash:016x} {}", moves.join(" ")).map_err(io_err)?;
+        }
+        file.flush().map_err(io_err)
+    }
+
+    pub fn read(file_path: &Path) -> Result<OpeningBook, ModelError> {
+        let name = file_path.display().to_string();
+        let content =
+            fs::read_to_string(file_path).map_err(|err| ModelError::Io(name.clone(), err))?;
+        let format_err = |msg: String| ModelError::Format(format!("{msg} in {name}"));
+        let mut lines = content.lines();
+        let version: u32 = match lines.next().and_then(|l| l.strip_prefix(BOOK_MAGIC)) {
+            Some(version) => parse_value("version", version)?,
+            None => {
+                return Err(ModelError::Format(format!(
+                    "{name} does not start with '{BOOK_MAGIC}'"
+                )))
+            }
+        };
+        if version > FORMAT_VERSION {
+            return Err(ModelError::UnsupportedVersion(version));
+        }
+
+        let mut book = OpeningBook::new(DEFAULT_DEPTH);
+        for line in lines.by_ref() {
+            if line.trim() == POSITIONS_MARKER {
+                break;
+            }
+            match line.split_once('=') {
+                Some((key, value)) if key.trim() == "depth" => {
+                    book.depth = parse_value("depth", value)?
+                }
+                Some(_) => {}
+                None => return Err(format_err(format!("unexpected line '{line}'"))),
+            }
+        }
+
+        for line in lines.filter(|line| !line.trim().is_empty()) {
+            let mut fields = line.split_whitespace();
+            let hash = fields.next().unwrap();
+            let hash = u64::from_str_radix(hash, 16)
+                .map_err(|_| format_err(format!("invalid hash '{hash}'")))?;
+            let entry = book.positions.entry(hash).or_default();
+            for field in fields {
+                let invalid = || format_err(format!("invalid move '{field}'"));
+                let (line, stats) = field.split_once('=').ok_or_else(invalid)?;
+                let stats: Vec<&str> = stats.split('/').collect();
+                let [games, wins, draws, margin] = stats[..] else {
+                    return Err(invalid());
+                };
+                let line: usize = parse_value("line", line)?;
+                if line >= 2 * BOARD_SIZE * BOARD_SIZE {
+                    return Err(invalid());
+                }
+                entry.entry(line).or_default().add(&MoveStats {
+                    games: parse_value("games", games)?,
+                    wins: parse_value("wins", wins)?,
+                    draws: parse_value("draws", draws)?,
+                    margin: parse_value("margin", margin)?,
+                });
+            }
+        }
+        Ok(book)
+    }
+}
+
+/// Plays `games` games of `player` against itself and adds them to the book. Within the
+/// depth of the book every move is replaced by a random line that does not hand out a box
+/// with probability `explore`, so the book covers more than one line of play.
+pub fn self_play(
+    book: &mut OpeningBook,
+    player: &dyn Player,
+    games: usize,
+    explore: f64,
+    rng: &mut impl Rng,
+) {
+    for _ in 0..games {
+        let mut env = BorderPatrol::new();
+        let mut moves = Vec::new();
+        while !env.game_info.is_decided() {
+            let line = if moves.len() < book.depth && rng.gen_bool(explore) {
+                let safe: Vec<usize> = ai::safe_lines(&env).collect();
+                match safe.choose(rng) {
+                    Some(&line) => line,
+                    None => env.random_action(rng),
+                }
+            } else {
+                player.choose_line(&env)
+            };
+            env.step(line);
+            moves.push(line);
+        }
+        book.add_game(&GameRecord {
+            score: env.game_info.score,
+            moves,
+            ..Default::default()
+        });
+    }
+}
+
+/// Plays the best book move for the first `max_moves` moves of a game and leaves the rest,
+/// and positions the book does not know, to another player.
+pub struct BookPlayer {
+    book: OpeningBook,
+    player: Box<dyn Player>,
+    pub max_moves: usize,
+}
+
+impl BookPlayer {
+    pub fn new(book: OpeningBook, player: Box<dyn Player>) -> BookPlayer {
+        BookPlayer {
+            max_moves: book.depth,
+            book,
+            player,
+        }
+    }
+}
+
+impl Player for BookPlayer {
+    fn init(&mut self) {
+        self.player.init();
+    }
+
+    fn choose_line(&self, border_patrol: &BorderPatrol) -> usize {
+        let played = interior_lines()
+            .filter(|&line| !border_patrol.is_possible(line))
+            .count();
+        let book_line = if played < self.max_moves {
+            self.book.best_line(border_patrol)
+        } else {
+            None
+        };
+        book_line.unwrap_or_else(|| self.player.choose_line(border_patrol))
+    }
+
+    fn choose_line_by(&self, border_patrol: &BorderPatrol, deadline: Instant) -> usize {
+        let played = interior_lines()
+            .filter(|&line| !border_patrol.is_possible(line))
+            .count();
+        let book_line = if played < self.max_moves {
+            self.book.best_line(border_patrol)
+        } else {
+            None
+        };
+        book_line.unwrap_or_else(|| self.player.choose_line_by(border_patrol, deadline))
+    }
+
+    fn explain(&self, border_patrol: &BorderPatrol) -> Option<Vec<f64>> {
+        self.player.explain(border_patrol)
+    }
+}
+
+/// Lines that are not part of the border.
+fn interior_lines() -> impl Iterator<Item = usize> {
+    (0..2 * BOARD_SIZE * BOARD_SIZE).filter(|&line| {
+        let (row, col) = (line / 2 / BOARD_SIZE, line / 2 % BOARD_SIZE);
+        match (line % 2) as u8 {
+            BOTTOM => row + 1 < BOARD_SIZE,
+            _ => col + 1 < BOARD_SIZE,
+        }
+    })
+}
+
+/// The smallest hash of the drawn lines over the eight symmetries and the symmetry that
+/// gave it.
+pub fn canonical_hash(env: &BorderPatrol) -> (u64, usize) {
+    (0..8)
+        .map(|symmetry| (symmetry_hash(env, symmetry), symmetry))
+        .min()
+        .unwrap()
+}
+
+/// The canonical hash of `env` and `line` in the orientation of the hash. Positions that
+/// are symmetric themselves have several such orientations, the one that gives the smallest
+/// line is taken so moves that are the same by symmetry share their statistics.
+fn canonical_move(env: &BorderPatrol, line: usize) -> (u64, usize) {
+    let (hash, _) = canonical_hash(env);
+    let line = (0..8)
+        .filter(|&symmetry| symmetry_hash(env, symmetry) == hash)
+        .map(|symmetry| transform_line(symmetry, line))
+        .min()
+        .unwrap();
+    (hash, line)
+}
+
+fn symmetry_hash(env: &BorderPatrol, symmetry: usize) -> u64 {
+    interior_lines()
+        .filter(|&line| !env.is_possible(line))
+        .fold(0, |hash, line| {
+            hash ^ line_key(transform_line(symmetry, line))
+        })
+}
+
+/// A fixed pseudo random key per line, the hash of a position is the xor of the keys of
+/// its lines. Keys must not change between versions since books store the hashes.
+fn line_key(line: usize) -> u64 {
+    // splitmix64
+    let mut x = (line as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+/// Maps an interior line to its image under one of the eight symmetries of the board. Bit 2
+/// of `symmetry` transposes the dots, bits 0 and 1 then mirror the rows and the columns.
+pub fn transform_line(symmetry: usize, line: usize) -> usize {
+    let transform = |(row, col): (usize, usize)| {
+        let (row, col) = if symmetry & 4 != 0 {
+            (col, row)
+        } else {
+            (row, col)
+        };
+        let row = if symmetry & 1 != 0 {
+            BOARD_SIZE - row
+        } else {
+            row
+        };
+        let col = if symmetry & 2 != 0 {
+            BOARD_SIZE - col
+        } else {
+            col
+        };
+        (row, col)
+    };
+    let [a, b] = line_dots(line).map(transform);
+    dots_line(a.min(b), a.max(b))
+}
+
+fn inverse_symmetry(symmetry: usize) -> usize {
+    match symmetry & 4 {
+        0 => symmetry,
+        _ => 4 | (symmetry & 1) << 1 | (symmetry & 2) >> 1,
+    }
+}
+
+/// The dots at the ends of a line, as row and column of the (BOARD_SIZE + 1)^2 dots.
+fn line_dots(line: usize) -> [(usize, usize); 2] {
+    let (row, col) = (line / 2 / BOARD_SIZE, line / 2 % BOARD_SIZE);
+    match (line % 2) as u8 {
+        BOTTOM => [(row + 1, col), (row + 1, col + 1)],
+        _ => [(row, col + 1), (row + 1, col + 1)],
+    }
+}
+
+fn dots_line(from: (usize, usize), to: (usize, usize)) -> usize {
+    if from.0 == to.0 {
+        ((from.0 - 1) * BOARD_SIZE + from.1) * 2 + BOTTOM as usize
+    } else {
+        (from.0 * BOARD_SIZE + from.1 - 1) * 2 + RIGHT as usize
+    }
+}
+
+/// Loads every `.game` record in `dir` into the book.
+pub fn add_records(book: &mut OpeningBook, dir: &str) -> Result<usize, ModelError> {
+    let (games, _) = records::load_dir(dir)?;
+    for game in &games {
+        book.add_game(game);
+    }
+    Ok(games.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAME: [usize; 6] = [1, 0, 57, 33, 14, 120];
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("borderpatrol-{}-{name}", std::process::id()))
+    }
+
+    fn book_with_game() -> OpeningBook {
+        let mut book = OpeningBook::new(GAME.len());
+        book.add_game(&GameRecord {
+            score: [60, 40],
+            moves: GAME.to_vec(),
+            ..Default::default()
+        });
+        book
+    }
+
+    #[test]
+    fn add_game_follows_the_played_lines() {
+        let book = book_with_game();
+        let mut env = BorderPatrol::new();
+        for &line in &GAME {
+            let moves = book.moves(&env);
+            assert_eq!(moves.len(), 1, "no single book move before {line}");
+            let (book_line, stats) = moves[0];
+            assert_eq!(stats.games, 1);
+            assert_eq!(stats.wins, (env.get_turn() == 0) as u32);
+
+            let (mut played, mut suggested) = (env.clone(), env.clone());
+            played.set_line(line, played.game_info.turn);
+            suggested.set_line(book_line, suggested.game_info.turn);
+            assert_eq!(canonical_hash(&played).0, canonical_hash(&suggested).0);
+            env = played;
+        }
+        assert!(book.moves(&env).is_empty());
+    }
+
+    #[test]
+    fn reply_is_stored_under_the_position_it_was_played_in() {
+        let book = book_with_game();
+        let mut env = BorderPatrol::new();
+        env.set_line(1, env.game_info.turn);
+        assert_eq!(book.best_line(&env), Some(0));
+    }
+
+    #[test]
+    fn transform_line_inverts() {
+        for symmetry in 0..8 {
+            for line in interior_lines() {
+                let image = transform_line(symmetry, line);
+                assert_eq!(transform_line(inverse_symmetry(symmetry), image), line);
+            }
+        }
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let book = book_with_game();
+        let path = temp_path("round-trip.book");
+        book.write(&path).unwrap();
+        let read = OpeningBook::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.depth, book.depth);
+        assert_eq!(read.positions, book.positions);
+    }
+
+    #[test]
+    fn read_rejects_invalid_books() {
+        let cases = [
+            ("magic", "opening-book 1\npositions\n"),
+            ("version", "borderpatrol-book 2\npositions\n"),
+            ("hash", "borderpatrol-book 1\npositions\nxyz 14=1/1/0/+2\n"),
+            ("stats", "borderpatrol-book 1\npositions\n00ff 14=1/1/+2\n"),
+            (
+                "line",
+                "borderpatrol-book 1\npositions\n00ff 200=1/1/0/+2\n",
+            ),
+            ("header", "borderpatrol-book 1\ndepth\npositions\n"),
+        ];
+        for (name, content) in cases {
+            let path = temp_path(&format!("invalid-{name}.book"));
+            fs::write(&path, content).unwrap();
+            let result = OpeningBook::read(&path);
+            fs::remove_file(&path).unwrap();
+            assert!(result.is_err(), "accepted an invalid {name}");
+        }
+    }
+}
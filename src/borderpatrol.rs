@@ -1,6 +1,8 @@
 #![allow(dead_code)]
 const BOX_MASK: u16 = 0b0000_0000_1111_0000;
 
+pub const BOARD_SIZE: usize = 10;
+
 pub const PLAYER_ONE: u8 = 9;
 pub const PLAYER_TWO: u8 = 8;
 
@@ -18,11 +20,11 @@ pub struct GameInfo {
 
 impl GameInfo {
     pub fn new() -> GameInfo {
-        return GameInfo {
+        GameInfo {
             score: [0, 0],
             turn: PLAYER_ONE,
             finished: false,
-        };
+        }
     }
 
     pub fn get_points(&self) -> u8 {
@@ -36,6 +38,14 @@ impl GameInfo {
     pub fn get_player_two_points(&self) -> u8 {
         self.score[1]
     }
+
+    /// Whether the result can no longer change, because a player holds more than half of the
+    /// boxes or every box is taken.
+    pub fn is_decided(&self) -> bool {
+        let boxes = (BOARD_SIZE * BOARD_SIZE) as u8;
+        self.score.iter().any(|&points| points > boxes / 2)
+            || self.score[0] + self.score[1] == boxes
+    }
 }
 
 #[derive(Clone)]
@@ -57,11 +67,11 @@ impl Board {
     }
 
     fn set_bit(&mut self, row: usize, column: usize, shift: u8) {
-        self.layout[row * 10 + column] = self.layout[row * 10 + column] | (1 << shift);
+        self.layout[row * 10 + column] |= 1 << shift;
     }
 
     pub fn get_bit(&self, row: usize, column: usize, shift: u8) -> bool {
-        return self.layout[row * 10 + column] & (1 << shift) > 0;
+        self.layout[row * 10 + column] & (1 << shift) > 0
     }
 }
 
@@ -106,9 +116,9 @@ impl BorderPatrol {
         self.check_box(row, column, player);
 
         let horizontal = (1 - side % 2) as i8;
-        let h_amount = -1 * side as i8 + 1;
+        let h_amount = -(side as i8) + 1;
         let vertical = (side % 2) as i8;
-        let v_amount = -1 * side as i8 + 2;
+        let v_amount = -(side as i8) + 2;
 
         let extra_row = (row as i8 + (horizontal * h_amount)) as usize;
         let extra_column = (column as i8 + (vertical * v_amount)) as usize;
@@ -122,6 +132,25 @@ impl BorderPatrol {
         self.game_info.turn = 9 - (self.game_info.turn % PLAYER_TWO);
     }
 
+    /// Number of drawn sides of a box, border sides included.
+    pub fn box_sides(&self, row: usize, column: usize) -> u32 {
+        let cell = self.board.layout[10 * row + column];
+        ((cell | cell >> 4) & 0b1111).count_ones()
+    }
+
+    /// The two boxes a line separates. Only valid for lines that are not part of the border.
+    pub fn line_boxes(num: usize) -> [(usize, usize); 2] {
+        let box_num = num / 2;
+        let row = box_num / 10;
+        let col = box_num % 10;
+
+        if (num % 2) as u8 == BOTTOM {
+            [(row, col), (row + 1, col)]
+        } else {
+            [(row, col), (row, col + 1)]
+        }
+    }
+
     fn check_box(&mut self, row: usize, column: usize, player: u8) {
         if (self.board.layout[10 * row + column] & BOX_MASK)
             | (self.board.layout[10 * row + column] << 4 & BOX_MASK)
@@ -0,0 +1,450 @@
+#![allow(dead_code)]
+/*
+    A checkpoint is a regular model file with the training state stored as extra header
+    entries, so it can be inspected with `model info` and played against directly.
+    The optional replay buffer lives next to it in a `.replay` file with one example per
+    line, inputs and targets separated by a semicolon.
+
+        <dir>/checkpoint-00001000.model
+        <dir>/checkpoint-00001000.replay
+        <dir>/best.model
+
+    AlphaZero runs are checkpointed the same way, but their checkpoints are not evaluated
+    and no best model is kept.
+
+    Checkpoints store the optimiser state of the network next to its weights, so a resumed
+    run continues with the same momentum or adam moments as the original one.
+*/
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use rand::SeedableRng;
+
+use crate::{
+    alphazero::{AlphaZeroAgent, SelfPlayObserver, SelfPlayState, SelfPlayStats},
+    ml::{BorderPatrolAgent, EpisodeStats, ReplayBuffer, TrainingObserver, TrainingState},
+    model::{self, parse_value, ModelError},
+    rng::{self, SeededRng},
+};
+
+const PREFIX: &str = "checkpoint-";
+const BEST: &str = "best.model";
+
+pub struct Checkpointer {
+    pub dir: PathBuf,
+    /// Save a checkpoint every this many episodes.
+    pub every: usize,
+    /// Number of most recent checkpoints to keep, older ones are deleted.
+    pub keep_last: usize,
+    /// Games against the random player used to rank checkpoints.
+    pub eval_games: usize,
+    pub save_replay: bool,
+    /// Print a line for every saved checkpoint.
+    pub print: bool,
+    /// Path and evaluation of the most recent checkpoint.
+    pub last_saved: Option<(PathBuf, f64)>,
+}
+
+impl Checkpointer {
+    pub fn new(dir: &str) -> Checkpointer {
+        Checkpointer {
+            dir: PathBuf::from(dir),
+            every: 1000,
+            keep_last: 3,
+            eval_games: 20,
+            save_replay: false,
+            print: true,
+            last_saved: None,
+        }
+    }
+
+    pub fn save(
+        &mut self,
+        agent: &BorderPatrolAgent,
+        training: &mut TrainingState,
+    ) -> Result<(), ModelError> {
+        let io_err = |path: &Path| {
+            let path = path.display().to_string();
+            move |err| ModelError::Io(path, err)
+        };
+        fs::create_dir_all(&self.dir).map_err(io_err(&self.dir))?;
+
+        // Evaluation uses its own stream so every checkpoint faces the same games and the
+        // training run is not influenced by how often checkpoints are taken.
+        let mut eval_rng = rng::seeded(agent.hyper_parms.seed, rng::EVALUATION);
+        let evaluation = agent.evaluate(self.eval_games, &mut eval_rng);
+        let improved = training
+            .best_evaluation
+            .is_none_or(|best| evaluation > best);
+        if improved {
+            training.best_evaluation = Some(evaluation);
+        }
+
+        let path = self
+            .dir
+            .join(format!("{PREFIX}{:08}.model", training.episode));
+        write(&path, agent, training, evaluation)?;
+        if self.save_replay {
+            write_replay(&path.with_extension("replay"), &training.replay)?;
+        }
+        if self.print {
+            println!(
+                "Saved checkpoint {} (evaluation {evaluation:.3})",
+                path.display()
+            );
+        }
+
+        if improved {
+            let best = self.dir.join(BEST);
+            fs::copy(&path, &best).map_err(io_err(&best))?;
+        }
+        self.last_saved = Some((path, evaluation));
+
+        self.prune()
+    }
+
+    /// Saves a checkpoint of an AlphaZero run.
+    pub fn save_self_play(
+        &mut self,
+        agent: &AlphaZeroAgent,
+        training: &SelfPlayState,
+    ) -> Result<(), ModelError> {
+        fs::create_dir_all(&self.dir)
+            .map_err(|err| ModelError::Io(self.dir.display().to_string(), err))?;
+
+        let path = self.dir.join(format!("{PREFIX}{:08}.model", training.game));
+        let mut metadata = agent.metadata();
+        metadata
+            .extra
+            .extend(run_entries(training.game, &training.rng));
+        model::write_with_state(&path.display().to_string(), &metadata, &agent.nn)?;
+        if self.print {
+            println!("Saved checkpoint {}", path.display());
+        }
+
+        self.prune()
+    }
+
+    fn prune(&self) -> Result<(), ModelError> {
+        let mut checkpoints = list(&self.dir)?;
+        checkpoints.sort();
+        let excess = checkpoints.len().saturating_sub(self.keep_last.max(1));
+        for path in checkpoints.into_iter().take(excess) {
+            let replay = path.with_extension("replay");
+            if replay.exists() {
+                let _ = fs::remove_file(replay);
+            }
+            fs::remove_file(&path)
+                .map_err(|err| ModelError::Io(path.display().to_string(), err))?;
+        }
+        Ok(())
+    }
+}
+
+impl TrainingObserver for Checkpointer {
+    fn after_episode(
+        &mut self,
+        agent: &mut BorderPatrolAgent,
+        training: &mut TrainingState,
+        _stats: &EpisodeStats,
+    ) -> Result<(), ModelError> {
+        let last = training.episode == agent.hyper_parms.episodes;
+        if !training.episode.is_multiple_of(self.every.max(1)) && !last {
+            return Ok(());
+        }
+        self.save(agent, training)
+    }
+}
+
+impl SelfPlayObserver for Checkpointer {
+    fn after_game(
+        &mut self,
+        agent: &mut AlphaZeroAgent,
+        training: &mut SelfPlayState,
+        _stats: &SelfPlayStats,
+    ) -> Result<(), ModelError> {
+        let last = training.game == agent.params.games;
+        if !training.game.is_multiple_of(self.every.max(1)) && !last {
+            return Ok(());
+        }
+        self.save_self_play(agent, training)
+    }
+}
+
+/// Returns all numbered checkpoints in `dir`.
+pub fn list(dir: &Path) -> Result<Vec<PathBuf>, ModelError> {
+    let entries =
+        fs::read_dir(dir).map_err(|err| ModelError::Io(dir.display().to_string(), err))?;
+    Ok(entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            name.starts_with(PREFIX) && name.ends_with(".model")
+        })
+        .collect())
+}
+
+fn write(
+    path: &Path,
+    agent: &BorderPatrolAgent,
+    training: &TrainingState,
+    evaluation: f64,
+) -> Result<(), ModelError> {
+    let mut metadata = agent.metadata();
+    metadata.extra = run_entries(training.episode, &training.rng);
+    metadata
+        .extra
+        .push(("evaluation".to_owned(), evaluation.to_string()));
+    if let Some(best) = training.best_evaluation {
+        metadata
+            .extra
+            .push(("best_evaluation".to_owned(), best.to_string()));
+    }
+
+    model::write_with_state(&path.display().to_string(), &metadata, &agent.nn)
+}
+
+/// The position of a run in its episodes and the state of its rng, as extra header entries.
+fn run_entries(episode: usize, rng: &SeededRng) -> Vec<(String, String)> {
+    let seed: String = rng.get_seed().iter().map(|b| format!("{b:02x}")).collect();
+    vec![
+        ("run_episode".to_owned(), episode.to_string()),
+        (
+            "rng".to_owned(),
+            format!("{seed}:{}:{}", rng.get_stream(), rng.get_word_pos()),
+        ),
+    ]
+}
+
+/// Reads the entries written by `run_entries`.
+fn read_run_entries(
+    metadata: &model::ModelMetadata,
+    file_path: &str,
+) -> Result<(usize, SeededRng), ModelError> {
+    let missing = |key: &str| {
+        ModelError::Format(format!("{file_path} is not a checkpoint, {key} is missing"))
+    };
+    let episode = parse_value(
+        "run_episode",
+        metadata
+            .extra("run_episode")
+            .ok_or_else(|| missing("run_episode"))?,
+    )?;
+    let rng = parse_rng(metadata.extra("rng").ok_or_else(|| missing("rng"))?)?;
+    Ok((episode, rng))
+}
+
+/// Loads the agent and training state stored in a checkpoint.
+pub fn resume(file_path: &str) -> Result<(BorderPatrolAgent, TrainingState), ModelError> {
+    let (metadata, network) = model::read(file_path)?;
+    let (episode, rng) = read_run_entries(&metadata, file_path)?;
+    let best_evaluation = match metadata.extra("best_evaluation") {
+        Some(best) => Some(parse_value("best_evaluation", best)?),
+        None => None,
+    };
+
+    let agent = BorderPatrolAgent::from_model(metadata, network)?;
+
+    let replay_path = Path::new(file_path).with_extension("replay");
+    let replay = if replay_path.exists() {
+        read_replay(&replay_path, agent.hyper_parms.replay_capacity)?
+    } else {
+        ReplayBuffer::new(agent.hyper_parms.replay_capacity)
+    };
+
+    println!("Resuming from {file_path} at episode {episode}.");
+    Ok((
+        agent,
+        TrainingState {
+            episode,
+            rng,
+            replay,
+            best_evaluation,
+            league: None,
+        },
+    ))
+}
+
+/// Loads the agent and self-play state stored in a checkpoint of an AlphaZero run.
+pub fn resume_self_play(file_path: &str) -> Result<(AlphaZeroAgent, SelfPlayState), ModelError> {
+    let (metadata, network) = model::read(file_path)?;
+    let (game, rng) = read_run_entries(&metadata, file_path)?;
+    let agent = AlphaZeroAgent::from_model(metadata, network)?;
+
+    println!("Resuming from {file_path} at game {game}.");
+    Ok((agent, SelfPlayState { game, rng }))
+}
+
+fn parse_rng(value: &str) -> Result<SeededRng, ModelError> {
+    let invalid = || ModelError::Format(format!("invalid rng state '{value}'"));
+    let parts: Vec<&str> = value.split(':').collect();
+    if parts.len() != 3 || parts[0].len() != 64 {
+        return Err(invalid());
+    }
+
+    let mut seed = [0u8; 32];
+    for (i, byte) in seed.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&parts[0][2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+    }
+
+    let mut rng = SeededRng::from_seed(seed);
+    rng.set_stream(parse_value("rng stream", parts[1])?);
+    rng.set_word_pos(parse_value("rng position", parts[2])?);
+    Ok(rng)
+}
+
+fn write_replay(path: &Path, replay: &ReplayBuffer) -> Result<(), ModelError> {
+    let io_err = |err| ModelError::Io(path.display().to_string(), err);
+    let mut file = BufWriter::new(File::create(path).map_err(io_err)?);
+    let join = |values: &[f64]| {
+        values
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+
+    for (input, target) in &replay.examples {
+        writeln!(file, "{};{}", join(input), join(target)).map_err(io_err)?;
+    }
+    file.flush().map_err(io_err)
+}
+
+fn read_replay(path: &Path, capacity: usize) -> Result<ReplayBuffer, ModelError> {
+    let io_err = |err| ModelError::Io(path.display().to_string(), err);
+    let file = File::open(path).map_err(io_err)?;
+    let split = |values: &str| -> Result<Vec<f64>, ModelError> {
+        values
+            .split(',')
+            .map(|x| parse_value("replay", x))
+            .collect()
+    };
+
+    let mut replay = ReplayBuffer::new(capacity);
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(io_err)?;
+        match line.split_once(';') {
+            Some((input, target)) => replay.push((split(input)?, split(target)?)),
+            None => {
+                return Err(ModelError::Format(format!(
+                    "invalid replay line in {}",
+                    path.display()
+                )))
+            }
+        }
+    }
+    Ok(replay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        alphazero::AlphaZeroParameters,
+        ml::HyperParameters,
+        network::{Optimizer, TrainingConfig},
+    };
+
+    fn agent() -> BorderPatrolAgent {
+        let hyper_parms = HyperParameters {
+            episodes: 4,
+            replay_capacity: 64,
+            replay_batch: 4,
+            seed: 11,
+            ..Default::default()
+        };
+        let mut agent = BorderPatrolAgent::new_with(&[100, 4, 200], hyper_parms);
+        agent.nn.config = TrainingConfig {
+            optimizer: Optimizer::Adam {
+                beta1: 0.9,
+                beta2: 0.999,
+            },
+            rate: 0.01,
+            ..Default::default()
+        };
+        agent
+    }
+
+    #[test]
+    fn resumed_runs_match_uninterrupted_runs() {
+        let dir = std::env::temp_dir().join(format!("borderpatrol-{}-resume", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let new_checkpointer = |dir: &Path| Checkpointer {
+            every: 2,
+            keep_last: 10,
+            eval_games: 2,
+            save_replay: true,
+            print: false,
+            ..Checkpointer::new(dir.to_str().unwrap())
+        };
+        let mut checkpointer = new_checkpointer(&dir);
+
+        let mut agent = agent();
+        let mut training = TrainingState::new(rng::seeded(11, rng::EXPLORATION), 64);
+        agent
+            .train_from(&mut training, &mut [&mut checkpointer])
+            .unwrap();
+
+        let halfway = dir.join(format!("{PREFIX}00000002.model"));
+        let (mut resumed, mut resumed_training) = resume(halfway.to_str().unwrap()).unwrap();
+        assert_eq!(resumed_training.episode, 2);
+        let mut resumed_checkpointer = new_checkpointer(&dir.join("resumed"));
+        resumed
+            .train_from(&mut resumed_training, &mut [&mut resumed_checkpointer])
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(resumed.nn.to_bytes(true), agent.nn.to_bytes(true));
+        assert_eq!(resumed.trained_episodes, agent.trained_episodes);
+        assert_eq!(resumed_training.episode, training.episode);
+        assert_eq!(
+            resumed_training.rng.get_word_pos(),
+            training.rng.get_word_pos()
+        );
+        assert_eq!(resumed_training.replay.examples, training.replay.examples);
+        assert_eq!(resumed_training.best_evaluation, training.best_evaluation);
+    }
+
+    #[test]
+    fn resumed_self_play_runs_match_uninterrupted_runs() {
+        let dir = std::env::temp_dir().join(format!(
+            "borderpatrol-{}-resume-self-play",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        let mut checkpointer = Checkpointer {
+            every: 1,
+            keep_last: 10,
+            print: false,
+            ..Checkpointer::new(dir.to_str().unwrap())
+        };
+        let params = AlphaZeroParameters {
+            simulations: 2,
+            games: 2,
+            ..Default::default()
+        };
+        let mut agent = AlphaZeroAgent::new_with(&[201, 8, 201], params).unwrap();
+        let mut training = SelfPlayState::new(rng::seeded(0, rng::EXPLORATION));
+        agent
+            .train_from(&mut training, &mut [&mut checkpointer])
+            .unwrap();
+
+        let halfway = dir.join(format!("{PREFIX}00000001.model"));
+        let (mut resumed, mut resumed_training) =
+            resume_self_play(halfway.to_str().unwrap()).unwrap();
+        assert_eq!(resumed_training.game, 1);
+        resumed.train_from(&mut resumed_training, &mut []).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(resumed.nn.to_bytes(true), agent.nn.to_bytes(true));
+        assert_eq!(resumed.trained_games, agent.trained_games);
+        assert_eq!(
+            resumed_training.rng.get_word_pos(),
+            training.rng.get_word_pos()
+        );
+    }
+}
@@ -0,0 +1,269 @@
+#![allow(dead_code)]
+/*
+    Time controls and game clocks. A time control is written as the main time of every
+    player, optionally followed by an increment added after each of their lines or by a
+    byoyomi period that every line may take once the main time is used up:
+
+        5m          five minutes for the whole game
+        5m+3s       five minutes, three seconds added after every line
+        10m/30s     ten minutes, then 30 seconds for every line
+
+    Durations are numbers with a unit of s, m or h, plain numbers are seconds. A player whose
+    line takes longer than their main time plus the byoyomi period loses on time.
+*/
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+/// Players plan their time as if this many of their lines were left.
+const LINES_TO_PLAN_FOR: u32 = 20;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeControl {
+    pub main: Duration,
+    pub increment: Duration,
+    pub byoyomi: Duration,
+}
+
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    let (number, unit) = match text.char_indices().find(|(_, c)| c.is_ascii_alphabetic()) {
+        Some((split, _)) => text.split_at(split),
+        None => (text, "s"),
+    };
+    let seconds: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration '{text}'"))?;
+    let factor = match unit {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(format!("Unknown unit '{unit}' in '{text}', use s, m or h")),
+    };
+    Duration::try_from_secs_f64(seconds * factor).map_err(|_| format!("Invalid duration '{text}'"))
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        _ if duration.subsec_nanos() != 0 => format!("{}s", duration.as_secs_f64()),
+        0 => "0s".to_owned(),
+        _ if seconds.is_multiple_of(3600) => format!("{}h", seconds / 3600),
+        _ if seconds.is_multiple_of(60) => format!("{}m", seconds / 60),
+        _ => format!("{seconds}s"),
+    }
+}
+
+impl FromStr for TimeControl {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut control = TimeControl::default();
+        let main = if let Some((main, increment)) = spec.split_once('+') {
+            control.increment = parse_duration(increment)?;
+            main
+        } else if let Some((main, byoyomi)) = spec.split_once('/') {
+            control.byoyomi = parse_duration(byoyomi)?;
+            main
+        } else {
+            spec
+        };
+        control.main = parse_duration(main)?;
+        if control.main.is_zero() && control.byoyomi.is_zero() {
+            return Err(format!("Time control '{spec}' leaves no time to move"));
+        }
+        Ok(control)
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format_duration(self.main))?;
+        if !self.increment.is_zero() {
+            write!(f, "+{}", format_duration(self.increment))?;
+        }
+        if !self.byoyomi.is_zero() {
+            write!(f, "/{}", format_duration(self.byoyomi))?;
+        }
+        Ok(())
+    }
+}
+
+/// The clocks of both players, counted as 0 for player one and 1 for player two.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    pub control: TimeControl,
+    /// Main time left of each player, not counting the running clock.
+    main: [Duration; 2],
+    /// The player whose clock runs and since when.
+    running: Option<(usize, Instant)>,
+    /// Since when the running clock is paused.
+    paused: Option<Instant>,
+    flagged: Option<usize>,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Clock {
+        Clock {
+            control,
+            main: [control.main; 2],
+            running: None,
+            paused: None,
+            flagged: None,
+        }
+    }
+
+    pub fn running(&self) -> Option<usize> {
+        self.running.map(|(player, _)| player)
+    }
+
+    /// Starts the clock of `player` for their next line.
+    pub fn start(&mut self, player: usize) {
+        if self.flagged.is_none() {
+            self.running = Some((player, Instant::now()));
+        }
+    }
+
+    /// Stops the running clock without ending the line of its player, e.g. while the game is
+    /// paused.
+    pub fn pause(&mut self) {
+        self.paused.get_or_insert_with(Instant::now);
+    }
+
+    pub fn resume(&mut self) {
+        let Some(paused) = self.paused.take() else {
+            return;
+        };
+        if let Some((_, started)) = &mut self.running {
+            *started += paused.elapsed();
+        }
+    }
+
+    /// Time the running clock has run since `started`, not counting a pause.
+    fn used(&self, started: Instant) -> Duration {
+        self.paused
+            .unwrap_or_else(Instant::now)
+            .saturating_duration_since(started)
+    }
+
+    /// Stops the running clock after its player drew a line and adds the increment. Returns
+    /// false if the line came too late and the player lost on time.
+    pub fn stop(&mut self) -> bool {
+        let Some((player, started)) = self.running.take() else {
+            return self.flagged.is_none();
+        };
+        let elapsed = self.used(started);
+        if elapsed > self.main[player] + self.control.byoyomi {
+            self.main[player] = Duration::ZERO;
+            self.flagged = Some(player);
+            return false;
+        }
+        self.main[player] = self.main[player].saturating_sub(elapsed) + self.control.increment;
+        true
+    }
+
+    /// Main time left of `player`, counting the running clock.
+    pub fn main_left(&self, player: usize) -> Duration {
+        match self.running {
+            Some((running, started)) if running == player => {
+                self.main[player].saturating_sub(self.used(started))
+            }
+            _ => self.main[player],
+        }
+    }
+
+    /// Time `player` has left for the line they are drawing, main time and byoyomi.
+    pub fn line_left(&self, player: usize) -> Duration {
+        match self.running {
+            Some((running, started)) if running == player => {
+                (self.main[player] + self.control.byoyomi).saturating_sub(self.used(started))
+            }
+            _ => self.main[player] + self.control.byoyomi,
+        }
+    }
+
+    /// The player who ran out of time, also while their clock is still running.
+    pub fn flagged(&self) -> Option<usize> {
+        self.flagged.or_else(|| {
+            self.running
+                .map(|(player, _)| player)
+                .filter(|&player| self.line_left(player).is_zero())
+        })
+    }
+
+    /// When the player to move should have drawn their line: a share of the main time plus
+    /// the increment and most of the byoyomi, well before they would lose on time.
+    pub fn deadline(&self, player: usize) -> Instant {
+        let main = self.main_left(player);
+        let budget =
+            main / LINES_TO_PLAN_FOR + self.control.increment + self.control.byoyomi * 4 / 5;
+        Instant::now() + budget.min(self.line_left(player) * 4 / 5)
+    }
+
+    /// The clock of `player` as shown to them, e.g. `4:59`, `0:08.4` below ten seconds, or
+    /// the byoyomi period left once the main time is used up.
+    pub fn display(&self, player: usize) -> String {
+        let main = self.main_left(player);
+        if main.is_zero() && !self.control.byoyomi.is_zero() {
+            return format!("BY {}", format_clock(self.line_left(player)));
+        }
+        format_clock(main)
+    }
+}
+
+fn format_clock(time: Duration) -> String {
+    let seconds = time.as_secs();
+    if seconds < 10 {
+        return format!("0:{seconds:02}.{}", time.subsec_millis() / 100);
+    }
+    if seconds >= 3600 {
+        return format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        );
+    }
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_controls_parse_their_display() {
+        let cases = [
+            ("5m", (300, 0, 0)),
+            ("5m+3s", (300, 3, 0)),
+            ("10m/30s", (600, 0, 30)),
+            ("1h", (3600, 0, 0)),
+            ("0s/30s", (0, 0, 30)),
+        ];
+        for (spec, (main, increment, byoyomi)) in cases {
+            let control: TimeControl = spec.parse().unwrap();
+            assert_eq!(
+                control,
+                TimeControl {
+                    main: Duration::from_secs(main),
+                    increment: Duration::from_secs(increment),
+                    byoyomi: Duration::from_secs(byoyomi),
+                }
+            );
+            assert_eq!(control.to_string(), spec);
+        }
+        assert_eq!("90".parse::<TimeControl>().unwrap().to_string(), "90s");
+        assert_eq!(
+            "1.5s".parse::<TimeControl>().unwrap().main,
+            Duration::from_millis(1500)
+        );
+    }
+
+    #[test]
+    fn parse_rejects_invalid_time_controls() {
+        for spec in ["", "0s", "5d", "five minutes", "-5m", "5m+", "5m+3s/30s"] {
+            assert!(spec.parse::<TimeControl>().is_err(), "accepted '{spec}'");
+        }
+    }
+}
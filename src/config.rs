@@ -0,0 +1,21 @@
+#![allow(dead_code)]
+/*
+    User configuration lives in `$XDG_CONFIG_HOME/borderpatrol`, or `~/.config/borderpatrol`
+    if that is not set. Every setting has its own plain text file there and missing files
+    mean the defaults.
+*/
+use std::{env, path::PathBuf};
+
+pub fn config_dir() -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => Some(PathBuf::from(dir).join("borderpatrol")),
+        None => {
+            env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("borderpatrol"))
+        }
+    }
+}
+
+/// Path of a file in the config directory.
+pub fn config_file(name: &str) -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join(name))
+}
@@ -0,0 +1,197 @@
+#![allow(dead_code)]
+use crate::{
+    checkpoint::Checkpointer,
+    engine::{Scene, Transition},
+    keys::Action,
+    metrics::MetricsLogger,
+    ml::{BorderPatrolAgent, TrainingObserver, TrainingState},
+    screen::{Screen, Style},
+};
+
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const SPARKLINE_WIDTH: usize = 60;
+const LEFT: usize = 16;
+
+/// Runs training inside the engine, one episode per update, and shows the metrics as
+/// sparklines over the whole run.
+pub struct TrainingDashboard {
+    agent: BorderPatrolAgent,
+    training: TrainingState,
+    metrics: MetricsLogger,
+    checkpointer: Option<Checkpointer>,
+    save_at: String,
+    finished: Option<String>,
+}
+
+impl TrainingDashboard {
+    pub fn new(
+        agent: BorderPatrolAgent,
+        training: TrainingState,
+        mut metrics: MetricsLogger,
+        mut checkpointer: Option<Checkpointer>,
+        save_at: &str,
+    ) -> Self {
+        // Printed lines would only flicker between two frames, the dashboard shows them instead.
+        metrics.print_every = 0;
+        if let Some(checkpointer) = &mut checkpointer {
+            checkpointer.print = false;
+        }
+        TrainingDashboard {
+            agent,
+            training,
+            metrics,
+            checkpointer,
+            save_at: save_at.to_owned(),
+            finished: None,
+        }
+    }
+
+    fn render_metrics(&self, screen: &mut Screen) {
+        let history = &self.metrics.history;
+        let last = history.last();
+        let total = self.agent.hyper_parms.episodes;
+        let done = self.training.episode.min(total);
+        let progress = SPARKLINE_WIDTH * done / total.max(1);
+
+        screen.print(2, LEFT, "Training dashboard", Style::PLAIN.bold());
+        screen.print(4, LEFT, &format!("Episode {done} / {total}"), Style::PLAIN);
+        let bar = format!(
+            "[{}{}]",
+            "#".repeat(progress),
+            ".".repeat(SPARKLINE_WIDTH - progress)
+        );
+        screen.print(5, LEFT, &bar, Style::PLAIN);
+
+        let rows: [(&str, Option<f64>, Vec<f64>); 5] = [
+            (
+                "Epsilon",
+                last.map(|row| row.stats.epsilon),
+                history.iter().map(|row| row.stats.epsilon).collect(),
+            ),
+            (
+                "TD error",
+                last.map(|row| row.avg_td_error),
+                history.iter().map(|row| row.avg_td_error).collect(),
+            ),
+            (
+                "Q value",
+                last.map(|row| row.avg_q_value),
+                history.iter().map(|row| row.avg_q_value).collect(),
+            ),
+            (
+                "Game length",
+                last.map(|row| row.avg_length),
+                history.iter().map(|row| row.avg_length).collect(),
+            ),
+            (
+                "Win rate",
+                self.metrics.last_win_rate(),
+                history.iter().filter_map(|row| row.win_rate).collect(),
+            ),
+        ];
+
+        let mut row = 7;
+        for (name, value, values) in rows {
+            let value = value
+                .map(|x| format!("{x:.4}"))
+                .unwrap_or_else(|| "-".to_owned());
+            let text = format!(
+                "{name:<12} {value:>10}   {}",
+                sparkline(&values, SPARKLINE_WIDTH)
+            );
+            screen.print(row, LEFT, &text, Style::PLAIN);
+            row += 1;
+        }
+
+        if let Some(best) = self.training.best_evaluation {
+            row += 1;
+            screen.print(
+                row,
+                LEFT,
+                &format!("Best checkpoint {best:.3}"),
+                Style::PLAIN,
+            );
+            row += 1;
+        }
+        if let Some((path, evaluation)) = self
+            .checkpointer
+            .as_ref()
+            .and_then(|checkpointer| checkpointer.last_saved.as_ref())
+        {
+            screen.print(
+                row,
+                LEFT,
+                &format!(
+                    "Saved checkpoint {} (evaluation {evaluation:.3})",
+                    path.display()
+                ),
+                Style::PLAIN,
+            );
+            row += 1;
+        }
+        if let Some(msg) = &self.finished {
+            screen.print(row + 1, LEFT, msg, Style::PLAIN);
+            screen.print(row + 2, LEFT, "Press q to exit.", Style::PLAIN);
+        }
+    }
+}
+
+/// Draws `values` as a line of block characters, averaging them into at most `width` buckets.
+pub fn sparkline(values: &[f64], width: usize) -> String {
+    if values.is_empty() || width == 0 {
+        return String::new();
+    }
+
+    let buckets = values.len().min(width);
+    let means: Vec<f64> = (0..buckets)
+        .map(|i| {
+            let bucket = &values[i * values.len() / buckets..(i + 1) * values.len() / buckets];
+            bucket.iter().sum::<f64>() / bucket.len() as f64
+        })
+        .collect();
+
+    let min = means.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = means.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    means
+        .iter()
+        .map(|x| {
+            if max <= min {
+                return SPARKS[0];
+            }
+            SPARKS[((x - min) / (max - min) * (SPARKS.len() - 1) as f64).round() as usize]
+        })
+        .collect()
+}
+
+impl Scene for TrainingDashboard {
+    fn update(&mut self) -> Transition {
+        let mut observers: Vec<&mut dyn TrainingObserver> = vec![&mut self.metrics];
+        if let Some(checkpointer) = &mut self.checkpointer {
+            observers.push(checkpointer);
+        }
+
+        match self.agent.train_step(&mut self.training, &mut observers) {
+            Ok(Some(_)) => (),
+            Ok(None) => {
+                self.finished = Some(match self.agent.write(&self.save_at) {
+                    Ok(()) => format!("Training finished, saved to {}.", self.save_at),
+                    Err(err) => err.to_string(),
+                })
+            }
+            Err(err) => self.finished = Some(err.to_string()),
+        }
+        Transition::Stay
+    }
+
+    fn wait_for_input(&self) -> bool {
+        self.finished.is_some()
+    }
+
+    fn render(&self, screen: &mut Screen) {
+        self.render_metrics(screen)
+    }
+
+    fn react(&mut self, _action: Action) -> Transition {
+        Transition::Stay
+    }
+}
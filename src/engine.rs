@@ -1,91 +1,631 @@
-#![allow(unused)]
-use console::Term;
+/*
+    The engine runs a stack of scenes, like a main menu with a game on top of it and a pause
+    menu on top of the game. Only the scene on top is ticked and gets the input, and every
+    tick, action and mouse event answers with a transition: stay, push a new scene, pop back
+    to the scene below, switch the top scene for another, go back home to the bottom scene or
+    quit. Scenes that are covered by a push are paused and resumed once they are on top
+    again. The engine stops when the last scene is popped.
 
-pub trait Game {
-    fn update(&mut self, content: &mut String) -> bool;
+    The engine runs at a fixed tick rate. Every tick the top scene is ticked, so it can run
+    its timers and animations and look after work done on other threads, and then rendered.
+    Scenes that have work of their own between ticks, like training, are updated over and
+    over until the next tick is due; all others sleep until the next tick or the next input.
+
+    Scenes are drawn by diffing. Scenes render into a fresh screen on every tick and after
+    every input, and only the cells that differ from the screen shown before are written.
+    Overlays like the pause menu are drawn over the scenes below them. The terminal is cleared
+    and drawn in full on the first render and whenever its size changed, so a resized terminal
+    is laid out again on the next tick.
+
+    Input is read on a thread of its own and sent to the engine, so it never blocks a tick.
+    The thread waits for input a little at a time and stops when the engine does. Keys are
+    turned into actions by the key bindings. The engine quits and shows the help overlay
+    itself and passes every other action on to the top scene.
+
+    Mouse reporting is switched on in SGR mode (ESC [ < button ; column ; row M, or m on
+    release) with motion events, which arrive as unknown escape sequences from `read_key`
+    followed by the rest of the report. Echo stays off while the engine runs so reports that
+    arrive between two reads do not show up on the screen. The terminal is put back the way
+    it was when the engine stops, and also when a thread panics, before the panic message is
+    printed.
+*/
+use std::{
+    fmt::Write as _,
+    panic::{self, PanicHookInfo},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use console::{Key, Term};
+
+use crate::{
+    keys::{Action, KeyBindings},
+    screen::{Screen, Style},
+    theme::Theme,
+};
+
+const MOUSE_ON: &str = "\x1B[?1003h\x1B[?1006h";
+const MOUSE_OFF: &str = "\x1B[?1003l\x1B[?1006l";
+/// Time between two ticks, 25 a second.
+const TICK: Duration = Duration::from_millis(40);
+/// How long the input thread waits for input before it checks whether to stop, in
+/// milliseconds.
+const INPUT_POLL: i32 = 50;
+
+pub trait Scene {
+    /// Does a piece of the work of a scene that does not wait for input. Called over and over
+    /// between ticks.
+    fn update(&mut self) -> Transition;
+    /// Whether the scene has nothing to do but react to input and ticks.
     fn wait_for_input(&self) -> bool;
-    fn init_screen(&mut self) -> String;
-    fn react(&mut self, content: &mut String, key: char) -> bool;
+    fn render(&self, screen: &mut Screen);
+    fn react(&mut self, action: Action) -> Transition;
+
+    /// Called for clicks and pointer movement.
+    fn mouse(&mut self, _event: MouseEvent) -> Transition {
+        Transition::Stay
+    }
+
+    /// Called once a tick.
+    fn tick(&mut self) -> Transition {
+        Transition::Stay
+    }
+
+    /// Whether the scene is drawn over the scene below it, like a pause menu over the game.
+    fn overlay(&self) -> bool {
+        false
+    }
+
+    /// Called when another scene is pushed on top of this one.
+    fn pause(&mut self) {}
+
+    /// Called when this scene is on top again.
+    fn resume(&mut self) {}
+}
+
+/// Where the engine goes after a scene was ticked or reacted to input.
+pub enum Transition {
+    Stay,
+    Push(Box<dyn Scene>),
+    /// Back to the scene below, the engine stops after the last scene.
+    Pop,
+    /// Replaces the top scene.
+    Switch(Box<dyn Scene>),
+    /// Back to the scene at the bottom of the stack.
+    Home,
+    Quit,
+}
+
+/// Measures a span of time from when it was started, for countdowns and animations that
+/// games check on their ticks.
+#[derive(Debug, Clone, Copy)]
+pub struct Timer {
+    start: Instant,
+    duration: Duration,
+}
+
+impl Timer {
+    pub fn start(duration: Duration) -> Timer {
+        Timer {
+            start: Instant::now(),
+            duration,
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    pub fn done(&self) -> bool {
+        self.elapsed() >= self.duration
+    }
+
+    /// How much of the span has passed, from 0 to 1.
+    pub fn progress(&self) -> f64 {
+        if self.duration.is_zero() {
+            return 1.0;
+        }
+        (self.elapsed().as_secs_f64() / self.duration.as_secs_f64()).min(1.0)
+    }
+
+    /// Which of the frames of an animation that shows every frame for `frame` is due, counting
+    /// on forever.
+    pub fn frame(&self, frame: Duration) -> usize {
+        (self.elapsed().as_nanos() / frame.as_nanos().max(1)) as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseKind {
+    /// The left button was pressed.
+    Click,
+    /// The pointer moved, with or without a button held.
+    Move,
+}
+
+/// A mouse event at a cell of the screen, counted from 0 like the cells of `Screen`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub kind: MouseKind,
+    pub row: usize,
+    pub column: usize,
+}
+
+impl MouseEvent {
+    /// Parses the part of an SGR report after `ESC [ <`, e.g. `0;12;5M`.
+    fn parse(report: &str) -> Option<MouseEvent> {
+        let (fields, pressed) = match report.strip_suffix('M') {
+            Some(fields) => (fields, true),
+            None => (report.strip_suffix('m')?, false),
+        };
+        let mut fields = fields.split(';').map(|field| field.parse::<usize>().ok());
+        let (button, column, row) = (fields.next()??, fields.next()??, fields.next()??);
+        let kind = match button {
+            b if b & 32 != 0 => MouseKind::Move,
+            0 if pressed => MouseKind::Click,
+            _ => return None,
+        };
+        Some(MouseEvent {
+            kind,
+            row: row.checked_sub(1)?,
+            column: column.checked_sub(1)?,
+        })
+    }
+}
+
+enum Input {
+    Key(Key),
+    Mouse(MouseEvent),
 }
 
 pub struct Engine {
-    game: Box<dyn Game>,
-    scene: Scene,
+    scenes: Vec<Box<dyn Scene>>,
+    display: Display,
     running: bool,
+    pub tick: Duration,
+    pub bindings: KeyBindings,
+    /// The help overlay is drawn with the frame of the theme.
+    pub theme: Theme,
 }
 
 impl Engine {
-    pub fn new(game: Box<dyn Game>) -> Engine {
+    pub fn new(scene: Box<dyn Scene>) -> Engine {
         Engine {
-            game,
-            scene: Scene::new(),
+            scenes: vec![scene],
+            display: Display::new(),
             running: false,
+            tick: TICK,
+            bindings: KeyBindings::default(),
+            theme: Theme::default(),
         }
     }
 
     pub fn start(&mut self) {
-        self.scene.term.set_title("Terminal Engine");
-        self.running = true;
-        self.scene.init(&mut self.game);
+        self.display.term.set_title("Terminal Engine");
+        let terminal = TerminalGuard::new(&self.display.term);
+        self.running = !self.scenes.is_empty();
 
-        self.scene.render();
+        // Dropped before the guard, so the terminal is only restored once nothing reads it
+        let inputs = InputThread::spawn();
+        let mut next_tick = Instant::now();
         while self.running {
-            let key = if self.game.wait_for_input() {
-                Some(self.scene.term.read_char().expect("Could not read key."))
+            let now = Instant::now();
+            if now >= next_tick {
+                // Ticks that were missed are skipped rather than caught up with
+                next_tick = now + self.tick;
+                let transition = self.top().tick();
+                self.go(transition);
+                self.render();
+                continue;
+            }
+
+            let timeout = if self.top().wait_for_input() {
+                next_tick - now
             } else {
-                None
+                Duration::ZERO
             };
-
-            match key {
-                Some('q') => self.running = false,
-                Some(key) => {
-                    let stay = self.game.react(&mut self.scene.current, key);
-                    self.scene.render();
-                    if !stay {
-                        self.scene.undo();
+            match inputs.receiver.recv_timeout(timeout) {
+                Ok(input) => {
+                    self.handle(input);
+                    self.render();
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if !self.top().wait_for_input() {
+                        let transition = self.top().update();
+                        self.go(transition);
                     }
                 }
-                _ => {
-                    self.running = self.game.update(&mut self.scene.current);
-                    self.scene.render();
+                Err(RecvTimeoutError::Disconnected) => self.running = false,
+            }
+        }
+        drop(inputs);
+        drop(terminal);
+    }
+
+    /// The scene that is ticked and gets the input, there is one as long as the engine runs.
+    fn top(&mut self) -> &mut Box<dyn Scene> {
+        self.scenes
+            .last_mut()
+            .expect("the engine runs without a scene")
+    }
+
+    fn handle(&mut self, input: Input) {
+        let transition = match input {
+            Input::Mouse(event) => self.top().mouse(event),
+            Input::Key(key) => match self.bindings.action(&key) {
+                Some(Action::Quit) => Transition::Quit,
+                Some(Action::Help) => {
+                    self.display.help = !self.display.help;
+                    Transition::Stay
+                }
+                Some(action) => self.top().react(action),
+                None => Transition::Stay,
+            },
+        };
+        self.go(transition);
+    }
+
+    fn go(&mut self, transition: Transition) {
+        match transition {
+            Transition::Stay => return,
+            Transition::Push(scene) => {
+                self.top().pause();
+                self.scenes.push(scene);
+                return;
+            }
+            Transition::Pop => {
+                self.scenes.pop();
+            }
+            Transition::Switch(scene) => {
+                self.scenes.pop();
+                self.scenes.push(scene);
+                return;
+            }
+            Transition::Home => self.scenes.truncate(1),
+            Transition::Quit => self.scenes.clear(),
+        }
+        match self.scenes.last_mut() {
+            Some(scene) => scene.resume(),
+            None => self.running = false,
+        }
+    }
+
+    fn render(&mut self) {
+        if self.running {
+            self.display
+                .render(&self.scenes, &self.bindings, &self.theme);
+        }
+    }
+}
+
+/// Reads input on a thread of its own until it is dropped or the engine stops listening.
+struct InputThread {
+    receiver: Receiver<Input>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl InputThread {
+    fn spawn() -> InputThread {
+        let (sender, receiver) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let handle = thread::spawn(move || {
+            let term = Term::stdout();
+            while !stopped.load(Ordering::Relaxed) {
+                if !input_ready(INPUT_POLL) {
+                    continue;
+                }
+                let Some(input) = read_input(&term) else {
+                    break;
+                };
+                if sender.send(input).is_err() {
+                    break;
                 }
             }
+        });
+        InputThread {
+            receiver,
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for InputThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            // A panic of the thread was already reported by the panic hook
+            let _ = handle.join();
         }
-        self.scene.term.clear_screen().unwrap();
     }
 }
 
-struct Scene {
-    current: String,
-    tmp: String,
-    last: String,
+/// Whether there is input to read within `timeout` milliseconds. Keys are read from the
+/// controlling terminal when stdin is not one, which can not be waited for, so that is
+/// always ready and blocks in the read instead.
+fn input_ready(timeout: i32) -> bool {
+    if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
+        return true;
+    }
+    let mut stdin = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut stdin, 1, timeout) != 0 }
+}
+
+/// The next key or mouse event, `None` once the terminal can not be read.
+fn read_input(term: &Term) -> Option<Input> {
+    let key = term.read_key().ok()?;
+    let Key::UnknownEscSeq(sequence) = &key else {
+        return Some(Input::Key(key));
+    };
+    if !sequence.starts_with(&['[', '<']) {
+        return Some(Input::Key(key));
+    }
+    // The rest of the report is already waiting and comes in one char at a time
+    let mut report: String = sequence[2..].iter().collect();
+    while !report.ends_with(['M', 'm']) {
+        match term.read_key() {
+            Ok(Key::Char(c)) if report.len() < 32 => report.push(c),
+            _ => break,
+        }
+    }
+    match MouseEvent::parse(&report) {
+        Some(event) => Some(Input::Mouse(event)),
+        None => Some(Input::Key(Key::Unknown)),
+    }
+}
+
+/// Sets the terminal up for the engine, with the cursor hidden, echo and line buffering off
+/// and mouse reporting on, and puts it back when dropped. Until then a panic puts it back
+/// before the panic message is printed.
+struct TerminalGuard {
     term: Term,
+    original: Option<libc::termios>,
+    /// The panic hook from before, which is put back as well.
+    previous: PanicHook,
 }
 
-impl Scene {
-    fn new() -> Scene {
-        Scene {
-            current: String::new(),
-            tmp: String::new(),
-            last: String::new(),
+type PanicHook = Arc<dyn Fn(&PanicHookInfo) + Send + Sync>;
+
+impl TerminalGuard {
+    fn new(term: &Term) -> TerminalGuard {
+        let mut termios = std::mem::MaybeUninit::uninit();
+        let original = if unsafe { libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) } == 0
+        {
+            let original = unsafe { termios.assume_init() };
+            let mut quiet = original;
+            quiet.c_lflag &= !(libc::ECHO | libc::ICANON);
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &quiet) };
+            Some(original)
+        } else {
+            None
+        };
+        term.hide_cursor().ok();
+        term.write_str(MOUSE_ON).ok();
+
+        let previous: PanicHook = Arc::from(panic::take_hook());
+        let (hook_term, hook_previous) = (term.clone(), previous.clone());
+        panic::set_hook(Box::new(move |info| {
+            restore(&hook_term, original.as_ref());
+            // Below what was drawn so far
+            let _ = hook_term.write_line("");
+            hook_previous(info);
+        }));
+        TerminalGuard {
+            term: term.clone(),
+            original,
+            previous,
+        }
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore(&self.term, self.original.as_ref());
+        // The hook can not be changed while panicking, and the screen keeps the message
+        if !thread::panicking() {
+            let previous = self.previous.clone();
+            panic::set_hook(Box::new(move |info| previous(info)));
+            self.term.clear_screen().ok();
+        }
+    }
+}
+
+/// Undoes what `TerminalGuard` did to the terminal. Restoring twice does no harm.
+fn restore(term: &Term, original: Option<&libc::termios>) {
+    term.write_str(&format!("\x1B[0m{MOUSE_OFF}")).ok();
+    if let Some(original) = original {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, original) };
+    }
+    term.show_cursor().ok();
+}
+
+struct Display {
+    term: Term,
+    help: bool,
+    /// The screen shown and the terminal size it was drawn for.
+    shown: Option<(Screen, (u16, u16))>,
+}
+
+impl Display {
+    fn new() -> Display {
+        Display {
             term: Term::stdout(),
+            help: false,
+            shown: None,
+        }
+    }
+
+    /// Draws the top scene and the scenes below it that it is an overlay of.
+    fn render(&mut self, scenes: &[Box<dyn Scene>], bindings: &KeyBindings, theme: &Theme) {
+        let size = self.term.size();
+        let mut screen = Screen::sized(size);
+        let bottom = scenes
+            .iter()
+            .rposition(|scene| !scene.overlay())
+            .unwrap_or(0);
+        for scene in &scenes[bottom..] {
+            scene.render(&mut screen);
+        }
+        if self.help {
+            render_help(&mut screen, bindings, theme);
+        } else if !bindings.keys(Action::Help).is_empty() {
+            let hint = format!("{} help", bindings.describe(Action::Help));
+            screen.print(screen.height() + 1, 0, &hint, Style::PLAIN);
+        }
+
+        let mut output = String::new();
+        let previous = match self.shown.take() {
+            Some((previous, shown_size)) if shown_size == size => previous,
+            _ => {
+                output.push_str("\x1B[2J");
+                Screen::new()
+            }
+        };
+        screen.diff(&previous, size, &mut output);
+
+        let (height, _) = size;
+        let _ = write!(
+            output,
+            "\x1B[{};1H",
+            screen.height().min(height as usize).max(1)
+        );
+        self.term.write_str(&output).unwrap();
+        self.term.flush().unwrap();
+        self.shown = Some((screen, size));
+    }
+}
+
+/// A framed list of the actions and their keys, drawn over the top left of the screen.
+fn render_help(screen: &mut Screen, bindings: &KeyBindings, theme: &Theme) {
+    let lines: Vec<(String, String)> = Action::ALL
+        .iter()
+        .filter(|&&action| !bindings.keys(action).is_empty())
+        .map(|&action| (action.description().to_owned(), bindings.describe(action)))
+        .collect();
+    let description_width = lines
+        .iter()
+        .map(|(d, _)| d.chars().count())
+        .max()
+        .unwrap_or(0);
+    let keys_width = lines
+        .iter()
+        .map(|(_, k)| k.chars().count())
+        .max()
+        .unwrap_or(0);
+    let width = description_width + keys_width + 3;
+
+    let (top, left) = (1, 2);
+    let (frame, border) = (&theme.frame, theme.frame_style.bold());
+    let horizontal = frame.horizontal.to_string().repeat(width + 2);
+    let (side, end) = (
+        format!("{} ", frame.vertical),
+        format!(" {}", frame.vertical),
+    );
+    screen.print(
+        top,
+        left,
+        &format!("{}{horizontal}{}", frame.top_left, frame.top_right),
+        border,
+    );
+    let title = format!("{:<width$}", "Keys");
+    let column = screen.print(top + 1, left, &side, border);
+    let column = screen.print(top + 1, column, &title, border);
+    screen.print(top + 1, column, &end, border);
+    for (i, (description, keys)) in lines.iter().enumerate() {
+        let row = top + 2 + i;
+        let column = screen.print(row, left, &side, border);
+        let column = screen.print(
+            row,
+            column,
+            &format!("{description:<description_width$}   "),
+            Style::PLAIN,
+        );
+        let column = screen.print(
+            row,
+            column,
+            &format!("{keys:<keys_width$}"),
+            Style::PLAIN.bold(),
+        );
+        screen.print(row, column, &end, border);
+    }
+    let bottom = format!("{}{horizontal}{}", frame.bottom_left, frame.bottom_right);
+    screen.print(top + 2 + lines.len(), left, &bottom, border);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A timer that was started `elapsed` ago.
+    fn started(elapsed: u64, duration: u64) -> Timer {
+        Timer {
+            start: Instant::now() - Duration::from_millis(elapsed),
+            duration: Duration::from_millis(duration),
         }
     }
 
-    fn init(&mut self, game: &mut Box<dyn Game>) {
-        self.current = game.init_screen();
-        self.last = self.current.to_owned();
+    #[test]
+    fn timer_progresses_until_done() {
+        let timer = started(50, 200);
+        assert!(!timer.done());
+        assert!((0.25..0.5).contains(&timer.progress()));
+        assert!(timer.elapsed() >= Duration::from_millis(50));
+
+        let timer = started(300, 200);
+        assert!(timer.done());
+        assert_eq!(timer.progress(), 1.0);
+        // A timer without a duration is done right away
+        assert!(Timer::start(Duration::ZERO).done());
+        assert_eq!(Timer::start(Duration::ZERO).progress(), 1.0);
     }
 
-    fn undo(&mut self) {
-        self.current = self.last.to_owned();
-        self.tmp = self.current.to_owned();
+    #[test]
+    fn timer_counts_frames() {
+        assert_eq!(started(0, 0).frame(Duration::from_secs(60)), 0);
+        assert_eq!(started(250, 0).frame(Duration::from_millis(100)), 2);
+        assert_eq!(started(1050, 100).frame(Duration::from_millis(100)), 10);
+        // Frames without a length count nanoseconds
+        assert!(started(1, 0).frame(Duration::ZERO) >= 1_000_000);
     }
 
-    pub fn render(&mut self) {
-        self.term.clear_screen().unwrap();
-        println!("{}", self.current);
-        self.last = self.tmp.to_owned();
-        self.tmp = self.current.to_owned();
+    fn event(kind: MouseKind, row: usize, column: usize) -> Option<MouseEvent> {
+        Some(MouseEvent { kind, row, column })
+    }
+
+    #[test]
+    fn mouse_event_parses_presses_and_motion() {
+        assert_eq!(MouseEvent::parse("0;12;5M"), event(MouseKind::Click, 4, 11));
+        assert_eq!(MouseEvent::parse("0;1;1M"), event(MouseKind::Click, 0, 0));
+        // Drag with the left button held and motion without a button
+        assert_eq!(MouseEvent::parse("32;3;4M"), event(MouseKind::Move, 3, 2));
+        assert_eq!(MouseEvent::parse("35;3;4M"), event(MouseKind::Move, 3, 2));
+    }
+
+    #[test]
+    fn mouse_event_ignores_releases_other_buttons_and_scrolling() {
+        for report in ["0;12;5m", "1;12;5M", "2;12;5M", "64;12;5M", "65;12;5M"] {
+            assert_eq!(MouseEvent::parse(report), None, "{report}");
+        }
+    }
+
+    #[test]
+    fn mouse_event_rejects_malformed_reports() {
+        for report in [
+            "", "M", "0;12M", "0;12;5", "0;12;5X", "a;12;5M", "0;;5M", "0;-1;5M", "0;0;5M",
+            "0;12;0M",
+        ] {
+            assert_eq!(MouseEvent::parse(report), None, "{report}");
+        }
     }
 }
@@ -0,0 +1,311 @@
+#![allow(dead_code)]
+/*
+    Key bindings. The `keys` file in the config directory maps actions to keys, one action
+    per line followed by its keys. Actions that are not listed keep their default keys:
+
+        # action = keys
+        left = h a left
+        down = j s down
+        up = k w up
+        right = l d right
+        flip = f tab
+        place = space enter
+        back = escape p
+        help = ?
+        quit = q
+
+    Keys are single characters or one of space, hash, enter, tab, backspace, escape, left,
+    right, up, down, home, end, pageup, pagedown, insert and delete. Everything after a `#` is
+    a comment, so `#` itself is bound as hash. A key listed for one action is taken from the
+    defaults of the others, so `quit = q escape` leaves back with just p.
+*/
+use std::{fmt, fs, path::Path, str::FromStr};
+
+use console::Key;
+
+use crate::config;
+
+const KEYS_FILE: &str = "keys";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Left,
+    Right,
+    Up,
+    Down,
+    /// Switches the cursor between horizontal and vertical lines.
+    Flip,
+    Place,
+    /// Pauses the game, or goes back from a menu.
+    Back,
+    Help,
+    Quit,
+}
+
+impl Action {
+    pub const ALL: [Action; 9] = [
+        Action::Left,
+        Action::Down,
+        Action::Up,
+        Action::Right,
+        Action::Flip,
+        Action::Place,
+        Action::Back,
+        Action::Help,
+        Action::Quit,
+    ];
+
+    /// What the action does, for the help overlay.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::Left => "Move left",
+            Action::Right => "Move right",
+            Action::Up => "Move up",
+            Action::Down => "Move down",
+            Action::Flip => "Switch between horizontal and vertical lines",
+            Action::Place => "Draw the line",
+            Action::Back => "Pause, or go back from a menu",
+            Action::Help => "Show or hide this help",
+            Action::Quit => "Quit",
+        }
+    }
+
+    fn default_keys(&self) -> Vec<Key> {
+        match self {
+            Action::Left => vec![Key::Char('h'), Key::Char('a'), Key::ArrowLeft],
+            Action::Down => vec![Key::Char('j'), Key::Char('s'), Key::ArrowDown],
+            Action::Up => vec![Key::Char('k'), Key::Char('w'), Key::ArrowUp],
+            Action::Right => vec![Key::Char('l'), Key::Char('d'), Key::ArrowRight],
+            Action::Flip => vec![Key::Char('f'), Key::Tab],
+            Action::Place => vec![Key::Char(' '), Key::Enter],
+            Action::Back => vec![Key::Escape, Key::Char('p')],
+            Action::Help => vec![Key::Char('?')],
+            Action::Quit => vec![Key::Char('q')],
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Action::ALL
+            .into_iter()
+            .find(|action| action.to_string() == name)
+            .ok_or_else(|| format!("Unknown action '{name}'"))
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Action::Left => "left",
+            Action::Right => "right",
+            Action::Up => "up",
+            Action::Down => "down",
+            Action::Flip => "flip",
+            Action::Place => "place",
+            Action::Back => "back",
+            Action::Help => "help",
+            Action::Quit => "quit",
+        };
+        write!(f, "{name}")
+    }
+}
+
+const KEY_NAMES: [(&str, Key); 16] = [
+    ("space", Key::Char(' ')),
+    ("hash", Key::Char('#')),
+    ("enter", Key::Enter),
+    ("tab", Key::Tab),
+    ("backspace", Key::Backspace),
+    ("escape", Key::Escape),
+    ("left", Key::ArrowLeft),
+    ("right", Key::ArrowRight),
+    ("up", Key::ArrowUp),
+    ("down", Key::ArrowDown),
+    ("home", Key::Home),
+    ("end", Key::End),
+    ("pageup", Key::PageUp),
+    ("pagedown", Key::PageDown),
+    ("insert", Key::Insert),
+    ("delete", Key::Del),
+];
+
+pub fn parse_key(name: &str) -> Result<Key, String> {
+    if let Some((_, key)) = KEY_NAMES.iter().find(|(key_name, _)| *key_name == name) {
+        return Ok(key.clone());
+    }
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(Key::Char(c)),
+        _ => Err(format!("Unknown key '{name}'")),
+    }
+}
+
+pub fn key_name(key: &Key) -> String {
+    match KEY_NAMES.iter().find(|(_, named)| named == key) {
+        Some((name, _)) => (*name).to_owned(),
+        None => match key {
+            Key::Char(c) => c.to_string(),
+            other => format!("{other:?}"),
+        },
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KeyBindings {
+    bindings: Vec<(Action, Vec<Key>)>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            bindings: Action::ALL
+                .into_iter()
+                .map(|action| (action, action.default_keys()))
+                .collect(),
+        }
+    }
+}
+
+impl KeyBindings {
+    /// The bindings of the `keys` file in the config directory, the defaults if there is none.
+    pub fn load() -> Result<KeyBindings, String> {
+        match config::config_file(KEYS_FILE) {
+            Some(file_path) if file_path.exists() => KeyBindings::read(&file_path),
+            _ => Ok(KeyBindings::default()),
+        }
+    }
+
+    pub fn read(file_path: &Path) -> Result<KeyBindings, String> {
+        let content = fs::read_to_string(file_path)
+            .map_err(|err| format!("Could not read {}: {err}", file_path.display()))?;
+        KeyBindings::parse(&content).map_err(|err| format!("{err} in {}", file_path.display()))
+    }
+
+    pub fn parse(content: &str) -> Result<KeyBindings, String> {
+        let mut bindings = KeyBindings::default();
+        let mut listed = vec![];
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (action, keys) = line
+                .split_once('=')
+                .ok_or_else(|| format!("Expected action = keys but got '{line}'"))?;
+            let action: Action = action.trim().parse()?;
+            let keys: Vec<Key> = keys
+                .split_whitespace()
+                .map(parse_key)
+                .collect::<Result<_, _>>()?;
+            for (other, defaults) in &mut bindings.bindings {
+                if *other != action && !listed.contains(other) {
+                    defaults.retain(|key| !keys.contains(key));
+                }
+            }
+            bindings.bind(action, keys);
+            listed.push(action);
+        }
+
+        for (action, keys) in &bindings.bindings {
+            for key in keys {
+                if let Some(other) = bindings.action(key).filter(|other| other != action) {
+                    return Err(format!(
+                        "Key '{}' is bound to both {other} and {action}",
+                        key_name(key)
+                    ));
+                }
+            }
+        }
+        Ok(bindings)
+    }
+
+    /// Replaces the keys of `action`.
+    pub fn bind(&mut self, action: Action, keys: Vec<Key>) {
+        if let Some(binding) = self.bindings.iter_mut().find(|(bound, _)| *bound == action) {
+            binding.1 = keys;
+        }
+    }
+
+    pub fn action(&self, key: &Key) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(_, keys)| keys.contains(key))
+            .map(|&(action, _)| action)
+    }
+
+    pub fn keys(&self, action: Action) -> &[Key] {
+        self.bindings
+            .iter()
+            .find(|(bound, _)| *bound == action)
+            .map_or(&[], |(_, keys)| keys.as_slice())
+    }
+
+    /// The keys of `action` as listed in the help, e.g. `h, a, left`.
+    pub fn describe(&self, action: Action) -> String {
+        let names: Vec<String> = self.keys(action).iter().map(key_name).collect();
+        names.join(", ")
+    }
+}
+
+impl fmt::Display for KeyBindings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (action, keys) in &self.bindings {
+            let names: Vec<String> = keys.iter().map(key_name).collect();
+            writeln!(f, "{action} = {}", names.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_keeps_the_defaults_of_unlisted_actions() {
+        let bindings =
+            KeyBindings::parse("# comment\n\nquit = q escape # and a comment\n").unwrap();
+        assert_eq!(bindings.keys(Action::Quit), [Key::Char('q'), Key::Escape]);
+        assert_eq!(bindings.keys(Action::Back), [Key::Char('p')]);
+        assert_eq!(bindings.keys(Action::Left), Action::Left.default_keys());
+        assert_eq!(bindings.action(&Key::Escape), Some(Action::Quit));
+    }
+
+    #[test]
+    fn parse_binds_hash_and_named_keys() {
+        let bindings = KeyBindings::parse("help = hash ? pageup\nplace = =").unwrap();
+        assert_eq!(
+            bindings.keys(Action::Help),
+            [Key::Char('#'), Key::Char('?'), Key::PageUp]
+        );
+        assert_eq!(bindings.keys(Action::Place), [Key::Char('=')]);
+        assert_eq!(bindings.describe(Action::Help), "hash, ?, pageup");
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let bindings = KeyBindings::parse("help = hash\nflip = tab f x").unwrap();
+        let read = KeyBindings::parse(&bindings.to_string()).unwrap();
+        for action in Action::ALL {
+            assert_eq!(read.keys(action), bindings.keys(action));
+        }
+    }
+
+    #[test]
+    fn parse_rejects_conflicts_and_unknown_names() {
+        for (content, error) in [
+            (
+                "quit = x\nhelp = x",
+                "Key 'x' is bound to both help and quit",
+            ),
+            ("jump = x", "Unknown action 'jump'"),
+            ("quit = ctrl-q", "Unknown key 'ctrl-q'"),
+            ("quit q", "Expected action = keys but got 'quit q'"),
+        ] {
+            assert_eq!(KeyBindings::parse(content).unwrap_err(), error);
+        }
+    }
+}
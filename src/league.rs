@@ -0,0 +1,283 @@
+#![allow(dead_code)]
+/*
+    The league is a directory of frozen model files. During training the learner is
+    snapshotted into it every few episodes and every episode it plays against one opponent
+    sampled from the frozen models and the scripted bots:
+
+        <dir>/member-00000001.model
+        <dir>/member-00000002.model
+
+    Members are numbered in the order they were frozen, a new member takes the number after
+    the largest one in the directory so members of earlier runs are never overwritten. The
+    directory is read again when training starts so the pool carries over between runs.
+*/
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use rand::Rng;
+
+use crate::{
+    ai::{AdvancedPlayer, GreedyPlayer, RandomPlayer},
+    borderpatrol::BorderPatrol,
+    ml::BorderPatrolAgent,
+    model::{self, ModelError},
+    terminal_borderpatrol::display::Player,
+};
+
+const PREFIX: &str = "member-";
+
+/// Relative chances of each kind of opponent. The frozen weight is shared by all frozen
+/// models and ignored while there are none.
+#[derive(Debug, Clone)]
+pub struct LeagueWeights {
+    pub frozen: f64,
+    pub random: f64,
+    pub greedy: f64,
+    pub advanced: f64,
+}
+
+impl Default for LeagueWeights {
+    fn default() -> Self {
+        LeagueWeights {
+            frozen: 4.0,
+            random: 1.0,
+            greedy: 2.0,
+            advanced: 3.0,
+        }
+    }
+}
+
+impl LeagueWeights {
+    /// Parses weights like `frozen=4,random=1,greedy=2,advanced=3`, missing kinds get 0.
+    pub fn parse(spec: &str) -> Result<LeagueWeights, String> {
+        let mut weights = LeagueWeights {
+            frozen: 0.0,
+            random: 0.0,
+            greedy: 0.0,
+            advanced: 0.0,
+        };
+        for entry in spec.split(',').filter(|e| !e.trim().is_empty()) {
+            let (name, value) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Expected name=weight but got '{entry}'"))?;
+            let value: f64 = value
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid weight '{}'", value.trim()))?;
+            match name.trim() {
+                "frozen" => weights.frozen = value,
+                "random" => weights.random = value,
+                "greedy" => weights.greedy = value,
+                "advanced" => weights.advanced = value,
+                other => return Err(format!("Unknown opponent '{other}'")),
+            }
+        }
+        Ok(weights)
+    }
+}
+
+impl fmt::Display for LeagueWeights {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "frozen={},random={},greedy={},advanced={}",
+            self.frozen, self.random, self.greedy, self.advanced
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Opponent {
+    Frozen(usize),
+    Random,
+    Greedy,
+    Advanced,
+}
+
+pub struct League {
+    pub dir: PathBuf,
+    pub weights: LeagueWeights,
+    /// Snapshot the learner into the league every this many episodes, 0 never does.
+    pub freeze_every: usize,
+    /// Maximum number of frozen models, the oldest ones are removed first.
+    pub max_frozen: usize,
+    frozen: Vec<(PathBuf, BorderPatrolAgent)>,
+    advanced: AdvancedPlayer,
+}
+
+impl League {
+    pub fn load(dir: &str, weights: LeagueWeights) -> Result<League, ModelError> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir).map_err(|err| ModelError::Io(dir.display().to_string(), err))?;
+
+        let entries =
+            fs::read_dir(&dir).map_err(|err| ModelError::Io(dir.display().to_string(), err))?;
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+                name.starts_with(PREFIX) && name.ends_with(".model")
+            })
+            .collect();
+        paths.sort();
+
+        let mut frozen = Vec::with_capacity(paths.len());
+        for path in paths {
+            let agent = load_member(&path)?;
+            frozen.push((path, agent));
+        }
+
+        Ok(League {
+            dir,
+            weights,
+            freeze_every: 1000,
+            max_frozen: 10,
+            frozen,
+            advanced: AdvancedPlayer::new(),
+        })
+    }
+
+    pub fn frozen_count(&self) -> usize {
+        self.frozen.len()
+    }
+
+    pub fn sample(&self, rng: &mut impl Rng) -> Opponent {
+        let frozen = if self.frozen.is_empty() {
+            0.0
+        } else {
+            self.weights.frozen.max(0.0)
+        };
+        let kinds = [
+            (frozen, Opponent::Frozen(0)),
+            (self.weights.random.max(0.0), Opponent::Random),
+            (self.weights.greedy.max(0.0), Opponent::Greedy),
+            (self.weights.advanced.max(0.0), Opponent::Advanced),
+        ];
+        let total: f64 = kinds.iter().map(|(weight, _)| weight).sum();
+        if total <= 0.0 {
+            return Opponent::Random;
+        }
+
+        let mut x = rng.gen_range(0.0..total);
+        for (weight, opponent) in kinds {
+            if x < weight {
+                return match opponent {
+                    Opponent::Frozen(_) => Opponent::Frozen(rng.gen_range(0..self.frozen.len())),
+                    opponent => opponent,
+                };
+            }
+            x -= weight;
+        }
+        Opponent::Random
+    }
+
+    /// Lets `opponent` pick a line. Randomness of the scripted bots is drawn from `rng` so
+    /// a resumed run plays the same games.
+    pub fn choose_line(
+        &self,
+        opponent: Opponent,
+        border_patrol: &BorderPatrol,
+        rng: &mut impl Rng,
+    ) -> usize {
+        match opponent {
+            Opponent::Frozen(i) => self.frozen[i].1.choose_line(border_patrol),
+            Opponent::Random => RandomPlayer::new(rng.gen()).choose_line(border_patrol),
+            Opponent::Greedy => GreedyPlayer::new(rng.gen()).choose_line(border_patrol),
+            Opponent::Advanced => self.advanced.choose_line(border_patrol),
+        }
+    }
+
+    pub fn name(&self, opponent: Opponent) -> String {
+        match opponent {
+            Opponent::Frozen(i) => self.frozen[i]
+                .0
+                .file_stem()
+                .and_then(|n| n.to_str())
+                .unwrap_or("frozen")
+                .to_owned(),
+            Opponent::Random => "random".to_owned(),
+            Opponent::Greedy => "greedy".to_owned(),
+            Opponent::Advanced => "advanced".to_owned(),
+        }
+    }
+
+    pub fn after_episode(
+        &mut self,
+        agent: &BorderPatrolAgent,
+        episode: usize,
+    ) -> Result<(), ModelError> {
+        if self.freeze_every == 0 || !episode.is_multiple_of(self.freeze_every) {
+            return Ok(());
+        }
+        self.freeze(agent)
+    }
+
+    /// Adds a frozen copy of `agent` to the league.
+    pub fn freeze(&mut self, agent: &BorderPatrolAgent) -> Result<(), ModelError> {
+        let mut index = self.last_index()? + 1;
+        let mut path = self.dir.join(format!("{PREFIX}{index:08}.model"));
+        while path.exists() {
+            index += 1;
+            path = self.dir.join(format!("{PREFIX}{index:08}.model"));
+        }
+        model::write(&path.display().to_string(), &agent.metadata(), &agent.nn)?;
+        let member = BorderPatrolAgent::from_model(agent.metadata(), agent.nn.clone())?;
+        self.frozen.push((path, member));
+
+        while self.frozen.len() > self.max_frozen.max(1) {
+            let (path, _) = self.frozen.remove(0);
+            fs::remove_file(&path)
+                .map_err(|err| ModelError::Io(path.display().to_string(), err))?;
+        }
+        Ok(())
+    }
+
+    /// The largest member number in the league directory, 0 if there are no members.
+    fn last_index(&self) -> Result<u64, ModelError> {
+        let entries = fs::read_dir(&self.dir)
+            .map_err(|err| ModelError::Io(self.dir.display().to_string(), err))?;
+        Ok(entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                name.strip_prefix(PREFIX)?
+                    .strip_suffix(".model")?
+                    .parse()
+                    .ok()
+            })
+            .max()
+            .unwrap_or(0))
+    }
+}
+
+fn load_member(path: &Path) -> Result<BorderPatrolAgent, ModelError> {
+    let (metadata, network) = model::read(&path.display().to_string())?;
+    BorderPatrolAgent::from_model(metadata, network)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn freeze_never_overwrites_members() {
+        let dir = std::env::temp_dir().join(format!("borderpatrol-{}-league", std::process::id()));
+        let agent = BorderPatrolAgent::new(&[100, 4, 200]);
+
+        let mut league = League::load(dir.to_str().unwrap(), LeagueWeights::default()).unwrap();
+        league.freeze(&agent).unwrap();
+        let mut league = League::load(dir.to_str().unwrap(), LeagueWeights::default()).unwrap();
+        league.freeze(&agent).unwrap();
+
+        let names: Vec<String> = league
+            .frozen
+            .iter()
+            .map(|(path, _)| path.file_name().unwrap().to_str().unwrap().to_owned())
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(names, ["member-00000001.model", "member-00000002.model"]);
+    }
+}
@@ -1,41 +1,847 @@
-#![allow(unused_imports)]
-use ai::AdvancedPlayer;
-use borderpatrol::BorderPatrol;
-use engine::Engine;
-use ml::{BorderPatrolAgent, HyperParameters};
-use terminal_borderpatrol::display::TerminalBorderPatrol;
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    env, fs,
+    path::Path,
+    process,
+    str::FromStr,
+    sync::{
+        atomic::{self, AtomicUsize},
+        Mutex,
+    },
+    thread,
+};
+
+use alphazero::{AlphaZeroAgent, AlphaZeroParameters, SelfPlayObserver, SelfPlayState};
+use arena::MatchResult;
+use book::{BookPlayer, OpeningBook};
+use checkpoint::Checkpointer;
+use clock::TimeControl;
+use dashboard::TrainingDashboard;
+use engine::{Engine, Scene};
+use keys::KeyBindings;
+use league::{League, LeagueWeights};
+use metrics::{MetricsLogger, SelfPlayMetrics};
+use ml::{BorderPatrolAgent, HyperParameters, TrainingObserver, TrainingState};
+use network::{Activation, Optimizer, TrainingConfig};
+use reward::RewardConfig;
+use scenes::{MainMenu, NewPlayer, Session, Settings};
+use schedule::{Exploration, Schedule};
+use selfplay::SelfPlayPool;
+use sweep::{SweepResult, SweepSpec};
+use terminal_borderpatrol::display::Player;
+use theme::{Theme, THEMES};
 
 mod ai;
+mod alphazero;
+mod arena;
+mod book;
 mod borderpatrol;
+mod checkpoint;
+mod clock;
+mod config;
+mod dashboard;
 mod engine;
+mod keys;
+mod league;
+mod metrics;
 mod ml;
+mod model;
+mod network;
+mod onnx;
+mod records;
+mod reward;
+mod rng;
+mod scenes;
+mod schedule;
+mod screen;
+mod selfplay;
+mod solver;
+mod sweep;
 mod terminal_borderpatrol;
+mod theme;
+
+const USAGE: &str = "Usage:
+    borderpatrol-terminal                     play against another human
+    borderpatrol-terminal play <model>        play against a trained model, --explain shows its q values
+    borderpatrol-terminal play random         play against random moves, --seed <n> replays a game
+    borderpatrol-terminal train <model>       train a new model and save it
+    borderpatrol-terminal match <a> <b>       play two models or bots against each other
+    borderpatrol-terminal model info <model>  print the metadata of a model
+    borderpatrol-terminal model export <model> <onnx>  write the network of a model as onnx
+    borderpatrol-terminal model import <onnx> <model>  turn an exported onnx file into a model
+    borderpatrol-terminal solve <positions>   write solver labelled endgame positions
+    borderpatrol-terminal sweep <spec>        train and rank the configurations of a sweep spec
+    borderpatrol-terminal book build <book>   build an opening book from self-play and recorded games
+    borderpatrol-terminal book info <book>    print a summary and the book moves of a position
+    borderpatrol-terminal book merge <out> <book>...  add up the statistics of several books
+    borderpatrol-terminal keys                print the active key bindings in the format of the keys file
+    borderpatrol-terminal theme [<name>]      list the colour themes or choose one
+
+Keys are read from the keys file in $XDG_CONFIG_HOME/borderpatrol or ~/.config/borderpatrol,
+press ? in a game for the active bindings. --theme <name> on a game chooses the colour theme
+and keeps it for later games: classic, color, colorblind, high-contrast or mono for terminals
+without Unicode box drawing.
+
+Players in play and match are model files or one of random, greedy, advanced and solver.
+--book <book> lets them play the best book move for the first --book-moves <n> moves
+(the depth of the book). --time <control> plays games on a clock like in match.
+
+Match options:
+    --games <n>                games to play, the players take turns starting (20)
+    --time <control>           play on a clock, e.g. 5m, 5m+3s or 10m/30s for a byoyomi
+                               period, a player who runs out of time loses
+    --seed <n>                 seed for the random and greedy players
+    --record <dir>             write every game to a record file in dir
+
+Solve options:
+    --games <n>                endgames to play out and label (100)
+    --max-lines <n>            lines left when an endgame starts (14)
+    --seed <n>                 seed for the games leading up to the endgames
+
+Book options:
+    --player <player>          player of the self-play games (advanced)
+    --games <n>                self-play games (1000)
+    --depth <n>                moves from the start that are recorded (8)
+    --explore <p>              chance of a random safe move within the depth (0.3)
+    --records <dir>            also add every game record in dir
+    --moves <lines>            comma separated lines leading to the position info shows
+    --seed <n>                 seed for the exploration and the players
+
+Sweep options:
+    --out <dir>                directory for the models and results.txt (sweep)
+    --jobs <n>                 configurations trained at the same time (1)
+    --baseline <player>        player every model is evaluated against (advanced)
+    --games <n>                evaluation games against the baseline (20)
+    --seed <n>                 seed of random sampling, configuration k trains with seed + k - 1
+
+Training options:
+    --agent <dqn|alphazero>    kind of agent to train (dqn)
+    --episodes <n>             episodes to train (20000), self-play games for alphazero (200)
+    --hidden <sizes>           comma separated sizes of the hidden layers (300,300)
+    --discount <x>             discount of future rewards (0.95)
+    --eps <x>                  initial exploration rate (0.4)
+    --eps-decay <x>            factor applied to the exploration rate after every episode (0.999)
+    --eps-schedule <spec>      schedule of the exploration rate, replaces --eps-decay
+    --exploration <name>       epsilon-greedy or boltzmann, which uses epsilon as temperature
+    --rate-schedule <spec>     schedule of the learning rate (constant)
+    --simulations <n>          alphazero tree search simulations per move (50)
+    --hidden-activation <name> sigmoid, tanh, relu, leaky-relu or linear (sigmoid)
+    --output-activation <name> activation of the q value outputs (sigmoid)
+    --optimizer <sgd|adam>     weight update rule (sgd)
+    --learning-rate <x>        step size (0.3 for sgd, 0.001 for adam)
+    --momentum <x>             sgd momentum (0)
+    --batch-size <n>           examples averaged into one update (1)
+    --l2 <x>                   weight decay (0)
+    --clip <x>                 clip the gradient norm of every update to x
+    --seed <n>                 seed for network initialisation, exploration and evaluation
+    --supervised <dir>         fit to the game records and positions in dir before self-play
+    --supervised-epochs <n>    passes over the supervised data (10)
+    --checkpoint-dir <dir>     save checkpoints to this directory
+    --checkpoint-every <n>     episodes between checkpoints (1000)
+    --keep-last <k>            number of recent checkpoints to keep (3)
+    --eval-games <n>           games against the random player per checkpoint (20)
+    --replay <capacity>        replay buffer size, 0 disables replay (0)
+    --rewards <spec>           reward shaping, entries left out keep their default
+                               (per-box=0.05,win=1,draw=0,loss=0,margin=0,chain-control=0,opponent-share=0.1)
+    --save-replay              store the replay buffer with every checkpoint
+    --resume <checkpoint>      continue the run stored in a checkpoint
+    --metrics <csv>            append per episode metrics to a csv file
+    --metrics-window <n>       episodes the rolling averages are taken over (100)
+    --eval-every <n>           episodes between win rate evaluations, 0 disables them (500)
+    --print-every <n>          episodes between summary lines, 0 disables them (100)
+    --dashboard                show a live dashboard instead of summary lines
+    --league <dir>             train against frozen models in dir and scripted bots
+    --league-weights <spec>    opponent weights (frozen=4,random=1,greedy=2,advanced=3)
+    --league-every <n>         episodes between freezing the learner into the league (1000)
+    --league-size <n>          maximum number of frozen models kept (10)
+    --workers <n>              play self-play games on n threads, runs depend on n (1)
+    --refresh-every <n>        updates between sending new weights to the workers (100)
+
+Schedules:
+    constant, exponential:<decay>[:<min>], linear:<end>:<episodes>, cosine:<end>:<episodes>
+    or step:<factor>:<every>[:<min>]";
 
 fn main() {
-    let game = TerminalBorderPatrol::new();
-    let mut engine = Engine::new(Box::new(game));
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
 
-    engine.start();
+    match args.as_slice() {
+        options if options.first().is_none_or(|arg| arg.starts_with("--")) => {
+            play(None, &Options::parse(options))
+        }
+        ["play", opponent, options @ ..] => play(Some(opponent), &Options::parse(options)),
+        ["train", save_at, options @ ..] => train_model(save_at, &Options::parse(options)),
+        ["match", first, second, options @ ..] => {
+            play_match(first, second, &Options::parse(options))
+        }
+        ["model", "info", model_at] => model_info(model_at),
+        ["model", "export", model_at, onnx_at] => export_model(model_at, onnx_at),
+        ["model", "import", onnx_at, model_at] => import_model(onnx_at, model_at),
+        ["solve", save_at, options @ ..] => solve_endgames(save_at, &Options::parse(options)),
+        ["sweep", spec_at, options @ ..] => run_sweep(spec_at, &Options::parse(options)),
+        ["keys"] => print!(
+            "{}",
+            KeyBindings::load().unwrap_or_else(|err| exit_with(err))
+        ),
+        ["theme"] => list_themes(),
+        ["theme", name] => {
+            let theme: Theme = name.parse().unwrap_or_else(|err| exit_with(err));
+            theme.save().unwrap_or_else(|err| exit_with(err));
+            println!("Games use the {theme} theme from now on.");
+        }
+        ["book", "build", save_at, options @ ..] => build_book(save_at, &Options::parse(options)),
+        ["book", "info", book_at, options @ ..] => book_info(book_at, &Options::parse(options)),
+        ["book", "merge", save_at, books @ ..] if !books.is_empty() => merge_books(save_at, books),
+        _ => {
+            eprintln!("{USAGE}");
+            process::exit(2);
+        }
+    }
 }
 
-fn train_model(save_at: &str) {
-    let mut agent = BorderPatrolAgent::new_with(
-        &[100, 300, 300, 200],
+fn train_model(save_at: &str, options: &Options) {
+    match options.value("agent") {
+        None | Some(model::AGENT_DQN) => {}
+        Some(model::AGENT_ALPHAZERO) => return train_alphazero(save_at, options),
+        Some(other) => exit_with(format!("Unknown agent '{other}'")),
+    }
+
+    let (mut agent, mut training) = match options.value("resume") {
+        Some(checkpoint_at) => {
+            checkpoint::resume(checkpoint_at).unwrap_or_else(|err| exit_with(err))
+        }
+        None => {
+            let seed = options.get("seed", rng::random_seed());
+            println!("Seed {seed}");
+            let agent = new_agent(options, seed).unwrap_or_else(|err| exit_with(err));
+            let training = TrainingState::new(
+                rng::seeded(seed, rng::EXPLORATION),
+                agent.hyper_parms.replay_capacity,
+            );
+            (agent, training)
+        }
+    };
+
+    if let Some(dir) = options.value("league") {
+        let weights = match options.value("league-weights") {
+            Some(spec) => LeagueWeights::parse(spec).unwrap_or_else(|err| exit_with(err)),
+            None => LeagueWeights::default(),
+        };
+        let mut league = League::load(dir, weights).unwrap_or_else(|err| exit_with(err));
+        league.freeze_every = options.get("league-every", 1000);
+        league.max_frozen = options.get("league-size", 10);
+        println!(
+            "League of {} frozen models and scripted bots",
+            league.frozen_count()
+        );
+        training.league = Some(league);
+    }
+
+    let mut checkpointer = options.value("checkpoint-dir").map(|dir| Checkpointer {
+        every: options.get("checkpoint-every", 1000),
+        keep_last: options.get("keep-last", 3),
+        eval_games: options.get("eval-games", 20),
+        save_replay: options.flag("save-replay"),
+        ..Checkpointer::new(dir)
+    });
+
+    let mut metrics = MetricsLogger::new();
+    metrics.window = options.get("metrics-window", 100);
+    metrics.eval_every = options.get("eval-every", 500);
+    metrics.eval_games = options.get("eval-games", 20);
+    metrics.print_every = options.get("print-every", 100);
+    if let Some(csv_at) = options.value("metrics") {
+        metrics = metrics
+            .with_csv(csv_at)
+            .unwrap_or_else(|err| exit_with(err));
+    }
+
+    let workers = options.get("workers", 1);
+    if workers > 1 && training.league.is_some() {
+        exit_with("--workers can not be combined with --league");
+    }
+
+    if options.flag("dashboard") {
+        if workers > 1 {
+            exit_with("--workers can not be combined with --dashboard");
+        }
+        let dashboard = TrainingDashboard::new(agent, training, metrics, checkpointer, save_at);
+        start_engine(
+            Box::new(dashboard),
+            Theme::load().unwrap_or_else(|err| exit_with(err)),
+        );
+        return;
+    }
+
+    let mut observers: Vec<&mut dyn TrainingObserver> = vec![&mut metrics];
+    if let Some(checkpointer) = &mut checkpointer {
+        observers.push(checkpointer);
+    }
+    let trained = if workers > 1 {
+        let pool = SelfPlayPool {
+            refresh_every: options.get("refresh-every", 100),
+            ..SelfPlayPool::new(workers)
+        };
+        pool.train(&mut agent, &mut training, &mut observers)
+    } else {
+        agent.train_from(&mut training, &mut observers)
+    };
+    if let Err(err) = trained.and_then(|_| agent.save(save_at)) {
+        exit_with(err);
+    }
+}
+
+/// A fresh agent configured by the training options, fitted to `--supervised` data if given.
+fn new_agent(options: &Options, seed: u64) -> Result<BorderPatrolAgent, String> {
+    let hidden: Vec<u32> = match options.value("hidden") {
+        Some(spec) => spec
+            .split(',')
+            .map(|size| size.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("Invalid value '{spec}' for --hidden"))?,
+        None => vec![300, 300],
+    };
+    let shape: Vec<u32> = [100].into_iter().chain(hidden).chain([200]).collect();
+
+    let mut agent = BorderPatrolAgent::new_with_activations(
+        &shape,
+        options.try_get("hidden-activation", Activation::Sigmoid)?,
+        options.try_get("output-activation", Activation::Sigmoid)?,
         HyperParameters {
-            discount: 0.95,
-            eps: 0.4,
-            eps_decay: 0.999,
-            episodes: 20000,
+            discount: options.try_get("discount", 0.95)?,
+            eps: options.try_get("eps", 0.4)?,
+            eps_schedule: match options.value("eps-schedule") {
+                Some(spec) => spec.parse()?,
+                None => Schedule::Exponential {
+                    decay: options.try_get("eps-decay", 0.999)?,
+                    min: 0.0,
+                },
+            },
+            exploration: match options.value("exploration") {
+                Some(name) => name.parse()?,
+                None => Exploration::EpsilonGreedy,
+            },
+            rate_schedule: match options.value("rate-schedule") {
+                Some(spec) => spec.parse()?,
+                None => Schedule::Constant,
+            },
+            episodes: options.try_get("episodes", 20000)?,
+            replay_capacity: options.try_get("replay", 0)?,
+            seed,
+            rewards: match options.value("rewards") {
+                Some(spec) => spec.parse()?,
+                None => RewardConfig::default(),
+            },
+            ..Default::default()
         },
     );
-    agent.train();
-    agent.save(save_at);
+    agent.nn.config = training_config(options)?;
+    agent.hyper_parms.learning_rate = agent.nn.config.rate;
+    if let Some(dir) = options.value("supervised") {
+        let epochs = options.try_get("supervised-epochs", 10)?;
+        train_supervised(&mut agent, dir, epochs, seed)?;
+    }
+    Ok(agent)
+}
+
+fn train_supervised(
+    agent: &mut BorderPatrolAgent,
+    dir: &str,
+    epochs: u32,
+    seed: u64,
+) -> Result<(), String> {
+    let (games, positions) = records::load_dir(dir).map_err(|err| err.to_string())?;
+    println!(
+        "Supervised training on {} games and {} positions",
+        games.len(),
+        positions.len()
+    );
+    let mut rng = rng::seeded(seed, rng::SUPERVISED);
+    for epoch in 1..=epochs {
+        let error = agent.fit_supervised(&games, &positions, &mut rng);
+        println!("Epoch {epoch}: error {error:.5}");
+    }
+    Ok(())
+}
+
+fn train_alphazero(save_at: &str, options: &Options) {
+    let (mut agent, mut training) = match options.value("resume") {
+        Some(checkpoint_at) => {
+            checkpoint::resume_self_play(checkpoint_at).unwrap_or_else(|err| exit_with(err))
+        }
+        None => {
+            let seed = options.get("seed", rng::random_seed());
+            println!("Seed {seed}");
+            let mut agent = AlphaZeroAgent::new_with_activations(
+                &[201, 128, 128, 201],
+                options.get("hidden-activation", Activation::Sigmoid),
+                AlphaZeroParameters {
+                    games: options.get("episodes", 200),
+                    simulations: options.get("simulations", 50),
+                    seed,
+                    ..Default::default()
+                },
+            )
+            .unwrap_or_else(|err| exit_with(err));
+            agent.nn.config = training_config(options).unwrap_or_else(|err| exit_with(err));
+            (
+                agent,
+                SelfPlayState::new(rng::seeded(seed, rng::EXPLORATION)),
+            )
+        }
+    };
+
+    let mut checkpointer = options.value("checkpoint-dir").map(|dir| Checkpointer {
+        every: options.get("checkpoint-every", 1000),
+        keep_last: options.get("keep-last", 3),
+        ..Checkpointer::new(dir)
+    });
+    let mut metrics = SelfPlayMetrics::new();
+    metrics.print_every = options.get("print-every", 1);
+    if let Some(csv_at) = options.value("metrics") {
+        metrics = metrics
+            .with_csv(csv_at)
+            .unwrap_or_else(|err| exit_with(err));
+    }
+
+    let mut observers: Vec<&mut dyn SelfPlayObserver> = vec![&mut metrics];
+    if let Some(checkpointer) = &mut checkpointer {
+        observers.push(checkpointer);
+    }
+    if let Err(err) = agent
+        .train_from(&mut training, &mut observers)
+        .and_then(|_| agent.save(save_at))
+    {
+        exit_with(err);
+    }
+}
+
+fn training_config(options: &Options) -> Result<TrainingConfig, String> {
+    let mut optimizer = options.try_get("optimizer", Optimizer::Sgd { momentum: 0.0 })?;
+    let default_rate = match &mut optimizer {
+        Optimizer::Sgd { momentum } => {
+            *momentum = options.try_get("momentum", 0.0)?;
+            0.3
+        }
+        Optimizer::Adam { .. } => 0.001,
+    };
+    let clip = options
+        .value("clip")
+        .map(|_| options.try_get("clip", 0.0))
+        .transpose()?;
+    if clip.is_some_and(|clip: f64| clip.is_nan() || clip <= 0.0) {
+        return Err("--clip has to be positive".to_owned());
+    }
+    Ok(TrainingConfig {
+        optimizer,
+        rate: options.try_get("learning-rate", default_rate)?,
+        batch_size: options.try_get("batch-size", 1)?,
+        l2: options.try_get("l2", 0.0)?,
+        clip,
+    })
+}
+
+fn run_sweep(spec_at: &str, options: &Options) {
+    let spec = SweepSpec::read(spec_at).unwrap_or_else(|err| exit_with(err));
+    let seed = options.get("seed", rng::random_seed());
+    println!("Seed {seed}");
+    let out_dir = options.value("out").unwrap_or("sweep");
+    fs::create_dir_all(out_dir)
+        .unwrap_or_else(|err| exit_with(format!("Could not create {out_dir}: {err}")));
+    let baseline = options.value("baseline").unwrap_or("advanced");
+    arena::load_player(baseline, seed).unwrap_or_else(|err| exit_with(err));
+    let games = options.get("games", 20);
+    let jobs = options.get("jobs", 1_usize).max(1);
+
+    let configurations = spec.configurations(&mut rng::seeded(seed, rng::SWEEP));
+    println!("{} configurations, {jobs} at a time", configurations.len());
+
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                let id = next.fetch_add(1, atomic::Ordering::Relaxed);
+                let Some(configuration) = configurations.get(id) else {
+                    break;
+                };
+                let model_at = format!("{out_dir}/config-{:03}.model", id + 1);
+                // Every configuration gets a seed of its own, the first one trains with the
+                // seed printed
+                let run_seed = seed.wrapping_add(id as u64);
+                let count = configurations.len();
+                match run_configuration(configuration, run_seed, &model_at, baseline, games) {
+                    Ok(result) => {
+                        println!("Configuration {}/{count}: {result}", id + 1);
+                        results.lock().unwrap().push(SweepResult {
+                            id: id + 1,
+                            configuration: configuration.clone(),
+                            model: model_at,
+                            result,
+                        });
+                    }
+                    Err(err) => eprintln!("Configuration {}/{count} failed: {err}", id + 1),
+                }
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    let failed = configurations.len() - results.len();
+    let table = sweep::ranking(&mut results);
+    let table_at = format!("{out_dir}/results.txt");
+    fs::write(&table_at, &table)
+        .unwrap_or_else(|err| exit_with(format!("Could not write {table_at}: {err}")));
+    println!("\n{table}");
+    if failed > 0 {
+        exit_with(format!(
+            "{failed} of {} configurations failed",
+            configurations.len()
+        ));
+    }
+}
+
+/// Trains one configuration of a sweep quietly, saves it and plays it against the baseline.
+/// `seed` is used unless the configuration sets one.
+fn run_configuration(
+    configuration: &sweep::Configuration,
+    seed: u64,
+    model_at: &str,
+    baseline: &str,
+    games: usize,
+) -> Result<MatchResult, String> {
+    let args: Vec<String> = configuration
+        .iter()
+        .flat_map(|(key, value)| [format!("--{key}"), value.clone()])
+        .collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let options =
+        Options::try_parse(&args).map_err(|arg| format!("Unexpected argument '{arg}'"))?;
+    let seed = options.try_get("seed", seed)?;
+
+    let mut agent = new_agent(&options, seed)?;
+    let mut training = TrainingState::new(
+        rng::seeded(seed, rng::EXPLORATION),
+        agent.hyper_parms.replay_capacity,
+    );
+    agent
+        .train_from(&mut training, &mut [])
+        .and_then(|_| agent.save(model_at))
+        .map_err(|err| err.to_string())?;
+    let baseline = arena::load_player(baseline, seed).map_err(|err| err.to_string())?;
+    Ok(arena::play_match(
+        &agent,
+        &*baseline,
+        games,
+        None,
+        |_, _| {},
+    ))
+}
+
+/// Starts at the main menu, games are against `opponent` or between two humans.
+fn play(opponent: Option<&str>, options: &Options) {
+    let settings = Settings {
+        theme: game_theme(options),
+        time: time_control(options),
+        explain: options.flag("explain"),
+    };
+    let opponent = opponent.map(|spec| (spec.to_owned(), new_opponent(spec, options)));
+    start_engine(
+        Box::new(MainMenu::new(Session::new(settings, opponent))),
+        settings.theme,
+    );
+}
+
+/// Sets up player two for every game against `spec`. The first player is loaded right away,
+/// so a broken model is reported before the terminal is taken over, and the book is read once.
+fn new_opponent(spec: &str, options: &Options) -> NewPlayer {
+    let seed = options.get("seed", rng::random_seed());
+    if spec == "random" {
+        println!("Seed {seed}");
+    }
+    let book = options
+        .value("book")
+        .map(|book_at| OpeningBook::read(Path::new(book_at)).unwrap_or_else(|err| exit_with(err)));
+    let book_moves = book
+        .as_ref()
+        .map(|book| options.get("book-moves", book.depth));
+    let spec = spec.to_owned();
+    let load = move |seed| -> Box<dyn Player> {
+        let player = arena::load_player(&spec, seed).unwrap_or_else(|err| exit_with(err));
+        let (Some(book), Some(max_moves)) = (&book, book_moves) else {
+            return player;
+        };
+        let mut player = BookPlayer::new(book.clone(), player);
+        player.max_moves = max_moves;
+        Box::new(player)
+    };
+    // Every game gets a seed of its own, the first one plays with the seed printed
+    let (first, games) = (RefCell::new(Some(load(seed))), Cell::new(0));
+    Box::new(move || {
+        let game = games.replace(games.get() + 1);
+        first
+            .take()
+            .unwrap_or_else(|| load(seed.wrapping_add(game)))
+    })
 }
 
-fn play_against(model_at: &str) {
-    let agent = BorderPatrolAgent::from_file(model_at);
+fn play_match(first: &str, second: &str, options: &Options) {
+    let seed = options.get("seed", rng::random_seed());
+    println!("Seed {seed}");
+    let load = |spec| {
+        with_book(
+            arena::load_player(spec, seed).unwrap_or_else(|err| exit_with(err)),
+            options,
+        )
+    };
+    let (first_player, second_player) = (load(first), load(second));
 
-    let terminal_border_patrol = Box::new(TerminalBorderPatrol::with_player_two(Box::new(agent)));
-    let mut engine = engine::Engine::new(terminal_border_patrol);
+    let record_dir = options.value("record");
+    if let Some(dir) = record_dir {
+        fs::create_dir_all(dir)
+            .unwrap_or_else(|err| exit_with(format!("Could not create {dir}: {err}")));
+    }
+
+    let games = options.get("games", 20);
+    let time = time_control(options);
+    let result = arena::play_match(
+        &*first_player,
+        &*second_player,
+        games,
+        time,
+        |game, mut record| {
+            let Some(dir) = record_dir else { return };
+            (record.player_one, record.player_two) = match game % 2 {
+                0 => (first.to_owned(), second.to_owned()),
+                _ => (second.to_owned(), first.to_owned()),
+            };
+            let file_path = Path::new(dir).join(format!("game-{:05}.game", game + 1));
+            if let Err(err) = record.write(&file_path) {
+                exit_with(err);
+            }
+        },
+    );
+    println!("{first} against {second}: {result}");
+}
+
+fn solve_endgames(save_at: &str, options: &Options) {
+    let seed = options.get("seed", rng::random_seed());
+    println!("Seed {seed}");
+    let max_lines = options.get("max-lines", 14);
+    if max_lines > solver::MAX_LINES {
+        exit_with(format!("--max-lines can be at most {}", solver::MAX_LINES));
+    }
+    let mut rng = rng::seeded(seed, rng::SOLVER);
+    let positions = solver::endgame_positions(options.get("games", 100), max_lines, &mut rng);
+    if let Err(err) = records::write_positions(Path::new(save_at), &positions) {
+        exit_with(err);
+    }
+    println!("Wrote {} positions to {save_at}", positions.len());
+}
+
+/// Lets `player` consult the `--book` opening book, if one is given.
+fn with_book(player: Box<dyn Player>, options: &Options) -> Box<dyn Player> {
+    let Some(book_at) = options.value("book") else {
+        return player;
+    };
+    let book = OpeningBook::read(Path::new(book_at)).unwrap_or_else(|err| exit_with(err));
+    let mut player = BookPlayer::new(book, player);
+    player.max_moves = options.get("book-moves", player.max_moves);
+    Box::new(player)
+}
+
+fn build_book(save_at: &str, options: &Options) {
+    let seed = options.get("seed", rng::random_seed());
+    println!("Seed {seed}");
+    let mut book = OpeningBook::new(options.get("depth", book::DEFAULT_DEPTH));
+
+    if let Some(dir) = options.value("records") {
+        let games = book::add_records(&mut book, dir).unwrap_or_else(|err| exit_with(err));
+        println!("Added {games} recorded games");
+    }
+    let games = options.get("games", 1000);
+    let explore: f64 = options.get("explore", 0.3);
+    if !(0.0..=1.0).contains(&explore) {
+        exit_with("--explore has to be between 0 and 1");
+    }
+    let player = arena::load_player(options.value("player").unwrap_or("advanced"), seed)
+        .unwrap_or_else(|err| exit_with(err));
+    book::self_play(
+        &mut book,
+        &*player,
+        games,
+        explore,
+        &mut rng::seeded(seed, rng::BOOK),
+    );
+
+    if let Err(err) = book.write(Path::new(save_at)) {
+        exit_with(err);
+    }
+    println!("Wrote {} positions to {save_at}", book.position_count());
+}
+
+fn book_info(book_at: &str, options: &Options) {
+    let book = OpeningBook::read(Path::new(book_at)).unwrap_or_else(|err| exit_with(err));
+    let moves: Vec<usize> = match options.value("moves") {
+        Some(spec) => spec
+            .split(',')
+            .map(|line| line.trim().parse())
+            .collect::<Result<_, _>>()
+            .unwrap_or_else(|_| exit_with(format!("Invalid value '{spec}' for --moves"))),
+        None => vec![],
+    };
+    let env = records::replay(&moves).unwrap_or_else(|err| exit_with(err));
+
+    println!("{book_at}");
+    println!("Depth:     {}", book.depth);
+    println!("Positions: {}", book.position_count());
+    println!("Games:     {}", book.game_count());
+    println!();
+    let known = book.moves(&env);
+    if known.is_empty() {
+        println!("No book moves after {} moves", moves.len());
+        return;
+    }
+    println!(
+        "{:>4}  {:>6}  {:>5}  {:>6}  move",
+        "line", "games", "score", "margin"
+    );
+    for (line, stats) in known {
+        println!(
+            "{line:>4}  {:>6}  {:>5.3}  {:>+6.1}  {}",
+            stats.games,
+            stats.score(),
+            stats.margin as f64 / stats.games.max(1) as f64,
+            terminal_borderpatrol::display::describe_line(line)
+        );
+    }
+}
+
+fn merge_books(save_at: &str, books: &[&str]) {
+    let mut merged = OpeningBook::new(0);
+    for book_at in books {
+        let book = OpeningBook::read(Path::new(book_at)).unwrap_or_else(|err| exit_with(err));
+        merged.merge(&book);
+    }
+    if let Err(err) = merged.write(Path::new(save_at)) {
+        exit_with(err);
+    }
+    println!("Wrote {} positions to {save_at}", merged.position_count());
+}
+
+/// The time control of `--time`, games without one are not timed.
+fn time_control(options: &Options) -> Option<TimeControl> {
+    let spec = options.value("time")?;
+    Some(spec.parse().unwrap_or_else(|err| exit_with(err)))
+}
+
+/// The theme chosen with `--theme`, which is stored for later games, or the stored one.
+fn game_theme(options: &Options) -> Theme {
+    let Some(name) = options.value("theme") else {
+        return Theme::load().unwrap_or_else(|err| exit_with(err));
+    };
+    let theme: Theme = name.parse().unwrap_or_else(|err| exit_with(err));
+    theme.save().unwrap_or_else(|err| exit_with(err));
+    theme
+}
+
+fn list_themes() {
+    let current = Theme::load().unwrap_or_else(|err| exit_with(err));
+    for theme in THEMES {
+        let marker = if theme.name == current.name { "*" } else { " " };
+        println!("{marker} {theme}");
+    }
+}
+
+/// Runs `scene` with the key bindings of the config directory.
+fn start_engine(scene: Box<dyn Scene>, theme: Theme) {
+    let mut engine = Engine::new(scene);
+    engine.bindings = KeyBindings::load().unwrap_or_else(|err| exit_with(err));
+    engine.theme = theme;
     engine.start();
 }
+
+fn model_info(model_at: &str) {
+    match model::read(model_at) {
+        Ok((metadata, network)) => {
+            let activations: Vec<String> = network
+                .activations()
+                .iter()
+                .map(|a| a.to_string())
+                .collect();
+            println!("{model_at}\n{metadata}");
+            println!("Activations:      {}", activations.join(", "));
+            println!("Optimizer:        {}", network.config);
+        }
+        Err(err) => exit_with(err),
+    }
+}
+
+fn export_model(model_at: &str, onnx_at: &str) {
+    let (metadata, network) = model::read(model_at).unwrap_or_else(|err| exit_with(err));
+    if let Err(err) = onnx::export(onnx_at, &metadata, &network) {
+        exit_with(err);
+    }
+    println!("Wrote {onnx_at}.");
+}
+
+fn import_model(onnx_at: &str, model_at: &str) {
+    let (metadata, network) = onnx::import(onnx_at).unwrap_or_else(|err| exit_with(err));
+    if let Err(err) = model::write(model_at, &metadata, &network) {
+        exit_with(err);
+    }
+    println!("Wrote {model_at}.");
+}
+
+fn exit_with(err: impl std::fmt::Display) -> ! {
+    eprintln!("{err}");
+    process::exit(1);
+}
+
+/// `--name value` and `--flag` arguments following the positional ones.
+struct Options<'a> {
+    named: HashMap<&'a str, Option<&'a str>>,
+}
+
+impl<'a> Options<'a> {
+    fn parse(args: &[&'a str]) -> Options<'a> {
+        Self::try_parse(args)
+            .unwrap_or_else(|arg| exit_with(format!("Unexpected argument '{arg}'\n\n{USAGE}")))
+    }
+
+    /// Like `parse`, but returns the first argument that is not an option instead of exiting.
+    fn try_parse(args: &[&'a str]) -> Result<Options<'a>, &'a str> {
+        let mut named = HashMap::new();
+        let mut args = args.iter().peekable();
+        while let Some(arg) = args.next() {
+            let name = arg.strip_prefix("--").ok_or(*arg)?;
+            let value = args.next_if(|next| !next.starts_with("--")).copied();
+            named.insert(name, value);
+        }
+        Ok(Options { named })
+    }
+
+    fn value(&self, name: &str) -> Option<&'a str> {
+        self.named.get(name).copied().flatten()
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.named.contains_key(name)
+    }
+
+    fn get<T: FromStr>(&self, name: &str, default: T) -> T {
+        self.try_get(name, default)
+            .unwrap_or_else(|err| exit_with(err))
+    }
+
+    /// Like `get`, but returns the message instead of exiting for values that do not parse.
+    fn try_get<T: FromStr>(&self, name: &str, default: T) -> Result<T, String> {
+        match self.value(name) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| format!("Invalid value '{value}' for --{name}")),
+            None => Ok(default),
+        }
+    }
+}
@@ -0,0 +1,260 @@
+#![allow(dead_code)]
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    alphazero::{AlphaZeroAgent, SelfPlayObserver, SelfPlayState, SelfPlayStats},
+    ml::{BorderPatrolAgent, EpisodeStats, TrainingObserver, TrainingState},
+    model::ModelError,
+    rng,
+};
+
+const CSV_HEADER: &str =
+    "episode,epsilon,td_error,q_value,length,opponent,win_rate,avg_td_error,avg_q_value,avg_length";
+const SELF_PLAY_CSV_HEADER: &str = "game,length,margin,loss";
+
+/// Metrics of one episode together with the rolling averages up to it.
+#[derive(Debug, Clone)]
+pub struct MetricsRow {
+    pub stats: EpisodeStats,
+    /// Only set for episodes after which the agent was evaluated.
+    pub win_rate: Option<f64>,
+    pub avg_td_error: f64,
+    pub avg_q_value: f64,
+    pub avg_length: f64,
+}
+
+pub struct MetricsLogger {
+    csv: Option<(String, BufWriter<File>)>,
+    /// Number of episodes the rolling averages are taken over.
+    pub window: usize,
+    /// Evaluate against the random player every this many episodes, 0 disables evaluation.
+    pub eval_every: usize,
+    pub eval_games: usize,
+    /// Print a summary line every this many episodes, 0 disables printing.
+    pub print_every: usize,
+    pub history: Vec<MetricsRow>,
+}
+
+impl MetricsLogger {
+    pub fn new() -> MetricsLogger {
+        MetricsLogger {
+            csv: None,
+            window: 100,
+            eval_every: 500,
+            eval_games: 20,
+            print_every: 100,
+            history: Vec::new(),
+        }
+    }
+
+    /// Appends the metrics to a csv file, writing the header if the file is new.
+    pub fn with_csv(mut self, file_path: &str) -> Result<MetricsLogger, ModelError> {
+        self.csv = Some(open_csv(file_path, CSV_HEADER)?);
+        Ok(self)
+    }
+
+    pub fn last_win_rate(&self) -> Option<f64> {
+        self.history.iter().rev().find_map(|row| row.win_rate)
+    }
+
+    fn rolling(&self, stats: &EpisodeStats, value: impl Fn(&EpisodeStats) -> f64) -> f64 {
+        let previous = self.history.len().min(self.window.max(1) - 1);
+        let sum: f64 = self.history[self.history.len() - previous..]
+            .iter()
+            .map(|row| value(&row.stats))
+            .sum();
+        (sum + value(stats)) / (previous + 1) as f64
+    }
+
+    fn write_csv(&mut self, row: &MetricsRow) -> Result<(), ModelError> {
+        let (file_path, writer) = match &mut self.csv {
+            Some(csv) => csv,
+            None => return Ok(()),
+        };
+        let win_rate = row.win_rate.map(|x| x.to_string()).unwrap_or_default();
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{},{},{}",
+            row.stats.episode,
+            row.stats.epsilon,
+            row.stats.td_error,
+            row.stats.q_value,
+            row.stats.length,
+            row.stats.opponent.as_deref().unwrap_or("self"),
+            win_rate,
+            row.avg_td_error,
+            row.avg_q_value,
+            row.avg_length
+        )
+        .and_then(|_| writer.flush())
+        .map_err(|err| ModelError::Io(file_path.clone(), err))
+    }
+}
+
+fn open_csv(file_path: &str, header: &str) -> Result<(String, BufWriter<File>), ModelError> {
+    let io_err = |err| ModelError::Io(file_path.to_owned(), err);
+    let exists = Path::new(file_path).exists();
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file_path)
+        .map_err(io_err)?;
+    let mut writer = BufWriter::new(file);
+    if !exists {
+        writeln!(writer, "{header}").map_err(io_err)?;
+    }
+    Ok((file_path.to_owned(), writer))
+}
+
+impl TrainingObserver for MetricsLogger {
+    fn after_episode(
+        &mut self,
+        agent: &mut BorderPatrolAgent,
+        _training: &mut TrainingState,
+        stats: &EpisodeStats,
+    ) -> Result<(), ModelError> {
+        let win_rate = if self.eval_every > 0 && stats.episode.is_multiple_of(self.eval_every) {
+            // Same games for every evaluation so the numbers are comparable over the run.
+            Some(agent.evaluate(
+                self.eval_games,
+                &mut rng::seeded(agent.hyper_parms.seed, rng::EVALUATION),
+            ))
+        } else {
+            None
+        };
+
+        let row = MetricsRow {
+            stats: stats.clone(),
+            win_rate,
+            avg_td_error: self.rolling(stats, |s| s.td_error),
+            avg_q_value: self.rolling(stats, |s| s.q_value),
+            avg_length: self.rolling(stats, |s| s.length as f64),
+        };
+        self.write_csv(&row)?;
+
+        if self.print_every > 0 && stats.episode.is_multiple_of(self.print_every) {
+            println!(
+                "Episode {:>6}  eps {:.4}  td error {:.4}  q {:.4}  length {:.1}{}",
+                stats.episode,
+                stats.epsilon,
+                row.avg_td_error,
+                row.avg_q_value,
+                row.avg_length,
+                win_rate
+                    .or(self.last_win_rate())
+                    .map(|x| format!("  win rate {x:.3}"))
+                    .unwrap_or_default()
+            );
+        }
+
+        self.history.push(row);
+        Ok(())
+    }
+}
+
+/// Prints and logs the games of an AlphaZero run, which has no rolling averages or
+/// evaluations.
+pub struct SelfPlayMetrics {
+    csv: Option<(String, BufWriter<File>)>,
+    /// Print the stats every this many games, 0 disables printing.
+    pub print_every: usize,
+}
+
+impl SelfPlayMetrics {
+    pub fn new() -> SelfPlayMetrics {
+        SelfPlayMetrics {
+            csv: None,
+            print_every: 1,
+        }
+    }
+
+    /// Appends the stats to a csv file, writing the header if the file is new.
+    pub fn with_csv(mut self, file_path: &str) -> Result<SelfPlayMetrics, ModelError> {
+        self.csv = Some(open_csv(file_path, SELF_PLAY_CSV_HEADER)?);
+        Ok(self)
+    }
+}
+
+impl SelfPlayObserver for SelfPlayMetrics {
+    fn after_game(
+        &mut self,
+        _agent: &mut AlphaZeroAgent,
+        _training: &mut SelfPlayState,
+        stats: &SelfPlayStats,
+    ) -> Result<(), ModelError> {
+        if let Some((file_path, writer)) = &mut self.csv {
+            writeln!(
+                writer,
+                "{},{},{},{}",
+                stats.game, stats.length, stats.margin, stats.loss
+            )
+            .and_then(|_| writer.flush())
+            .map_err(|err| ModelError::Io(file_path.clone(), err))?;
+        }
+        if self.print_every > 0 && stats.game.is_multiple_of(self.print_every) {
+            println!("{stats}");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn log_episode(logger: &mut MetricsLogger, episode: usize, td_error: f64) {
+        let mut agent = BorderPatrolAgent::new(&[100, 4, 200]);
+        let mut training = TrainingState::new(rng::seeded(0, rng::EXPLORATION), 0);
+        let stats = EpisodeStats {
+            episode,
+            epsilon: 0.5,
+            td_error,
+            q_value: 0.75,
+            length: 120,
+            ..Default::default()
+        };
+        logger
+            .after_episode(&mut agent, &mut training, &stats)
+            .unwrap();
+    }
+
+    #[test]
+    fn csv_rows_are_appended_below_a_single_header() {
+        let path =
+            std::env::temp_dir().join(format!("borderpatrol-{}-metrics.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+        let quiet = |logger: MetricsLogger| MetricsLogger {
+            eval_every: 0,
+            print_every: 0,
+            ..logger
+        };
+
+        let mut logger = quiet(MetricsLogger::new().with_csv(path).unwrap());
+        log_episode(&mut logger, 1, 0.25);
+        log_episode(&mut logger, 2, 0.75);
+        drop(logger);
+        let mut logger = quiet(MetricsLogger::new().with_csv(path).unwrap());
+        log_episode(&mut logger, 3, 0.5);
+        drop(logger);
+
+        let content = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                CSV_HEADER,
+                "1,0.5,0.25,0.75,120,self,,0.25,0.75,120",
+                "2,0.5,0.75,0.75,120,self,,0.5,0.75,120",
+                "3,0.5,0.5,0.75,120,self,,0.5,0.75,120",
+            ]
+        );
+    }
+}
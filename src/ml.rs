@@ -1,241 +1,880 @@
 #![allow(dead_code)]
 
-use std::{
-    fs::File,
-    io::{Read, Write},
-    path::Path,
-};
+use std::{cmp::Ordering, collections::VecDeque};
 
-use nn::{HaltCondition, NN};
-use rand::{seq::IteratorRandom, Rng};
+use rand::{
+    seq::{IteratorRandom, SliceRandom},
+    Rng,
+};
 
 use crate::{
-    borderpatrol::{BorderPatrol, PLAYER_ONE, PLAYER_TWO},
+    borderpatrol::{BorderPatrol, BOARD_SIZE, PLAYER_ONE, PLAYER_TWO},
+    league::League,
+    model::{self, ModelError, ModelMetadata, MODEL_FORMAT_VERSION},
+    network::{masked_argmax, Activation, Network},
+    records::{self, GameRecord, LabelledPosition},
+    reward::RewardConfig,
+    rng::{self, SeededRng},
+    schedule::{Exploration, Schedule},
     terminal_borderpatrol::display::Player,
 };
 
+/// Name of the state encoding implemented by `convert_state`, stored in model files.
+pub const ENCODER: &str = "layout-raw";
+/// What `ENCODER` means, for consumers of exported networks.
+pub const ENCODER_DESCRIPTION: &str = "100 inputs, one per box in row major order, each the raw \
+u16 cell of the board layout: bits 0-3 are the bottom, right, top and left lines drawn by player \
+two, bits 4-7 the same lines drawn by player one, bit 8 marks a box of player two and bit 9 a box \
+of player one. Border lines are always set.";
+/// What the outputs of the network mean, for consumers of exported networks.
+pub const OUTPUT_DESCRIPTION: &str = "200 q values, one per line. Line 2 * (10 * row + column) is \
+the bottom line of that box and the next one its right line. Drawn lines have to be skipped.";
+
+/// Examples built at a time by supervised training, to keep the memory bounded.
+const SUPERVISED_CHUNK: usize = 1024;
+
+#[derive(Debug, Clone)]
 pub struct HyperParameters {
     pub discount: f64,
+    /// Exploration at the start of a run, moved along `eps_schedule` every episode.
     pub eps: f64,
-    pub eps_decay: f64,
+    pub eps_schedule: Schedule,
+    pub exploration: Exploration,
+    /// Learning rate at the start of a run, only used if `rate_schedule` is not constant.
+    pub learning_rate: f64,
+    pub rate_schedule: Schedule,
     pub episodes: usize,
+    /// Number of recent training examples kept for replay, 0 disables replay.
+    pub replay_capacity: usize,
+    /// Number of replayed examples trained alongside every new one.
+    pub replay_batch: usize,
+    /// Seeds network initialisation, exploration and evaluation games.
+    pub seed: u64,
+    pub rewards: RewardConfig,
+}
+
+impl Default for HyperParameters {
+    fn default() -> Self {
+        HyperParameters {
+            discount: 0.95,
+            eps: 0.5,
+            eps_schedule: Schedule::Exponential {
+                decay: 0.999,
+                min: 0.0,
+            },
+            exploration: Exploration::EpsilonGreedy,
+            learning_rate: 0.3,
+            rate_schedule: Schedule::Constant,
+            episodes: 500,
+            replay_capacity: 0,
+            replay_batch: 32,
+            seed: 0,
+            rewards: RewardConfig::default(),
+        }
+    }
 }
 
 pub struct BorderPatrolAgent {
     pub hyper_parms: HyperParameters,
-    pub nn: NN,
+    pub nn: Network,
+    pub shape: Vec<u32>,
+    pub trained_episodes: usize,
 }
 
 impl BorderPatrolAgent {
     pub fn new(shape: &[u32]) -> BorderPatrolAgent {
-        BorderPatrolAgent {
-            hyper_parms: HyperParameters {
-                discount: 0.95,
-                eps: 0.5,
-                eps_decay: 0.999,
-                episodes: 500,
-            },
-            nn: NN::new(shape),
-        }
+        Self::new_with(shape, HyperParameters::default())
     }
 
-    pub fn from_json(json: String) -> BorderPatrolAgent {
-        BorderPatrolAgent {
-            hyper_parms: HyperParameters {
-                discount: 0.95,
-                eps: 0.5,
-                eps_decay: 0.999,
-                episodes: 500,
-            },
-            nn: NN::from_json(&json),
-        }
+    pub fn from_json(json: String) -> Result<BorderPatrolAgent, ModelError> {
+        let nn = Network::from_nn_json(&json).map_err(ModelError::Format)?;
+        Ok(BorderPatrolAgent {
+            hyper_parms: HyperParameters::default(),
+            shape: nn.shape(),
+            nn,
+            trained_episodes: 0,
+        })
     }
 
     pub fn new_with(shape: &[u32], hyper_parms: HyperParameters) -> BorderPatrolAgent {
+        Self::new_with_activations(shape, Activation::Sigmoid, Activation::Sigmoid, hyper_parms)
+    }
+
+    pub fn new_with_activations(
+        shape: &[u32],
+        hidden: Activation,
+        output: Activation,
+        hyper_parms: HyperParameters,
+    ) -> BorderPatrolAgent {
+        let mut rng = rng::seeded(hyper_parms.seed, rng::NETWORK_INIT);
         BorderPatrolAgent {
             hyper_parms,
-            nn: NN::new(shape),
+            nn: Network::random(shape, hidden, output, &mut rng),
+            shape: shape.to_vec(),
+            trained_episodes: 0,
         }
     }
 
-    pub fn from_file(file_path: &str) -> BorderPatrolAgent {
-        let path = Path::new(file_path);
-        let mut file = match File::open(&path) {
-            Ok(file) => file,
-            _ => panic!("Could not open file at {file_path}"),
-        };
-        let mut json = String::new();
-        match file.read_to_string(&mut json) {
-            Ok(_) => println!("Read from {file_path}."),
-            _ => panic!("Could not read from {file_path}"),
+    pub fn from_file(file_path: &str) -> Result<BorderPatrolAgent, ModelError> {
+        let (metadata, network) = model::read(file_path)?;
+        let agent = Self::from_model(metadata, network)?;
+        println!("Read from {file_path}.");
+        Ok(agent)
+    }
+
+    pub fn from_model(
+        metadata: ModelMetadata,
+        network: Network,
+    ) -> Result<BorderPatrolAgent, ModelError> {
+        if metadata.agent != model::AGENT_DQN {
+            return Err(ModelError::WrongAgent {
+                expected: model::AGENT_DQN.to_owned(),
+                found: metadata.agent,
+            });
         }
-        return Self::from_json(json);
+        if metadata.board_size != BOARD_SIZE {
+            return Err(ModelError::BoardSizeMismatch {
+                expected: BOARD_SIZE,
+                found: metadata.board_size,
+            });
+        }
+        if metadata.encoder != ENCODER {
+            return Err(ModelError::UnknownEncoder(metadata.encoder));
+        }
+        model::check_shape(
+            &network,
+            BOARD_SIZE * BOARD_SIZE,
+            2 * BOARD_SIZE * BOARD_SIZE,
+        )?;
+
+        Ok(BorderPatrolAgent {
+            hyper_parms: metadata.hyper_parms,
+            nn: network,
+            shape: metadata.shape,
+            trained_episodes: metadata.trained_episodes,
+        })
     }
 
-    pub fn save(&self, file_path: &str) {
-        let path = Path::new(file_path);
-        let mut file = match File::create(&path) {
-            Ok(file) => file,
-            _ => panic!("Could not create file at {file_path}"),
-        };
-        match file.write_all(self.nn.to_json().as_bytes()) {
-            Ok(_) => println!("Wrote to {file_path}."),
-            _ => panic!("Could not write to {file_path}"),
-        };
+    pub fn metadata(&self) -> ModelMetadata {
+        ModelMetadata {
+            version: MODEL_FORMAT_VERSION,
+            agent: model::AGENT_DQN.to_owned(),
+            board_size: BOARD_SIZE,
+            encoder: ENCODER.to_owned(),
+            shape: self.shape.clone(),
+            hyper_parms: self.hyper_parms.clone(),
+            trained_episodes: self.trained_episodes,
+            created: model::now(),
+            extra: vec![],
+        }
     }
 
-    fn get_action(&self, r: f64, env: &BorderPatrol, state: &Vec<f64>) -> usize {
-        if r < self.hyper_parms.eps {
-            env.random_action()
-        } else {
-            self.nn
-                .run(state)
-                .into_iter()
-                .enumerate()
-                .filter(|&(i, _)| env.is_possible(i))
-                .reduce(|acc, x| if acc.1 >= x.1 { acc } else { x })
-                .unwrap()
-                .0
+    pub fn save(&self, file_path: &str) -> Result<(), ModelError> {
+        self.write(file_path)?;
+        println!("Wrote to {file_path}.");
+        Ok(())
+    }
+
+    /// Like `save` without printing, for callers that own the terminal.
+    pub fn write(&self, file_path: &str) -> Result<(), ModelError> {
+        model::write(file_path, &self.metadata(), &self.nn)
+    }
+
+    /// Picks a line to explore with, `eps` is the epsilon or temperature of the episode.
+    fn get_action(&self, rng: &mut impl Rng, env: &BorderPatrol, state: &[f64], eps: f64) -> usize {
+        explore_line(self.hyper_parms.exploration, eps, env, rng, || {
+            self.nn.run(state)
+        })
+    }
+
+    /// Epsilon, or the temperature, of the run's `episode`th episode.
+    pub fn eps_at(&self, episode: usize) -> f64 {
+        self.hyper_parms
+            .eps_schedule
+            .value(self.hyper_parms.eps, episode)
+    }
+
+    /// Sets the learning rate of the run's `episode`th episode.
+    pub fn schedule_rate(&mut self, episode: usize) {
+        if self.hyper_parms.rate_schedule != Schedule::Constant {
+            self.nn.config.rate = self
+                .hyper_parms
+                .rate_schedule
+                .value(self.hyper_parms.learning_rate, episode);
         }
     }
 
+    fn greedy_action(&self, env: &BorderPatrol, state: &[f64]) -> usize {
+        greedy_line(&self.nn, env, state)
+    }
+
     fn convert_state(&self, state: [u16; 100]) -> Vec<f64> {
-        state.into_iter().map(|x| x.try_into().unwrap()).collect()
+        convert_state(state)
     }
 
     pub fn train(&mut self) {
-        let mut rng = rand::thread_rng();
-        for i in 0..self.hyper_parms.episodes {
-            println!("Episode {i}");
-            let mut env = BorderPatrol::new();
+        let mut training = TrainingState::new(
+            rng::seeded(self.hyper_parms.seed, rng::EXPLORATION),
+            self.hyper_parms.replay_capacity,
+        );
+        self.train_from(&mut training, &mut [])
+            .expect("Training without observers does not touch the file system.");
+    }
 
-            let mut states = Vec::with_capacity(2);
-            let mut actions: [usize; 2] = [0, 0];
-            let mut rewards: [f64; 2] = [0.0, 0.0];
+    /// Trains until `training.episode` reaches the configured number of episodes. Passing the
+    /// state restored from a checkpoint continues the run exactly where it stopped.
+    pub fn train_from(
+        &mut self,
+        training: &mut TrainingState,
+        observers: &mut [&mut dyn TrainingObserver],
+    ) -> Result<(), ModelError> {
+        while self.train_step(training, observers)?.is_some() {}
+        Ok(())
+    }
 
-            states.push(self.convert_state(env.board.layout));
-            actions[0] = self.get_action(rng.gen_range(0.0..1.0), &env, &states[0]);
-            rewards[0] = env.step(actions[0]);
+    /// Trains a single episode and notifies the observers, returns `None` once all episodes
+    /// of the run are done.
+    pub fn train_step(
+        &mut self,
+        training: &mut TrainingState,
+        observers: &mut [&mut dyn TrainingObserver],
+    ) -> Result<Option<EpisodeStats>, ModelError> {
+        if training.episode >= self.hyper_parms.episodes {
+            return Ok(None);
+        }
 
-            states.push(self.convert_state(env.board.layout));
-            actions[1] = self.get_action(rng.gen_range(0.0..1.0), &env, &states[1]);
-            rewards[1] = env.step(actions[1]);
+        let mut stats = self.train_episode(training);
+        training.episode += 1;
+        stats.episode = training.episode;
 
-            let mut state = self.convert_state(env.board.layout);
+        if let Some(league) = &mut training.league {
+            league.after_episode(self, training.episode)?;
+        }
 
-            let mut turn = env.get_turn();
+        for observer in observers.iter_mut() {
+            observer.after_episode(self, training, &stats)?;
+        }
+        Ok(Some(stats))
+    }
 
-            self.hyper_parms.eps *= self.hyper_parms.eps_decay;
+    fn train_episode(&mut self, training: &mut TrainingState) -> EpisodeStats {
+        if training.league.is_some() {
+            return self.train_league_episode(training);
+        }
 
-            while !env.game_info.finished {
-                // Get predicted move form neural network for the new state
-                let target = rewards[turn]
-                    + self.hyper_parms.discount
-                        * self
-                            .nn
-                            .run(&state)
-                            .into_iter()
-                            .enumerate()
-                            .filter(|&(i, _)| env.is_possible(i))
-                            .map(|(_, x)| x)
-                            .reduce(f64::max)
-                            .unwrap();
-
-                // Adjust values to account for target value of new state
-                let mut target_vec = self.nn.run(&states[turn]);
-                target_vec[actions[turn]] = target;
-
-                actions[turn] = self.get_action(rng.gen_range(0.0..1.0), &env, &state);
-                rewards[turn] = env.step(actions[turn]);
-                rewards[(turn + 1) % 2] -= rewards[turn] / 10.0;
-
-                // Train neural network on the target value with the current state
-                if turn == 0 {
-                    self.nn
-                        .train(&[(state, target_vec)])
-                        .halt_condition(HaltCondition::Epochs(1))
-                        .go();
+        let eps = self.eps_at(training.episode);
+        self.schedule_rate(training.episode);
+        self.trained_episodes += 1;
+
+        let hyper_parms = self.hyper_parms.clone();
+        let mut learner = OnlineLearner {
+            agent: self,
+            replay: &mut training.replay,
+            stats: EpisodeStats {
+                epsilon: eps,
+                ..Default::default()
+            },
+        };
+        let length = self_play_episode(&mut learner, &hyper_parms, eps, &mut training.rng);
+        let mut stats = learner.stats;
+        stats.length = length;
+        stats.average_updates();
+        stats
+    }
+
+    /// Highest q value among the legal lines of `env`.
+    fn best_value(&self, env: &BorderPatrol, state: &[f64]) -> f64 {
+        self.nn
+            .run_argmax(state, |line| env.is_possible(line))
+            .expect("a game that is not finished has a legal line")
+            .1
+    }
+
+    /// Plays one game of the learner against an opponent sampled from the league. Only the
+    /// learner's own moves are trained on, the opponent's boxes count against its reward.
+    fn train_league_episode(&mut self, training: &mut TrainingState) -> EpisodeStats {
+        let TrainingState {
+            episode,
+            rng,
+            replay,
+            league,
+            ..
+        } = training;
+        let league = league.as_ref().unwrap();
+        let learner = *episode % 2;
+        let eps = self.eps_at(*episode);
+        self.schedule_rate(*episode);
+        let opponent = league.sample(rng);
+
+        let mut env = BorderPatrol::new();
+        let mut stats = EpisodeStats {
+            epsilon: eps,
+            opponent: Some(league.name(opponent)),
+            ..Default::default()
+        };
+
+        self.trained_episodes += 1;
+
+        // State, action and reward of the learner's last move, waiting for the state the
+        // learner sees next to compute its target
+        let mut last: Option<(Vec<f64>, usize, f64)> = None;
+
+        while !env.game_info.finished {
+            stats.length += 1;
+            if env.get_turn() != learner {
+                let line = league.choose_line(opponent, &env, rng);
+                let (reward, learner_reward) = env.step_with(line, &self.hyper_parms.rewards);
+                if let Some((_, _, last_reward)) = &mut last {
+                    *last_reward +=
+                        learner_reward - reward * self.hyper_parms.rewards.opponent_share;
+                }
+                continue;
+            }
+
+            let state = self.convert_state(env.board.layout);
+            if let Some((last_state, action, reward)) = last.take() {
+                let target = reward + self.hyper_parms.discount * self.best_value(&env, &state);
+                stats.add_update(self.td_update(replay, rng, last_state, action, target));
+            }
+
+            let action = self.get_action(rng, &env, &state, eps);
+            let (reward, _) = env.step_with(action, &self.hyper_parms.rewards);
+            last = Some((state, action, reward));
+        }
+
+        // The game is over, so the final move is only judged by its reward
+        if let Some((last_state, action, reward)) = last {
+            stats.add_update(self.td_update(replay, rng, last_state, action, reward));
+        }
+        stats.average_updates();
+        stats
+    }
+
+    /// Moves the predicted value of `action` in `state` towards `target` and returns the
+    /// previous prediction together with its absolute error.
+    pub fn td_update(
+        &mut self,
+        replay: &mut ReplayBuffer,
+        rng: &mut SeededRng,
+        state: Vec<f64>,
+        action: usize,
+        target: f64,
+    ) -> (f64, f64) {
+        let mut target_vec = self.nn.run(&state);
+        let predicted = target_vec[action];
+        target_vec[action] = target;
+        self.learn(replay, rng, (state, target_vec));
+        (predicted, (target - predicted).abs())
+    }
+
+    /// Trains the network on a new example, replaying earlier examples alongside it if a
+    /// replay buffer is configured.
+    fn learn(
+        &mut self,
+        replay: &mut ReplayBuffer,
+        rng: &mut SeededRng,
+        example: (Vec<f64>, Vec<f64>),
+    ) {
+        let mut batch = replay.sample(self.hyper_parms.replay_batch, rng);
+        batch.push(example);
+        replay.push(batch.last().unwrap().clone());
+
+        self.nn.train(&batch, 1);
+    }
+
+    /// Fits the network to recorded games and solver labelled positions for one pass and
+    /// returns the mean squared error. The moves of a game are taught as the best line of
+    /// their position, and labelled values are mapped from the boxes still on the board to
+    /// the q value range, 0.5 being an even split of them.
+    pub fn fit_supervised(
+        &mut self,
+        games: &[GameRecord],
+        positions: &[LabelledPosition],
+        rng: &mut SeededRng,
+    ) -> f64 {
+        let mut sources: Vec<Option<usize>> = (0..games.len()).map(Some).collect();
+        sources.extend((0..positions.len()).map(|_| None));
+        sources.shuffle(rng);
+        let mut positions = positions.iter();
+
+        let (mut batch, mut error, mut examples) = (Vec::new(), 0.0, 0);
+        for source in sources {
+            match source {
+                Some(game) => {
+                    let mut env = BorderPatrol::new();
+                    for &line in &games[game].moves {
+                        let state = self.convert_state(env.board.layout);
+                        let mut target = self.nn.run(&state);
+                        for (i, value) in target.iter_mut().enumerate() {
+                            if env.is_possible(i) {
+                                *value = if i == line { 1.0 } else { 0.0 };
+                            }
+                        }
+                        batch.push((state, target));
+                        env.set_line(line, env.game_info.turn);
+                    }
                 }
+                None => {
+                    let position = positions.next().unwrap();
+                    let env =
+                        records::replay(&position.moves).expect("positions are checked when read");
+                    let boxes_left = (100 - env.game_info.score[0] - env.game_info.score[1]).max(1);
+                    let state = self.convert_state(env.board.layout);
+                    let mut target = self.nn.run(&state);
+                    for &(line, value) in &position.values {
+                        target[line] = 0.5 + 0.5 * value as f64 / boxes_left as f64;
+                    }
+                    batch.push((state, target));
+                }
+            }
 
-                state = self.convert_state(env.board.layout);
-                turn = env.get_turn();
+            if batch.len() >= SUPERVISED_CHUNK {
+                batch.shuffle(rng);
+                error += self.nn.train(&batch, 1) * batch.len() as f64;
+                examples += batch.len();
+                batch.clear();
             }
         }
+        if !batch.is_empty() {
+            batch.shuffle(rng);
+            error += self.nn.train(&batch, 1) * batch.len() as f64;
+            examples += batch.len();
+        }
+        error / examples.max(1) as f64
+    }
+
+    /// Plays `games` greedy games against a random player, alternating sides, and returns the
+    /// share of points won, counting a draw as half a win.
+    pub fn evaluate(&self, games: usize, rng: &mut impl Rng) -> f64 {
+        let mut points = 0.0;
+        for game in 0..games {
+            let side = game % 2;
+            let mut env = BorderPatrol::new();
+
+            while !env.game_info.finished {
+                let action = if env.get_turn() == side {
+                    self.greedy_action(&env, &self.convert_state(env.board.layout))
+                } else {
+                    env.random_action(rng)
+                };
+                env.step(action);
+            }
+
+            points += match env.game_info.score[side].cmp(&env.game_info.score[1 - side]) {
+                Ordering::Greater => 1.0,
+                Ordering::Equal => 0.5,
+                Ordering::Less => 0.0,
+            };
+        }
+        points / games.max(1) as f64
+    }
+}
+
+/// The line with the highest q value among the legal ones.
+pub fn greedy_line(nn: &Network, env: &BorderPatrol, state: &[f64]) -> usize {
+    nn.run_argmax(state, |line| env.is_possible(line))
+        .expect("a game that is not finished has a legal line")
+        .0
+}
+
+/// Picks a line to explore with, `q_values` is only asked for when the line is not random.
+fn explore_line(
+    exploration: Exploration,
+    eps: f64,
+    env: &BorderPatrol,
+    rng: &mut impl Rng,
+    q_values: impl FnOnce() -> Vec<f64>,
+) -> usize {
+    match exploration {
+        Exploration::EpsilonGreedy if rng.gen_range(0.0..1.0) < eps => env.random_action(rng),
+        Exploration::EpsilonGreedy => {
+            masked_argmax(&q_values(), |line| env.is_possible(line))
+                .expect("a game that is not finished has a legal line")
+                .0
+        }
+        Exploration::Boltzmann => boltzmann_line(&q_values(), env, eps, rng),
+    }
+}
+
+/// Draws a legal line with probability proportional to exp(q / temperature). A temperature
+/// of zero, or q values that give no usable weights, pick the best line.
+pub fn boltzmann_line(
+    q_values: &[f64],
+    env: &BorderPatrol,
+    temperature: f64,
+    rng: &mut impl Rng,
+) -> usize {
+    let (best_line, best) = masked_argmax(q_values, |line| env.is_possible(line))
+        .expect("a game that is not finished has a legal line");
+    if temperature <= f64::EPSILON {
+        return best_line;
+    }
+
+    let legal: Vec<(usize, f64)> = q_values
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|&(line, _)| env.is_possible(line))
+        .collect();
+    let weights: Vec<f64> = legal
+        .iter()
+        .map(|&(_, q)| ((q - best) / temperature).exp())
+        .map(|weight| if weight.is_nan() { 0.0 } else { weight })
+        .collect();
+    let total = weights.iter().sum::<f64>();
+    if !(total.is_finite() && total > 0.0) {
+        return best_line;
+    }
+    let mut pick = rng.gen_range(0.0..total);
+    for (&(line, _), weight) in legal.iter().zip(weights) {
+        if pick < weight {
+            return line;
+        }
+        pick -= weight;
+    }
+    legal.last().unwrap().0
+}
+
+pub fn convert_state(state: [u16; 100]) -> Vec<f64> {
+    state.into_iter().map(f64::from).collect()
+}
+
+/// Where `self_play_episode` gets its q values from and sends the moves to.
+pub trait EpisodeLearner {
+    /// The q values of every line in `state`.
+    fn q_values(&self, state: &[f64]) -> Vec<f64>;
+
+    /// Moves the q value of `action` in `state` towards `target`.
+    fn learn(&mut self, rng: &mut SeededRng, state: Vec<f64>, action: usize, target: f64);
+}
+
+/// Plays one exploring game against itself and returns the number of lines drawn. Every move
+/// is handed to `learner` once the same player is about to move again, with the state it was
+/// made in and its reward plus the discounted best q value of the new state as target. The
+/// final moves are only judged by their reward. A player's reward is reduced by a share of
+/// the opponent's reward before its next turn.
+pub fn self_play_episode(
+    learner: &mut impl EpisodeLearner,
+    hyper_parms: &HyperParameters,
+    eps: f64,
+    rng: &mut SeededRng,
+) -> usize {
+    let mut env = BorderPatrol::new();
+    let mut length = 0;
+
+    // State, action and reward of each player's last move, waiting for the state the
+    // player sees next to compute its target
+    let mut last: [Option<(Vec<f64>, usize, f64)>; 2] = [None, None];
+
+    while !env.game_info.finished {
+        let turn = env.get_turn();
+        let state = convert_state(env.board.layout);
+        if let Some((last_state, action, reward)) = last[turn].take() {
+            let (_, best) = masked_argmax(&learner.q_values(&state), |line| env.is_possible(line))
+                .expect("a game that is not finished has a legal line");
+            learner.learn(
+                rng,
+                last_state,
+                action,
+                reward + hyper_parms.discount * best,
+            );
+        }
+
+        let action = explore_line(hyper_parms.exploration, eps, &env, rng, || {
+            learner.q_values(&state)
+        });
+        let (reward, opponent_reward) = env.step_with(action, &hyper_parms.rewards);
+        if let Some((_, _, last_reward)) = &mut last[1 - turn] {
+            *last_reward += opponent_reward - reward * hyper_parms.rewards.opponent_share;
+        }
+        last[turn] = Some((state, action, reward));
+        length += 1;
+    }
+
+    for (state, action, reward) in last.into_iter().flatten() {
+        learner.learn(rng, state, action, reward);
+    }
+    length
+}
+
+/// Trains the agent on the moves of `self_play_episode` as soon as they are played.
+struct OnlineLearner<'a> {
+    agent: &'a mut BorderPatrolAgent,
+    replay: &'a mut ReplayBuffer,
+    stats: EpisodeStats,
+}
+
+impl EpisodeLearner for OnlineLearner<'_> {
+    fn q_values(&self, state: &[f64]) -> Vec<f64> {
+        self.agent.nn.run(state)
+    }
+
+    fn learn(&mut self, rng: &mut SeededRng, state: Vec<f64>, action: usize, target: f64) {
+        let update = self
+            .agent
+            .td_update(self.replay, rng, state, action, target);
+        self.stats.add_update(update);
+    }
+}
+
+/// Measurements of a single training episode.
+#[derive(Debug, Clone, Default)]
+pub struct EpisodeStats {
+    /// Number of episodes finished in the run, including this one.
+    pub episode: usize,
+    /// Exploration rate the episode was played with.
+    pub epsilon: f64,
+    /// Mean absolute difference between predicted and target q values.
+    pub td_error: f64,
+    /// Mean predicted q value of the chosen actions.
+    pub q_value: f64,
+    /// Number of lines drawn.
+    pub length: usize,
+    /// Name of the league opponent, `None` when the agent played itself.
+    pub opponent: Option<String>,
+    /// Number of moves trained on.
+    pub updates: usize,
+}
+
+impl EpisodeStats {
+    /// Adds the prediction and error of a trained move, see `BorderPatrolAgent::td_update`.
+    pub fn add_update(&mut self, (q_value, td_error): (f64, f64)) {
+        self.q_value += q_value;
+        self.td_error += td_error;
+        self.updates += 1;
+    }
+
+    /// Turns the sums of `add_update` into means once the episode is over.
+    pub fn average_updates(&mut self) {
+        if self.updates > 0 {
+            self.q_value /= self.updates as f64;
+            self.td_error /= self.updates as f64;
+        }
+    }
+}
+
+/// Gets called after every training episode, e.g. to write checkpoints or log metrics.
+pub trait TrainingObserver {
+    fn after_episode(
+        &mut self,
+        agent: &mut BorderPatrolAgent,
+        training: &mut TrainingState,
+        stats: &EpisodeStats,
+    ) -> Result<(), ModelError>;
+}
+
+/// Recent training examples that are replayed together with every new one.
+pub struct ReplayBuffer {
+    pub capacity: usize,
+    pub examples: VecDeque<(Vec<f64>, Vec<f64>)>,
+}
+
+impl ReplayBuffer {
+    pub fn new(capacity: usize) -> ReplayBuffer {
+        ReplayBuffer {
+            capacity,
+            examples: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, example: (Vec<f64>, Vec<f64>)) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.examples.len() == self.capacity {
+            self.examples.pop_front();
+        }
+        self.examples.push_back(example);
+    }
+
+    fn sample(&self, amount: usize, rng: &mut impl Rng) -> Vec<(Vec<f64>, Vec<f64>)> {
+        if self.examples.is_empty() {
+            return Vec::new();
+        }
+        self.examples.iter().cloned().choose_multiple(rng, amount)
+    }
+}
+
+/// Everything besides the agent itself that is needed to continue a training run.
+pub struct TrainingState {
+    /// Episodes finished in the current run.
+    pub episode: usize,
+    pub rng: SeededRng,
+    pub replay: ReplayBuffer,
+    pub best_evaluation: Option<f64>,
+    /// Opponents to train against instead of playing itself.
+    pub league: Option<League>,
+}
+
+impl TrainingState {
+    pub fn new(rng: SeededRng, replay_capacity: usize) -> TrainingState {
+        TrainingState {
+            episode: 0,
+            rng,
+            replay: ReplayBuffer::new(replay_capacity),
+            best_evaluation: None,
+            league: None,
+        }
     }
 }
 
 impl BorderPatrol {
-    fn step(&mut self, action: usize) -> f64 {
-        let player = self.game_info.turn;
+    /// Draws `action` for the player to move and returns its reward under the default
+    /// reward configuration.
+    pub fn step(&mut self, action: usize) -> f64 {
+        self.step_with(action, &RewardConfig::default()).0
+    }
 
-        let before = self.game_info.get_points();
+    /// Draws `action` for the player to move and returns the rewards of the mover and of its
+    /// opponent.
+    pub fn step_with(&mut self, action: usize, rewards: &RewardConfig) -> (f64, f64) {
+        let before = self.clone();
+        let player = self.game_info.turn;
         self.set_line(action, self.game_info.turn);
         let after = self.game_info.score[(PLAYER_ONE - player) as usize];
 
-        let mut reward = (after - before) as f64 * 0.05;
-
         if after > 50 {
             self.game_info.finished = true;
-            reward += 1.0;
         }
 
         if after == 50 && self.game_info.get_points() == 50 {
             self.game_info.finished = true;
         }
-        return reward;
+        rewards.rewards(&before, self)
     }
 
-    fn is_possible(&self, line: usize) -> bool {
+    pub fn is_possible(&self, line: usize) -> bool {
         !(self.get_line(line, PLAYER_ONE) || self.get_line(line, PLAYER_TWO))
     }
 
-    fn get_turn(&self) -> usize {
+    pub fn get_turn(&self) -> usize {
         (PLAYER_ONE - self.game_info.turn) as usize
     }
 
-    fn random_action(&self) -> usize {
-        let offset = self.game_info.turn - PLAYER_TWO;
-        let (i, value) = self
-            .board
-            .layout
-            .iter()
-            .enumerate()
-            .filter(|&(_, x)| (x >> offset) & 3 != 3)
-            .choose(&mut rand::thread_rng())
-            .unwrap();
-
-        if (value >> offset) & 1 == 0 {
-            return i;
-        }
-
-        i + 1
+    pub fn random_action(&self, rng: &mut impl Rng) -> usize {
+        (0..2 * BOARD_SIZE * BOARD_SIZE)
+            .filter(|&i| self.is_possible(i))
+            .choose(rng)
+            .unwrap()
     }
 }
 
 impl Player for BorderPatrolAgent {
     fn init(&mut self) {}
 
-    fn make_move(&self, border_patrol: &mut BorderPatrol) {
-        let converted_state: Vec<f64> = border_patrol
-            .board
-            .layout
-            .clone()
-            .into_iter()
-            .map(|x| x.try_into().unwrap())
-            .collect();
-
-        let action = self
-            .nn
-            .run(&converted_state)
-            .into_iter()
-            .enumerate()
-            .filter(|&(i, _)| {
-                !(border_patrol.get_line(i, PLAYER_ONE) || border_patrol.get_line(i, PLAYER_TWO))
-            })
-            .reduce(|acc, x| if acc.1 >= x.1 { acc } else { x })
-            .unwrap()
-            .0;
+    fn choose_line(&self, border_patrol: &BorderPatrol) -> usize {
+        self.greedy_action(
+            border_patrol,
+            &self.convert_state(border_patrol.board.layout),
+        )
+    }
+
+    /// The q values of all lines.
+    fn explain(&self, border_patrol: &BorderPatrol) -> Option<Vec<f64>> {
+        Some(self.nn.run(&self.convert_state(border_patrol.board.layout)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_model_checks_the_network_shape() {
+        let agent = BorderPatrolAgent::new(&[100, 4, 200]);
+        assert!(BorderPatrolAgent::from_model(agent.metadata(), agent.nn.clone()).is_ok());
+
+        let wrong = BorderPatrolAgent::new(&[100, 4, 100]);
+        assert!(matches!(
+            BorderPatrolAgent::from_model(wrong.metadata(), wrong.nn),
+            Err(ModelError::Format(_))
+        ));
+    }
+
+    #[test]
+    fn boltzmann_line_survives_nan_q_values() {
+        let env = BorderPatrol::new();
+        let mut rng = rng::seeded(0, rng::EXPLORATION);
+        let mut q_values = vec![f64::NAN; 200];
+        let line = boltzmann_line(&q_values, &env, 1.0, &mut rng);
+        assert!(env.is_possible(line));
+
+        q_values[7] = 0.5;
+        assert_eq!(boltzmann_line(&q_values, &env, 0.0, &mut rng), 7);
+        assert!(env.is_possible(boltzmann_line(&q_values, &env, 1.0, &mut rng)));
+    }
+
+    #[test]
+    fn from_json_rejects_invalid_json() {
+        assert!(matches!(
+            BorderPatrolAgent::from_json("{\"layers\": [".to_owned()),
+            Err(ModelError::Format(_))
+        ));
+    }
+
+    /// Keeps every move handed to it, with q values that do not depend on the state.
+    struct MoveLog(Vec<(Vec<f64>, usize, f64)>);
+
+    impl EpisodeLearner for MoveLog {
+        fn q_values(&self, _: &[f64]) -> Vec<f64> {
+            vec![0.5; 200]
+        }
+
+        fn learn(&mut self, _: &mut SeededRng, state: Vec<f64>, action: usize, target: f64) {
+            self.0.push((state, action, target));
+        }
+    }
+
+    #[test]
+    fn self_play_trains_every_move_on_the_state_it_was_made_in() {
+        let mut log = MoveLog(Vec::new());
+        let hyper_parms = HyperParameters::default();
+        let mut rng = rng::seeded(1, rng::EXPLORATION);
+        let length = self_play_episode(&mut log, &hyper_parms, 0.5, &mut rng);
+        assert_eq!(log.0.len(), length);
+
+        // Replaying the trained moves from their states has to play the whole game
+        let mut env = BorderPatrol::new();
+        while !env.game_info.finished {
+            let state = convert_state(env.board.layout);
+            let &(_, action, _) = log
+                .0
+                .iter()
+                .find(|(trained, _, _)| *trained == state)
+                .expect("a position of the game was not trained on");
+            assert!(env.is_possible(action));
+            env.step(action);
+        }
+    }
+
+    #[test]
+    fn episode_stats_average_the_trained_moves() {
+        let mut agent = BorderPatrolAgent::new(&[100, 4, 200]);
+        let mut training = TrainingState::new(rng::seeded(0, rng::EXPLORATION), 0);
+        let stats = agent.train_step(&mut training, &mut []).unwrap().unwrap();
+
+        assert_eq!(stats.updates, stats.length);
+        assert!(stats.q_value > 0.0 && stats.q_value < 1.0);
+        assert!(stats.td_error > 0.0 && stats.td_error < 1.5);
+    }
+
+    fn train_with_seed(seed: u64) -> Vec<u8> {
+        let hyper_parms = HyperParameters {
+            episodes: 3,
+            replay_capacity: 32,
+            replay_batch: 4,
+            seed,
+            ..Default::default()
+        };
+        let mut agent = BorderPatrolAgent::new_with(&[100, 4, 200], hyper_parms);
+        let mut training = TrainingState::new(rng::seeded(seed, rng::EXPLORATION), 32);
+        agent.train_from(&mut training, &mut []).unwrap();
+        agent.nn.to_bytes(true)
+    }
 
-        border_patrol.set_line(action, border_patrol.game_info.turn);
+    #[test]
+    fn training_is_reproduced_by_its_seed() {
+        assert_eq!(train_with_seed(5), train_with_seed(5));
+        assert_ne!(train_with_seed(5), train_with_seed(6));
     }
 }
@@ -0,0 +1,453 @@
+#![allow(dead_code)]
+/*
+    Model files start with a plain text header followed by the network weights:
+
+        borderpatrol-model 2
+        agent = dqn
+        board_size = 10
+        encoder = layout-raw
+        shape = 100,300,300,200
+        ...
+        weights
+        <binary network, see network.rs>
+
+    Version 1 files store the weights as json of the nn crate after the same header and files
+    written before the header existed only contain that json. Both are still accepted, the
+    latter are reported as version 0.
+*/
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{ml::HyperParameters, network::Network, schedule::Schedule};
+
+pub const MODEL_FORMAT_VERSION: u32 = 2;
+const MAGIC: &str = "borderpatrol-model";
+const WEIGHTS_MARKER: &str = "weights";
+
+pub const AGENT_DQN: &str = "dqn";
+pub const AGENT_ALPHAZERO: &str = "alphazero";
+
+#[derive(Debug)]
+pub enum ModelError {
+    Io(String, io::Error),
+    Format(String),
+    UnsupportedVersion(u32),
+    BoardSizeMismatch { expected: usize, found: usize },
+    UnknownEncoder(String),
+    WrongAgent { expected: String, found: String },
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModelError::Io(path, err) => write!(f, "Could not access {path}: {err}"),
+            ModelError::Format(msg) => write!(f, "Malformed model file: {msg}"),
+            ModelError::UnsupportedVersion(version) => write!(
+                f,
+                "Model format version {version} is not supported (newest known is {MODEL_FORMAT_VERSION})"
+            ),
+            ModelError::BoardSizeMismatch { expected, found } => write!(
+                f,
+                "Model was trained on a {found}x{found} board but this game uses {expected}x{expected}"
+            ),
+            ModelError::UnknownEncoder(encoder) => write!(f, "Unknown state encoder '{encoder}'"),
+            ModelError::WrongAgent { expected, found } => {
+                write!(f, "Expected a {expected} model but found a {found} model")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
+
+#[derive(Debug, Clone)]
+pub struct ModelMetadata {
+    pub version: u32,
+    /// Kind of agent the weights belong to, see `AGENT_DQN` and `AGENT_ALPHAZERO`.
+    pub agent: String,
+    pub board_size: usize,
+    pub encoder: String,
+    pub shape: Vec<u32>,
+    pub hyper_parms: HyperParameters,
+    pub trained_episodes: usize,
+    /// Seconds since the unix epoch, 0 if unknown.
+    pub created: u64,
+    /// Header entries this module does not interpret itself, e.g. training state in checkpoints.
+    pub extra: Vec<(String, String)>,
+}
+
+impl ModelMetadata {
+    fn legacy() -> ModelMetadata {
+        ModelMetadata {
+            version: 0,
+            agent: AGENT_DQN.to_owned(),
+            board_size: crate::borderpatrol::BOARD_SIZE,
+            encoder: crate::ml::ENCODER.to_owned(),
+            shape: vec![],
+            hyper_parms: HyperParameters::default(),
+            trained_episodes: 0,
+            created: 0,
+            extra: vec![],
+        }
+    }
+
+    pub fn extra(&self, key: &str) -> Option<&str> {
+        self.extra
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn to_header(&self) -> String {
+        let shape: Vec<String> = self.shape.iter().map(|x| x.to_string()).collect();
+        let mut header = format!("{MAGIC} {}\n", self.version);
+        header.push_str(&format!("agent = {}\n", self.agent));
+        header.push_str(&format!("board_size = {}\n", self.board_size));
+        header.push_str(&format!("encoder = {}\n", self.encoder));
+        header.push_str(&format!("shape = {}\n", shape.join(",")));
+        header.push_str(&format!("discount = {}\n", self.hyper_parms.discount));
+        header.push_str(&format!("eps = {}\n", self.hyper_parms.eps));
+        header.push_str(&format!(
+            "eps_schedule = {}\n",
+            self.hyper_parms.eps_schedule
+        ));
+        header.push_str(&format!("exploration = {}\n", self.hyper_parms.exploration));
+        header.push_str(&format!(
+            "learning_rate = {}\n",
+            self.hyper_parms.learning_rate
+        ));
+        header.push_str(&format!(
+            "rate_schedule = {}\n",
+            self.hyper_parms.rate_schedule
+        ));
+        header.push_str(&format!("episodes = {}\n", self.hyper_parms.episodes));
+        header.push_str(&format!(
+            "replay_capacity = {}\n",
+            self.hyper_parms.replay_capacity
+        ));
+        header.push_str(&format!(
+            "replay_batch = {}\n",
+            self.hyper_parms.replay_batch
+        ));
+        header.push_str(&format!("seed = {}\n", self.hyper_parms.seed));
+        if self.agent == AGENT_DQN {
+            header.push_str(&format!("rewards = {}\n", self.hyper_parms.rewards));
+        }
+        header.push_str(&format!("trained_episodes = {}\n", self.trained_episodes));
+        header.push_str(&format!("created = {}\n", self.created));
+        for (key, value) in &self.extra {
+            header.push_str(&format!("{key} = {value}\n"));
+        }
+        header
+    }
+
+    pub fn from_header(header: &str) -> Result<ModelMetadata, ModelError> {
+        let mut lines = header.lines();
+        let version = match lines.next().and_then(|l| l.strip_prefix(MAGIC)) {
+            Some(version) => parse_value("version", version)?,
+            None => return Err(ModelError::Format("missing file header".to_owned())),
+        };
+        if version > MODEL_FORMAT_VERSION {
+            return Err(ModelError::UnsupportedVersion(version));
+        }
+
+        let mut metadata = ModelMetadata::legacy();
+        metadata.version = version;
+        for line in lines.filter(|l| !l.trim().is_empty()) {
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => return Err(ModelError::Format(format!("unexpected line '{line}'"))),
+            };
+            match key {
+                "agent" => metadata.agent = value.to_owned(),
+                "board_size" => metadata.board_size = parse_value(key, value)?,
+                "encoder" => metadata.encoder = value.to_owned(),
+                "shape" => {
+                    metadata.shape = value
+                        .split(',')
+                        .filter(|x| !x.is_empty())
+                        .map(|x| parse_value(key, x))
+                        .collect::<Result<_, _>>()?
+                }
+                "discount" => metadata.hyper_parms.discount = parse_value(key, value)?,
+                "eps" => metadata.hyper_parms.eps = parse_value(key, value)?,
+                // Files written before schedules existed only decay epsilon exponentially
+                "eps_decay" => {
+                    metadata.hyper_parms.eps_schedule = Schedule::Exponential {
+                        decay: parse_value(key, value)?,
+                        min: 0.0,
+                    }
+                }
+                "eps_schedule" => metadata.hyper_parms.eps_schedule = parse_value(key, value)?,
+                "exploration" => metadata.hyper_parms.exploration = parse_value(key, value)?,
+                "learning_rate" => metadata.hyper_parms.learning_rate = parse_value(key, value)?,
+                "rate_schedule" => metadata.hyper_parms.rate_schedule = parse_value(key, value)?,
+                "episodes" => metadata.hyper_parms.episodes = parse_value(key, value)?,
+                "trained_episodes" => metadata.trained_episodes = parse_value(key, value)?,
+                "replay_capacity" => {
+                    metadata.hyper_parms.replay_capacity = parse_value(key, value)?
+                }
+                "replay_batch" => metadata.hyper_parms.replay_batch = parse_value(key, value)?,
+                "seed" => metadata.hyper_parms.seed = parse_value(key, value)?,
+                "rewards" => metadata.hyper_parms.rewards = parse_value(key, value)?,
+                "created" => metadata.created = parse_value(key, value)?,
+                _ => metadata.extra.push((key.to_owned(), value.to_owned())),
+            }
+        }
+        Ok(metadata)
+    }
+}
+
+impl fmt::Display for ModelMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let shape: Vec<String> = self.shape.iter().map(|x| x.to_string()).collect();
+        let created = if self.created == 0 {
+            "unknown".to_owned()
+        } else {
+            format_timestamp(self.created)
+        };
+        writeln!(f, "Format version:   {}", self.version)?;
+        writeln!(f, "Agent:            {}", self.agent)?;
+        writeln!(f, "Board size:       {0}x{0}", self.board_size)?;
+        writeln!(f, "Encoder:          {}", self.encoder)?;
+        if shape.is_empty() {
+            writeln!(f, "Shape:            unknown")?;
+        } else {
+            writeln!(f, "Shape:            {}", shape.join(" -> "))?;
+        }
+        if self.agent == AGENT_DQN {
+            writeln!(f, "Discount:         {}", self.hyper_parms.discount)?;
+            writeln!(f, "Epsilon:          {}", self.hyper_parms.eps)?;
+            writeln!(f, "Epsilon schedule: {}", self.hyper_parms.eps_schedule)?;
+            writeln!(f, "Exploration:      {}", self.hyper_parms.exploration)?;
+            if self.hyper_parms.rate_schedule != Schedule::Constant {
+                writeln!(
+                    f,
+                    "Rate schedule:    {} from {}",
+                    self.hyper_parms.rate_schedule, self.hyper_parms.learning_rate
+                )?;
+            }
+            writeln!(f, "Episodes per run: {}", self.hyper_parms.episodes)?;
+            if self.hyper_parms.replay_capacity > 0 {
+                writeln!(
+                    f,
+                    "Replay buffer:    {} examples, {} per update",
+                    self.hyper_parms.replay_capacity, self.hyper_parms.replay_batch
+                )?;
+            }
+            writeln!(f, "Rewards:          {}", self.hyper_parms.rewards)?;
+        } else {
+            for (key, value) in &self.extra {
+                writeln!(f, "{:<17} {value}", format!("{key}:"))?;
+            }
+        }
+        writeln!(f, "Seed:             {}", self.hyper_parms.seed)?;
+        writeln!(f, "Trained episodes: {}", self.trained_episodes)?;
+        write!(f, "Created:          {created}")
+    }
+}
+
+/// Fails unless `network` takes `inputs` values and returns `outputs` values, the sizes an
+/// agent's encoder and the board need.
+pub fn check_shape(network: &Network, inputs: usize, outputs: usize) -> Result<(), ModelError> {
+    let shape = network.shape();
+    let (found_inputs, found_outputs) = (shape[0] as usize, *shape.last().unwrap() as usize);
+    if (found_inputs, found_outputs) != (inputs, outputs) {
+        return Err(ModelError::Format(format!(
+            "network has {found_inputs} inputs and {found_outputs} outputs but {inputs} and \
+             {outputs} are needed"
+        )));
+    }
+    Ok(())
+}
+
+pub fn parse_value<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, ModelError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| ModelError::Format(format!("invalid value '{}' for {key}", value.trim())))
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Formats seconds since the unix epoch as `YYYY-MM-DD HH:MM:SS UTC`.
+pub fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // Civil date from days since epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Reads a model file and returns its metadata together with the network.
+pub fn read(file_path: &str) -> Result<(ModelMetadata, Network), ModelError> {
+    let content = fs::read(file_path).map_err(|err| ModelError::Io(file_path.to_owned(), err))?;
+
+    if content.trim_ascii_start().starts_with(b"{") {
+        let network = read_weights(&content)?;
+        let mut metadata = ModelMetadata::legacy();
+        metadata.shape = network.shape();
+        return Ok((metadata, network));
+    }
+
+    let marker = format!("\n{WEIGHTS_MARKER}\n");
+    let split = match content
+        .windows(marker.len())
+        .position(|w| w == marker.as_bytes())
+    {
+        Some(split) => split,
+        None => return Err(ModelError::Format("missing weights section".to_owned())),
+    };
+    let header = std::str::from_utf8(&content[..split])
+        .map_err(|_| ModelError::Format("header is not valid text".to_owned()))?;
+
+    Ok((
+        ModelMetadata::from_header(header)?,
+        read_weights(&content[split + marker.len()..])?,
+    ))
+}
+
+fn read_weights(weights: &[u8]) -> Result<Network, ModelError> {
+    if weights.trim_ascii_start().starts_with(b"{") {
+        let json = std::str::from_utf8(weights)
+            .map_err(|_| ModelError::Format("weights are not valid json".to_owned()))?;
+        Network::from_nn_json(json).map_err(ModelError::Format)
+    } else {
+        Network::from_bytes(weights).map_err(ModelError::Format)
+    }
+}
+
+/// Reads only the metadata of a model file.
+pub fn read_metadata(file_path: &str) -> Result<ModelMetadata, ModelError> {
+    read(file_path).map(|(metadata, _)| metadata)
+}
+
+/// Writes the model without the optimiser state.
+pub fn write(
+    file_path: &str,
+    metadata: &ModelMetadata,
+    network: &Network,
+) -> Result<(), ModelError> {
+    write_network(file_path, metadata, network, false)
+}
+
+/// Writes the model together with the optimiser state, used for checkpoints.
+pub fn write_with_state(
+    file_path: &str,
+    metadata: &ModelMetadata,
+    network: &Network,
+) -> Result<(), ModelError> {
+    write_network(file_path, metadata, network, true)
+}
+
+fn write_network(
+    file_path: &str,
+    metadata: &ModelMetadata,
+    network: &Network,
+    with_state: bool,
+) -> Result<(), ModelError> {
+    let io_err = |err| ModelError::Io(file_path.to_owned(), err);
+    let mut file = File::create(file_path).map_err(io_err)?;
+    file.write_all(metadata.to_header().as_bytes())
+        .and_then(|_| file.write_all(format!("{WEIGHTS_MARKER}\n").as_bytes()))
+        .and_then(|_| file.write_all(&network.to_bytes(with_state)))
+        .map_err(io_err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ml::BorderPatrolAgent, reward::RewardConfig};
+
+    fn metadata() -> ModelMetadata {
+        let mut metadata = BorderPatrolAgent::new(&[100, 4, 200]).metadata();
+        metadata.hyper_parms.eps_schedule = Schedule::Linear {
+            end: 0.05,
+            episodes: 1000,
+        };
+        metadata.hyper_parms.rewards = "win=1,loss=-1".parse::<RewardConfig>().unwrap();
+        metadata.trained_episodes = 1234;
+        metadata.extra = vec![("episode".to_owned(), "1234".to_owned())];
+        metadata
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let metadata = metadata();
+        let read = ModelMetadata::from_header(&metadata.to_header()).unwrap();
+        assert_eq!(read.to_header(), metadata.to_header());
+        assert_eq!(read.shape, [100, 4, 200]);
+        assert_eq!(read.extra("episode"), Some("1234"));
+    }
+
+    #[test]
+    fn header_reads_the_epsilon_decay_of_old_files() {
+        let read = ModelMetadata::from_header("borderpatrol-model 1\neps_decay = 0.99\n").unwrap();
+        assert_eq!(read.version, 1);
+        assert_eq!(
+            read.hyper_parms.eps_schedule,
+            Schedule::Exponential {
+                decay: 0.99,
+                min: 0.0
+            }
+        );
+    }
+
+    #[test]
+    fn header_rejects_invalid_headers() {
+        for header in [
+            "",
+            "model 2\n",
+            "borderpatrol-model 3\n",
+            "borderpatrol-model two\n",
+            "borderpatrol-model 2\nshape 100,200\n",
+            "borderpatrol-model 2\nshape = 100,wide\n",
+            "borderpatrol-model 2\nrewards = bonus=1\n",
+            "borderpatrol-model 2\neps_schedule = sawtooth\n",
+        ] {
+            assert!(
+                ModelMetadata::from_header(header).is_err(),
+                "accepted '{header}'"
+            );
+        }
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let agent = BorderPatrolAgent::new(&[100, 4, 200]);
+        let path =
+            std::env::temp_dir().join(format!("borderpatrol-{}-test.model", std::process::id()));
+        let path = path.to_str().unwrap();
+        let metadata = agent.metadata();
+        write(path, &metadata, &agent.nn).unwrap();
+        let (read_metadata, network) = read(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(read_metadata.to_header(), metadata.to_header());
+        assert_eq!(network.to_bytes(false), agent.nn.to_bytes(false));
+    }
+}
@@ -0,0 +1,1000 @@
+#![allow(dead_code)]
+/*
+    A small fully connected network. Every layer stores one row of weights per output with
+    the bias first, the same layout the nn crate used, so its json files can be imported.
+
+    Networks are saved in a little endian binary format:
+
+        "BPNN", u32 format version
+        u32 inputs, u32 layer count, per layer u32 outputs and u8 activation
+        training config: u8 optimiser, f64 rate, f64 momentum or beta1, f64 beta2,
+                         u32 batch size, f64 l2, f64 clip (0 disables clipping)
+        per layer the weights as f64
+        u8 1 if the optimiser state follows, u64 step, the first moments of all layers
+           and for adam the second moments of all layers
+
+    Model files leave out the optimiser state, checkpoints keep it so a resumed run is exact.
+*/
+use std::{fmt, str::FromStr};
+
+use rand::Rng;
+
+const MAGIC: &[u8; 4] = b"BPNN";
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activation {
+    Sigmoid,
+    Tanh,
+    Relu,
+    LeakyRelu,
+    Linear,
+}
+
+impl Activation {
+    const ALL: [Activation; 5] = [
+        Activation::Sigmoid,
+        Activation::Tanh,
+        Activation::Relu,
+        Activation::LeakyRelu,
+        Activation::Linear,
+    ];
+
+    fn apply(self, x: f64) -> f64 {
+        match self {
+            Activation::Sigmoid => 1.0 / (1.0 + (-x).exp()),
+            Activation::Tanh => x.tanh(),
+            Activation::Relu => x.max(0.0),
+            Activation::LeakyRelu => {
+                if x > 0.0 {
+                    x
+                } else {
+                    0.01 * x
+                }
+            }
+            Activation::Linear => x,
+        }
+    }
+
+    /// Derivative expressed through the activated value `y`.
+    fn derivative(self, y: f64) -> f64 {
+        match self {
+            Activation::Sigmoid => y * (1.0 - y),
+            Activation::Tanh => 1.0 - y * y,
+            Activation::Relu => {
+                if y > 0.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Activation::LeakyRelu => {
+                if y > 0.0 {
+                    1.0
+                } else {
+                    0.01
+                }
+            }
+            Activation::Linear => 1.0,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Activation::Sigmoid => "sigmoid",
+            Activation::Tanh => "tanh",
+            Activation::Relu => "relu",
+            Activation::LeakyRelu => "leaky-relu",
+            Activation::Linear => "linear",
+        }
+    }
+}
+
+impl fmt::Display for Activation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Activation {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Activation::ALL
+            .into_iter()
+            .find(|activation| activation.name() == name)
+            .ok_or_else(|| format!("Unknown activation '{name}'"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Optimizer {
+    Sgd { momentum: f64 },
+    Adam { beta1: f64, beta2: f64 },
+}
+
+impl fmt::Display for Optimizer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Optimizer::Sgd { momentum } => write!(f, "sgd (momentum {momentum})"),
+            Optimizer::Adam { beta1, beta2 } => write!(f, "adam (beta1 {beta1}, beta2 {beta2})"),
+        }
+    }
+}
+
+impl FromStr for Optimizer {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "sgd" => Ok(Optimizer::Sgd { momentum: 0.0 }),
+            "adam" => Ok(Optimizer::Adam {
+                beta1: 0.9,
+                beta2: 0.999,
+            }),
+            _ => Err(format!("Unknown optimizer '{name}'")),
+        }
+    }
+}
+
+/// How `Network::train` updates the weights. The default trains one example at a time with
+/// plain gradient descent, like the nn crate did.
+#[derive(Debug, Clone)]
+pub struct TrainingConfig {
+    pub optimizer: Optimizer,
+    pub rate: f64,
+    /// Examples averaged into one update.
+    pub batch_size: usize,
+    /// Weight decay, biases are not decayed.
+    pub l2: f64,
+    /// Largest allowed norm of a batch gradient.
+    pub clip: Option<f64>,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> Self {
+        TrainingConfig {
+            optimizer: Optimizer::Sgd { momentum: 0.0 },
+            rate: 0.3,
+            batch_size: 1,
+            l2: 0.0,
+            clip: None,
+        }
+    }
+}
+
+impl fmt::Display for TrainingConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}, rate {}, batch {}, l2 {}",
+            self.optimizer, self.rate, self.batch_size, self.l2
+        )?;
+        match self.clip {
+            Some(clip) => write!(f, ", clip {clip}"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Layer {
+    inputs: usize,
+    outputs: usize,
+    activation: Activation,
+    /// `outputs` rows of `inputs + 1` weights, the bias first.
+    weights: Vec<f64>,
+}
+
+/// Moment buffers of the optimiser, shaped like the weights of each layer.
+#[derive(Debug, Clone)]
+struct OptimizerState {
+    step: u64,
+    first: Vec<Vec<f64>>,
+    second: Vec<Vec<f64>>,
+}
+
+/// The parameters of one layer in the usual matrix form, used to exchange networks with
+/// other formats.
+#[derive(Debug, Clone)]
+pub struct DenseLayer {
+    /// `outputs` rows of `inputs` weights.
+    pub weights: Vec<f64>,
+    pub bias: Vec<f64>,
+    pub activation: Activation,
+}
+
+#[derive(Debug, Clone)]
+pub struct Network {
+    inputs: usize,
+    layers: Vec<Layer>,
+    pub config: TrainingConfig,
+    state: Option<OptimizerState>,
+}
+
+impl Network {
+    /// Creates a network with weights drawn uniformly from [-0.5, 0.5). `shape` lists the
+    /// size of every layer including the inputs.
+    pub fn random(
+        shape: &[u32],
+        hidden: Activation,
+        output: Activation,
+        rng: &mut impl Rng,
+    ) -> Network {
+        assert!(shape.len() >= 2, "must have at least two layers");
+        assert!(
+            shape.iter().all(|&size| size > 0),
+            "can't have any empty layers"
+        );
+
+        let layers = shape
+            .windows(2)
+            .enumerate()
+            .map(|(i, sizes)| {
+                let (inputs, outputs) = (sizes[0] as usize, sizes[1] as usize);
+                Layer {
+                    inputs,
+                    outputs,
+                    activation: if i == shape.len() - 2 { output } else { hidden },
+                    weights: (0..outputs * (inputs + 1))
+                        .map(|_| rng.gen_range(-0.5..0.5))
+                        .collect(),
+                }
+            })
+            .collect();
+
+        Network {
+            inputs: shape[0] as usize,
+            layers,
+            config: TrainingConfig::default(),
+            state: None,
+        }
+    }
+
+    pub fn shape(&self) -> Vec<u32> {
+        let mut shape = vec![self.inputs as u32];
+        shape.extend(self.layers.iter().map(|layer| layer.outputs as u32));
+        shape
+    }
+
+    pub fn activations(&self) -> Vec<Activation> {
+        self.layers.iter().map(|layer| layer.activation).collect()
+    }
+
+    pub fn dense_layers(&self) -> Vec<DenseLayer> {
+        self.layers
+            .iter()
+            .map(|layer| {
+                let rows = layer.weights.chunks_exact(layer.inputs + 1);
+                DenseLayer {
+                    weights: rows
+                        .clone()
+                        .flat_map(|row| row[1..].iter().copied())
+                        .collect(),
+                    bias: rows.map(|row| row[0]).collect(),
+                    activation: layer.activation,
+                }
+            })
+            .collect()
+    }
+
+    /// Builds a network with the default training config from the layers of `dense_layers`.
+    pub fn from_dense_layers(inputs: usize, dense: Vec<DenseLayer>) -> Result<Network, String> {
+        if dense.is_empty() {
+            return Err("network has no layers".to_owned());
+        }
+        let mut layers = Vec::with_capacity(dense.len());
+        let mut previous = inputs;
+        for layer in dense {
+            let outputs = layer.bias.len();
+            if outputs == 0 || layer.weights.len() != outputs * previous {
+                return Err(format!(
+                    "expected {outputs} x {previous} weights but found {}",
+                    layer.weights.len()
+                ));
+            }
+            let weights = layer
+                .weights
+                .chunks_exact(previous)
+                .zip(&layer.bias)
+                .flat_map(|(row, &bias)| std::iter::once(bias).chain(row.iter().copied()))
+                .collect();
+            layers.push(Layer {
+                inputs: previous,
+                outputs,
+                activation: layer.activation,
+                weights,
+            });
+            previous = outputs;
+        }
+
+        Ok(Network {
+            inputs,
+            layers,
+            config: TrainingConfig::default(),
+            state: None,
+        })
+    }
+
+    pub fn run(&self, input: &[f64]) -> Vec<f64> {
+        self.forward(input).pop().unwrap()
+    }
+
+    /// Index and value of the highest output that `allowed` admits, see `masked_argmax`.
+    pub fn run_argmax(
+        &self,
+        input: &[f64],
+        allowed: impl Fn(usize) -> bool,
+    ) -> Option<(usize, f64)> {
+        masked_argmax(&self.run(input), allowed)
+    }
+
+    /// Activations of every layer, starting with the input itself.
+    fn forward(&self, input: &[f64]) -> Vec<Vec<f64>> {
+        assert_eq!(
+            input.len(),
+            self.inputs,
+            "input has a different length than the network's input layer"
+        );
+        let mut values = Vec::with_capacity(self.layers.len() + 1);
+        values.push(input.to_vec());
+        for layer in &self.layers {
+            let previous = values.last().unwrap();
+            let next = layer
+                .weights
+                .chunks_exact(layer.inputs + 1)
+                .map(|row| {
+                    let sum = row[0]
+                        + row[1..]
+                            .iter()
+                            .zip(previous)
+                            .map(|(w, x)| w * x)
+                            .sum::<f64>();
+                    layer.activation.apply(sum)
+                })
+                .collect();
+            values.push(next);
+        }
+        values
+    }
+
+    /// Trains on `examples` for `epochs` passes, updating the weights after every batch of
+    /// `config.batch_size` examples. Returns the mean squared error of the last pass.
+    pub fn train(&mut self, examples: &[(Vec<f64>, Vec<f64>)], epochs: u32) -> f64 {
+        let outputs = self.layers.last().unwrap().outputs;
+        for (input, target) in examples {
+            assert_eq!(
+                input.len(),
+                self.inputs,
+                "input has a different length than the network's input layer"
+            );
+            assert_eq!(
+                target.len(),
+                outputs,
+                "output has a different length than the network's output layer"
+            );
+        }
+
+        let mut gradients: Vec<Vec<f64>> = self
+            .layers
+            .iter()
+            .map(|l| vec![0.0; l.weights.len()])
+            .collect();
+        let mut error = 0.0;
+        for _ in 0..epochs.max(1) {
+            error = 0.0;
+            for batch in examples.chunks(self.config.batch_size.max(1)) {
+                gradients.iter_mut().for_each(|g| g.fill(0.0));
+                for (input, target) in batch {
+                    error += self.backpropagate(input, target, &mut gradients);
+                }
+                let scale = 1.0 / batch.len() as f64;
+                gradients.iter_mut().flatten().for_each(|g| *g *= scale);
+                self.update(&mut gradients);
+            }
+            error /= examples.len().max(1) as f64;
+        }
+        error
+    }
+
+    /// Adds the gradient of half the squared error on one example to `gradients` and returns
+    /// the mean squared error.
+    fn backpropagate(&self, input: &[f64], target: &[f64], gradients: &mut [Vec<f64>]) -> f64 {
+        let values = self.forward(input);
+        let output = values.last().unwrap();
+        let activation = self.layers.last().unwrap().activation;
+
+        let error = output
+            .iter()
+            .zip(target)
+            .map(|(o, t)| (t - o).powi(2))
+            .sum::<f64>()
+            / output.len() as f64;
+        let mut deltas: Vec<f64> = output
+            .iter()
+            .zip(target)
+            .map(|(o, t)| (o - t) * activation.derivative(*o))
+            .collect();
+
+        for (index, layer) in self.layers.iter().enumerate().rev() {
+            let previous = &values[index];
+            let stride = layer.inputs + 1;
+            for (row, &delta) in gradients[index].chunks_exact_mut(stride).zip(&deltas) {
+                if delta == 0.0 {
+                    continue;
+                }
+                row[0] += delta;
+                for (g, x) in row[1..].iter_mut().zip(previous) {
+                    *g += delta * x;
+                }
+            }
+
+            if index > 0 {
+                let activation = self.layers[index - 1].activation;
+                let mut next = vec![0.0; layer.inputs];
+                for (row, &delta) in layer.weights.chunks_exact(stride).zip(&deltas) {
+                    for (n, w) in next.iter_mut().zip(&row[1..]) {
+                        *n += delta * w;
+                    }
+                }
+                for (n, &y) in next.iter_mut().zip(previous) {
+                    *n *= activation.derivative(y);
+                }
+                deltas = next;
+            }
+        }
+        error
+    }
+
+    fn update(&mut self, gradients: &mut [Vec<f64>]) {
+        let config = &self.config;
+        if config.l2 > 0.0 {
+            for (layer, gradient) in self.layers.iter().zip(gradients.iter_mut()) {
+                let stride = layer.inputs + 1;
+                for (i, (g, w)) in gradient.iter_mut().zip(&layer.weights).enumerate() {
+                    if i % stride != 0 {
+                        *g += config.l2 * w;
+                    }
+                }
+            }
+        }
+
+        if let Some(clip) = config.clip {
+            let norm = gradients
+                .iter()
+                .flatten()
+                .map(|g| g * g)
+                .sum::<f64>()
+                .sqrt();
+            if norm > clip {
+                let scale = clip / norm;
+                gradients.iter_mut().flatten().for_each(|g| *g *= scale);
+            }
+        }
+
+        let rate = config.rate;
+        match config.optimizer {
+            Optimizer::Sgd { momentum: 0.0 } => {
+                for (layer, gradient) in self.layers.iter_mut().zip(gradients.iter()) {
+                    for (w, g) in layer.weights.iter_mut().zip(gradient) {
+                        *w -= rate * g;
+                    }
+                }
+            }
+            Optimizer::Sgd { momentum } => {
+                let state = fitting_state(&mut self.state, &self.layers, false);
+                for ((layer, gradient), velocity) in self
+                    .layers
+                    .iter_mut()
+                    .zip(gradients.iter())
+                    .zip(&mut state.first)
+                {
+                    for ((w, g), v) in layer.weights.iter_mut().zip(gradient).zip(velocity) {
+                        *v = momentum * *v - rate * g;
+                        *w += *v;
+                    }
+                }
+            }
+            Optimizer::Adam { beta1, beta2 } => {
+                let state = fitting_state(&mut self.state, &self.layers, true);
+                state.step += 1;
+                let correction1 = 1.0 - beta1.powi(state.step as i32);
+                let correction2 = 1.0 - beta2.powi(state.step as i32);
+                let layers = self.layers.iter_mut().zip(gradients.iter());
+                for ((layer, gradient), (first, second)) in
+                    layers.zip(state.first.iter_mut().zip(&mut state.second))
+                {
+                    for (((w, g), m), v) in layer
+                        .weights
+                        .iter_mut()
+                        .zip(gradient)
+                        .zip(first)
+                        .zip(second)
+                    {
+                        *m = beta1 * *m + (1.0 - beta1) * g;
+                        *v = beta2 * *v + (1.0 - beta2) * g * g;
+                        *w -= rate * (*m / correction1) / ((*v / correction2).sqrt() + 1e-8);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn to_bytes(&self, with_state: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        put_u32(&mut out, FORMAT_VERSION);
+        put_u32(&mut out, self.inputs as u32);
+        put_u32(&mut out, self.layers.len() as u32);
+        for layer in &self.layers {
+            put_u32(&mut out, layer.outputs as u32);
+            out.push(
+                Activation::ALL
+                    .iter()
+                    .position(|&a| a == layer.activation)
+                    .unwrap() as u8,
+            );
+        }
+
+        let config = &self.config;
+        let (kind, a, b) = match config.optimizer {
+            Optimizer::Sgd { momentum } => (0, momentum, 0.0),
+            Optimizer::Adam { beta1, beta2 } => (1, beta1, beta2),
+        };
+        out.push(kind);
+        put_f64(&mut out, config.rate);
+        put_f64(&mut out, a);
+        put_f64(&mut out, b);
+        put_u32(&mut out, config.batch_size as u32);
+        put_f64(&mut out, config.l2);
+        put_f64(&mut out, config.clip.unwrap_or(0.0));
+
+        for layer in &self.layers {
+            layer.weights.iter().for_each(|&w| put_f64(&mut out, w));
+        }
+
+        match self.state.as_ref().filter(|_| with_state) {
+            Some(state) => {
+                out.push(1);
+                out.extend_from_slice(&state.step.to_le_bytes());
+                for buffer in state.first.iter().chain(&state.second) {
+                    buffer.iter().for_each(|&x| put_f64(&mut out, x));
+                }
+            }
+            None => out.push(0),
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Network, String> {
+        let mut reader = ByteReader { bytes, position: 0 };
+        if reader.take(4)? != MAGIC {
+            return Err("not a network file".to_owned());
+        }
+        let version = reader.u32()?;
+        if version > FORMAT_VERSION {
+            return Err(format!("network format version {version} is not supported"));
+        }
+
+        let inputs = reader.u32()? as usize;
+        let count = reader.u32()? as usize;
+        // Every layer takes at least 5 bytes, the count must not be trusted before that
+        if count > reader.remaining() / 5 {
+            return Err("unexpected end of network data".to_owned());
+        }
+        let mut layers = Vec::with_capacity(count);
+        let mut previous = inputs;
+        for _ in 0..count {
+            let outputs = reader.u32()? as usize;
+            let activation = *Activation::ALL
+                .get(reader.u8()? as usize)
+                .ok_or("unknown activation")?;
+            layers.push(Layer {
+                inputs: previous,
+                outputs,
+                activation,
+                weights: vec![],
+            });
+            previous = outputs;
+        }
+        if layers.is_empty() {
+            return Err("network has no layers".to_owned());
+        }
+
+        let kind = reader.u8()?;
+        let rate = reader.f64()?;
+        let (a, b) = (reader.f64()?, reader.f64()?);
+        let optimizer = match kind {
+            0 => Optimizer::Sgd { momentum: a },
+            1 => Optimizer::Adam { beta1: a, beta2: b },
+            _ => return Err("unknown optimizer".to_owned()),
+        };
+        let batch_size = reader.u32()? as usize;
+        let l2 = reader.f64()?;
+        let clip = Some(reader.f64()?).filter(|&clip| clip > 0.0);
+
+        for layer in &mut layers {
+            let weights = layer
+                .outputs
+                .checked_mul(layer.inputs + 1)
+                .ok_or("unexpected end of network data")?;
+            layer.weights = reader.f64s(weights)?;
+        }
+
+        let state = if reader.u8()? == 1 {
+            let step = u64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+            let mut state = OptimizerState {
+                step,
+                first: vec![],
+                second: vec![],
+            };
+            for layer in &layers {
+                state.first.push(reader.f64s(layer.weights.len())?);
+            }
+            if let Optimizer::Adam { .. } = optimizer {
+                for layer in &layers {
+                    state.second.push(reader.f64s(layer.weights.len())?);
+                }
+            }
+            Some(state)
+        } else {
+            None
+        };
+
+        Ok(Network {
+            inputs,
+            layers,
+            config: TrainingConfig {
+                optimizer,
+                rate,
+                batch_size,
+                l2,
+                clip,
+            },
+            state,
+        })
+    }
+
+    /// Imports the json written by the nn crate, `{"layers":[[[bias, weights...]]],"num_inputs":n}`.
+    /// All layers of those networks use the sigmoid activation.
+    pub fn from_nn_json(json: &str) -> Result<Network, String> {
+        let mut parser = JsonParser {
+            bytes: json.as_bytes(),
+            position: 0,
+        };
+        let value = parser.value()?;
+        let fields = match value {
+            Json::Object(fields) => fields,
+            _ => return Err("expected a json object".to_owned()),
+        };
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value)
+                .ok_or_else(|| format!("missing field '{name}'"))
+        };
+
+        let inputs = match field("num_inputs")? {
+            Json::Number(n) => *n as usize,
+            _ => return Err("num_inputs must be a number".to_owned()),
+        };
+        let mut layers = Vec::new();
+        let mut previous = inputs;
+        for layer in field("layers")?.as_array()? {
+            let nodes = layer.as_array()?;
+            let mut weights = Vec::with_capacity(nodes.len() * (previous + 1));
+            for node in nodes {
+                let node = node.as_array()?;
+                if node.len() != previous + 1 {
+                    return Err(format!(
+                        "expected {} weights per node but found {}",
+                        previous + 1,
+                        node.len()
+                    ));
+                }
+                for weight in node {
+                    weights.push(weight.as_number()?);
+                }
+            }
+            layers.push(Layer {
+                inputs: previous,
+                outputs: nodes.len(),
+                activation: Activation::Sigmoid,
+                weights,
+            });
+            previous = nodes.len();
+        }
+        if layers.is_empty() {
+            return Err("network has no layers".to_owned());
+        }
+
+        Ok(Network {
+            inputs,
+            layers,
+            config: TrainingConfig::default(),
+            state: None,
+        })
+    }
+}
+
+/// Index and value of the highest of `values` whose index `allowed` admits, the first one on
+/// ties. NaN values only win if every admitted value is NaN, `None` if nothing is admitted.
+pub fn masked_argmax(values: &[f64], allowed: impl Fn(usize) -> bool) -> Option<(usize, f64)> {
+    let admitted = || {
+        values
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|&(i, _)| allowed(i))
+    };
+    admitted()
+        .filter(|(_, value)| !value.is_nan())
+        .reduce(|best, x| {
+            if x.1.total_cmp(&best.1).is_gt() {
+                x
+            } else {
+                best
+            }
+        })
+        .or_else(|| admitted().next())
+}
+
+/// Returns the optimiser state, resetting it when it does not fit the optimiser.
+fn fitting_state<'a>(
+    state: &'a mut Option<OptimizerState>,
+    layers: &[Layer],
+    second_moment: bool,
+) -> &'a mut OptimizerState {
+    let fits = state
+        .as_ref()
+        .is_some_and(|state| state.second.is_empty() != second_moment);
+    if !fits {
+        let zeros = || layers.iter().map(|l| vec![0.0; l.weights.len()]).collect();
+        *state = Some(OptimizerState {
+            step: 0,
+            first: zeros(),
+            second: if second_moment { zeros() } else { vec![] },
+        });
+    }
+    state.as_mut().unwrap()
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_f64(out: &mut Vec<u8>, value: f64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, amount: usize) -> Result<&'a [u8], String> {
+        let slice = self
+            .position
+            .checked_add(amount)
+            .and_then(|end| self.bytes.get(self.position..end))
+            .ok_or("unexpected end of network data")?;
+        self.position += amount;
+        Ok(slice)
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f64s(&mut self, amount: usize) -> Result<Vec<f64>, String> {
+        let bytes = amount
+            .checked_mul(8)
+            .ok_or("unexpected end of network data")?;
+        Ok(self
+            .take(bytes)?
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes(b.try_into().unwrap()))
+            .collect())
+    }
+}
+
+/// Just enough json to read the files of the nn crate.
+enum Json {
+    Number(f64),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn as_array(&self) -> Result<&[Json], String> {
+        match self {
+            Json::Array(values) => Ok(values),
+            _ => Err("expected a json array".to_owned()),
+        }
+    }
+
+    fn as_number(&self) -> Result<f64, String> {
+        match self {
+            Json::Number(n) => Ok(*n),
+            _ => Err("expected a number".to_owned()),
+        }
+    }
+}
+
+struct JsonParser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl JsonParser<'_> {
+    fn peek(&mut self) -> Option<u8> {
+        while self
+            .bytes
+            .get(self.position)
+            .is_some_and(|b| b.is_ascii_whitespace())
+        {
+            self.position += 1;
+        }
+        self.bytes.get(self.position).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.peek() != Some(byte) {
+            return Err(format!(
+                "expected '{}' at offset {}",
+                byte as char, self.position
+            ));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some(b'[') => {
+                self.position += 1;
+                let mut values = Vec::new();
+                if self.peek() == Some(b']') {
+                    self.position += 1;
+                    return Ok(Json::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    match self.peek() {
+                        Some(b',') => self.position += 1,
+                        _ => break,
+                    }
+                }
+                self.expect(b']')?;
+                Ok(Json::Array(values))
+            }
+            Some(b'{') => {
+                self.position += 1;
+                let mut fields = Vec::new();
+                if self.peek() == Some(b'}') {
+                    self.position += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    fields.push((key, self.value()?));
+                    match self.peek() {
+                        Some(b',') => self.position += 1,
+                        _ => break,
+                    }
+                }
+                self.expect(b'}')?;
+                Ok(Json::Object(fields))
+            }
+            Some(_) => {
+                let start = self.position;
+                while self
+                    .bytes
+                    .get(self.position)
+                    .is_some_and(|b| b.is_ascii_digit() || b"+-.eE".contains(b))
+                {
+                    self.position += 1;
+                }
+                let text = std::str::from_utf8(&self.bytes[start..self.position]).unwrap();
+                text.parse()
+                    .map(Json::Number)
+                    .map_err(|_| format!("invalid number at offset {start}"))
+            }
+            None => Err("unexpected end of json".to_owned()),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let start = self.position;
+        while self.bytes.get(self.position).is_some_and(|&b| b != b'"') {
+            self.position += 1;
+        }
+        let text = String::from_utf8_lossy(&self.bytes[start..self.position]).into_owned();
+        self.expect(b'"')?;
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    fn network() -> Network {
+        let mut rng = ChaCha8Rng::seed_from_u64(7);
+        let mut network =
+            Network::random(&[3, 4, 2], Activation::Relu, Activation::Sigmoid, &mut rng);
+        network.config = TrainingConfig {
+            optimizer: Optimizer::Adam {
+                beta1: 0.9,
+                beta2: 0.999,
+            },
+            rate: 0.01,
+            batch_size: 2,
+            l2: 0.001,
+            clip: Some(1.5),
+        };
+        network
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut network = network();
+        network.train(&[(vec![0.1, 0.2, 0.3], vec![1.0, 0.0])], 2);
+        for with_state in [false, true] {
+            let bytes = network.to_bytes(with_state);
+            let read = Network::from_bytes(&bytes).unwrap();
+            assert_eq!(read.to_bytes(with_state), bytes);
+            assert_eq!(read.shape(), vec![3, 4, 2]);
+            assert_eq!(read.config.clip, Some(1.5));
+            assert_eq!(read.state.is_some(), with_state);
+        }
+    }
+
+    #[test]
+    fn from_bytes_rejects_invalid_data() {
+        let bytes = network().to_bytes(false);
+        assert!(Network::from_bytes(b"NOPE").is_err());
+        assert!(Network::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut huge_count = bytes[..12].to_vec();
+        huge_count.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Network::from_bytes(&huge_count).is_err());
+
+        let mut huge_layer = bytes[..16].to_vec();
+        huge_layer.extend_from_slice(&u32::MAX.to_le_bytes());
+        huge_layer.push(0);
+        huge_layer.extend_from_slice(&bytes[21..]);
+        assert!(Network::from_bytes(&huge_layer).is_err());
+    }
+
+    #[test]
+    fn masked_argmax_skips_masked_and_nan_values() {
+        let values = [0.9, 0.5, f64::NAN, 0.7, 0.7];
+        assert_eq!(masked_argmax(&values, |_| true), Some((0, 0.9)));
+        assert_eq!(masked_argmax(&values, |i| i != 0), Some((3, 0.7)));
+        assert_eq!(masked_argmax(&values, |i| i == 1 || i == 2), Some((1, 0.5)));
+        assert_eq!(masked_argmax(&values, |_| false), None);
+
+        let (line, value) = masked_argmax(&[f64::NAN, f64::NAN], |i| i == 1).unwrap();
+        assert!(line == 1 && value.is_nan());
+    }
+}
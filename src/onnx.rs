@@ -0,0 +1,647 @@
+/*
+    ONNX export and import of model networks. The protobuf encoding is written by hand since
+    only a handful of messages are needed. An exported network is a chain of Gemm nodes,
+    each followed by its activation unless it is linear:
+
+        input  "board"    float [batch, inputs]
+        Gemm   layer0.weight [outputs, inputs] (transB), layer0.bias [outputs]
+        Sigmoid / Tanh / Relu / LeakyRelu (alpha 0.01)
+        ...
+        output "values"   float [batch, outputs]
+
+    Weights are stored as 32 bit floats, so a network that went through ONNX matches the
+    original only up to float precision. The model metadata props hold the model header
+    (`borderpatrol_header`), which is used to restore the metadata on import, and plain
+    text descriptions of the input encoding and the outputs for other consumers.
+*/
+use std::fs;
+
+use crate::{
+    alphazero, ml,
+    model::{ModelError, ModelMetadata, MODEL_FORMAT_VERSION},
+    network::{Activation, DenseLayer, Network},
+};
+
+const IR_VERSION: u64 = 7;
+const OPSET_VERSION: u64 = 13;
+const INPUT_NAME: &str = "board";
+const OUTPUT_NAME: &str = "values";
+const HEADER_PROP: &str = "borderpatrol_header";
+const LEAKY_RELU_ALPHA: f32 = 0.01;
+
+// TensorProto.DataType and AttributeProto.AttributeType values
+const FLOAT: u64 = 1;
+const DOUBLE: u64 = 11;
+const ATTRIBUTE_FLOAT: u64 = 1;
+const ATTRIBUTE_INT: u64 = 2;
+
+pub fn export(
+    file_path: &str,
+    metadata: &ModelMetadata,
+    network: &Network,
+) -> Result<(), ModelError> {
+    let shape = network.shape();
+    let (inputs, outputs) = (shape[0] as u64, *shape.last().unwrap() as u64);
+    let (encoding, output) = descriptions(&metadata.encoder);
+
+    let mut graph = Message::new();
+    let mut previous = INPUT_NAME.to_owned();
+    let layers = network.dense_layers();
+    for (i, layer) in layers.iter().enumerate() {
+        let name = format!("layer{i}");
+        let last = i == layers.len() - 1;
+        let gemm_output = match (layer.activation, last) {
+            (Activation::Linear, true) => OUTPUT_NAME.to_owned(),
+            (Activation::Linear, false) => format!("{name}.output"),
+            _ => format!("{name}.linear"),
+        };
+
+        let (weight, bias) = (format!("{name}.weight"), format!("{name}.bias"));
+        let mut gemm = node(
+            &format!("{name}.gemm"),
+            "Gemm",
+            &[&previous, &weight, &bias],
+            &gemm_output,
+        );
+        let mut trans_b = Message::new();
+        trans_b.string(1, "transB");
+        trans_b.varint(3, 1);
+        trans_b.varint(20, ATTRIBUTE_INT);
+        gemm.message(5, &trans_b);
+        graph.message(1, &gemm);
+
+        previous = gemm_output;
+        if let Some(op_type) = op_type(layer.activation) {
+            let activated = if last {
+                OUTPUT_NAME.to_owned()
+            } else {
+                format!("{name}.output")
+            };
+            let mut activation = node(
+                &format!("{name}.activation"),
+                op_type,
+                &[&previous],
+                &activated,
+            );
+            if layer.activation == Activation::LeakyRelu {
+                let mut alpha = Message::new();
+                alpha.string(1, "alpha");
+                alpha.fixed32(2, LEAKY_RELU_ALPHA.to_bits());
+                alpha.varint(20, ATTRIBUTE_FLOAT);
+                activation.message(5, &alpha);
+            }
+            graph.message(1, &activation);
+            previous = activated;
+        }
+    }
+
+    graph.string(2, "borderpatrol");
+    for (i, layer) in layers.iter().enumerate() {
+        let rows = layer.bias.len() as u64;
+        let columns = layer.weights.len() as u64 / rows;
+        graph.message(
+            5,
+            &tensor(
+                &format!("layer{i}.weight"),
+                &[rows, columns],
+                &layer.weights,
+            ),
+        );
+        graph.message(5, &tensor(&format!("layer{i}.bias"), &[rows], &layer.bias));
+    }
+    graph.message(11, &value_info(INPUT_NAME, inputs, encoding));
+    graph.message(12, &value_info(OUTPUT_NAME, outputs, output));
+
+    let mut opset = Message::new();
+    opset.string(1, "");
+    opset.varint(2, OPSET_VERSION);
+
+    let mut onnx = Message::new();
+    onnx.varint(1, IR_VERSION);
+    onnx.string(2, "borderpatrol-terminal");
+    onnx.string(3, env!("CARGO_PKG_VERSION"));
+    onnx.string(
+        6,
+        &format!(
+            "{} agent, input encoding {}",
+            metadata.agent, metadata.encoder
+        ),
+    );
+    onnx.message(7, &graph);
+    onnx.message(8, &opset);
+    for (key, value) in [
+        (HEADER_PROP, metadata.to_header().as_str()),
+        ("encoder", &metadata.encoder),
+        ("input_encoding", encoding),
+        ("outputs", output),
+    ] {
+        let mut prop = Message::new();
+        prop.string(1, key);
+        prop.string(2, value);
+        onnx.message(14, &prop);
+    }
+
+    fs::write(file_path, onnx.bytes).map_err(|err| ModelError::Io(file_path.to_owned(), err))
+}
+
+/// Reads a network exported by `export`. Other chains of Gemm nodes and activations are
+/// accepted too, their metadata is that of an untrained dqn model.
+pub fn import(file_path: &str) -> Result<(ModelMetadata, Network), ModelError> {
+    let bytes = fs::read(file_path).map_err(|err| ModelError::Io(file_path.to_owned(), err))?;
+    let format_err = |msg: String| ModelError::Format(format!("{file_path}: {msg}"));
+    let onnx = fields(&bytes).map_err(format_err)?;
+
+    let mut metadata = None;
+    for prop in messages(&onnx, 14).map_err(format_err)? {
+        if string(&prop, 1) == HEADER_PROP {
+            metadata = Some(ModelMetadata::from_header(&string(&prop, 2))?);
+        }
+    }
+    let graph = messages(&onnx, 7)
+        .map_err(format_err)?
+        .pop()
+        .ok_or_else(|| format_err("missing graph".to_owned()))?;
+    let network = read_graph(&graph).map_err(format_err)?;
+
+    let mut metadata = match metadata {
+        Some(metadata) => metadata,
+        None => ModelMetadata::from_header(&format!("borderpatrol-model {MODEL_FORMAT_VERSION}"))?,
+    };
+    metadata.version = MODEL_FORMAT_VERSION;
+    metadata.shape = network.shape();
+    Ok((metadata, network))
+}
+
+fn descriptions(encoder: &str) -> (&'static str, &'static str) {
+    match encoder {
+        alphazero::ENCODER => (
+            alphazero::ENCODER_DESCRIPTION,
+            alphazero::OUTPUT_DESCRIPTION,
+        ),
+        ml::ENCODER => (ml::ENCODER_DESCRIPTION, ml::OUTPUT_DESCRIPTION),
+        _ => ("unknown encoding", "unknown outputs"),
+    }
+}
+
+fn op_type(activation: Activation) -> Option<&'static str> {
+    match activation {
+        Activation::Sigmoid => Some("Sigmoid"),
+        Activation::Tanh => Some("Tanh"),
+        Activation::Relu => Some("Relu"),
+        Activation::LeakyRelu => Some("LeakyRelu"),
+        Activation::Linear => None,
+    }
+}
+
+fn node(name: &str, op_type: &str, inputs: &[&str], output: &str) -> Message {
+    let mut node = Message::new();
+    for input in inputs {
+        node.string(1, input);
+    }
+    node.string(2, output);
+    node.string(3, name);
+    node.string(4, op_type);
+    node
+}
+
+fn tensor(name: &str, dims: &[u64], values: &[f64]) -> Message {
+    let mut tensor = Message::new();
+    for &dim in dims {
+        tensor.varint(1, dim);
+    }
+    tensor.varint(2, FLOAT);
+    tensor.string(8, name);
+    let raw: Vec<u8> = values
+        .iter()
+        .flat_map(|&x| (x as f32).to_le_bytes())
+        .collect();
+    tensor.bytes_field(9, &raw);
+    tensor
+}
+
+/// A float tensor of shape [batch, size].
+fn value_info(name: &str, size: u64, doc: &str) -> Message {
+    let mut batch = Message::new();
+    batch.string(2, "batch");
+    let mut features = Message::new();
+    features.varint(1, size);
+    let mut shape = Message::new();
+    shape.message(1, &batch);
+    shape.message(1, &features);
+
+    let mut tensor_type = Message::new();
+    tensor_type.varint(1, FLOAT);
+    tensor_type.message(2, &shape);
+    let mut type_proto = Message::new();
+    type_proto.message(1, &tensor_type);
+
+    let mut info = Message::new();
+    info.string(1, name);
+    info.message(2, &type_proto);
+    info.string(3, doc);
+    info
+}
+
+fn read_graph(graph: &[(u64, Field)]) -> Result<Network, String> {
+    let mut initializers = Vec::new();
+    for tensor in messages(graph, 5)? {
+        initializers.push((string(&tensor, 8), read_tensor(&tensor)?));
+    }
+    let initializer = |name: &str| {
+        initializers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, tensor)| tensor)
+            .ok_or_else(|| format!("missing initializer '{name}'"))
+    };
+
+    let input = messages(graph, 11)?
+        .into_iter()
+        .map(|info| string(&info, 1))
+        .find(|name| initializers.iter().all(|(n, _)| n != name))
+        .ok_or("missing graph input")?;
+
+    let mut inputs = None;
+    let mut layers: Vec<DenseLayer> = Vec::new();
+    let mut current = input;
+    for node in messages(graph, 1)? {
+        let node_inputs = strings(&node, 1);
+        let node_output = strings(&node, 2).pop().ok_or("node without output")?;
+        let op_type = string(&node, 4);
+        if node_inputs.first() != Some(&current) {
+            return Err(format!(
+                "{op_type} node does not continue the chain of layers"
+            ));
+        }
+
+        let attributes = messages(&node, 5)?;
+        let attribute = |name: &str| attributes.iter().find(|a| string(a, 1) == name);
+        let int = |name: &str| attribute(name).and_then(|a| varint(a, 3)).unwrap_or(0);
+        let float = |name: &str, default: f32| {
+            attribute(name)
+                .and_then(|a| fixed32(a, 2))
+                .map_or(default, f32::from_bits)
+        };
+
+        let activation = match op_type.as_str() {
+            "Gemm" => {
+                if int("transA") != 0 || float("alpha", 1.0) != 1.0 || float("beta", 1.0) != 1.0 {
+                    return Err(
+                        "only Gemm nodes without transA, alpha and beta are supported".to_owned(),
+                    );
+                }
+                let (dims, weights) =
+                    initializer(node_inputs.get(1).ok_or("Gemm without weights")?)?;
+                let (_, bias) = initializer(node_inputs.get(2).ok_or("Gemm without bias")?)?;
+                let [rows, columns] = dims[..] else {
+                    return Err("Gemm weights are not a matrix".to_owned());
+                };
+                let (rows, columns) = (rows as usize, columns as usize);
+                let weights = if int("transB") != 0 {
+                    weights.clone()
+                } else {
+                    // Stored as [inputs, outputs], turn it into one row per output
+                    (0..columns)
+                        .flat_map(|output| {
+                            (0..rows).map(move |input| weights[input * columns + output])
+                        })
+                        .collect()
+                };
+                let outputs = bias.len();
+                inputs.get_or_insert(weights.len() / outputs.max(1));
+                layers.push(DenseLayer {
+                    weights,
+                    bias: bias.clone(),
+                    activation: Activation::Linear,
+                });
+                None
+            }
+            "Sigmoid" => Some(Activation::Sigmoid),
+            "Tanh" => Some(Activation::Tanh),
+            "Relu" => Some(Activation::Relu),
+            "LeakyRelu" if float("alpha", LEAKY_RELU_ALPHA) == LEAKY_RELU_ALPHA => {
+                Some(Activation::LeakyRelu)
+            }
+            "LeakyRelu" => {
+                return Err(format!(
+                    "LeakyRelu is only supported with alpha {LEAKY_RELU_ALPHA}"
+                ))
+            }
+            "Identity" => Some(Activation::Linear),
+            other => return Err(format!("unsupported operator {other}")),
+        };
+        if let Some(activation) = activation {
+            let layer = layers
+                .last_mut()
+                .ok_or("activation before the first Gemm node")?;
+            if layer.activation != Activation::Linear {
+                return Err("two activations after one Gemm node".to_owned());
+            }
+            layer.activation = activation;
+        }
+        current = node_output;
+    }
+
+    Network::from_dense_layers(inputs.ok_or("graph has no Gemm nodes")?, layers)
+}
+
+fn read_tensor(tensor: &[(u64, Field)]) -> Result<(Vec<u64>, Vec<f64>), String> {
+    let mut dims = Vec::new();
+    for (number, field) in tensor {
+        match (number, field) {
+            (1, Field::Varint(dim)) => dims.push(*dim),
+            (1, Field::Bytes(packed)) => dims.extend(packed_varints(packed)?),
+            _ => {}
+        }
+    }
+    let data_type = varint(tensor, 2).unwrap_or(FLOAT);
+
+    let mut values = Vec::new();
+    for (number, field) in tensor {
+        match (data_type, number, field) {
+            (FLOAT, 9, Field::Bytes(raw)) | (FLOAT, 4, Field::Bytes(raw)) => values.extend(
+                raw.chunks_exact(4)
+                    .map(|x| f32::from_le_bytes(x.try_into().unwrap()) as f64),
+            ),
+            (FLOAT, 4, Field::Fixed32(x)) => values.push(f32::from_bits(*x) as f64),
+            (DOUBLE, 9, Field::Bytes(raw)) | (DOUBLE, 10, Field::Bytes(raw)) => values.extend(
+                raw.chunks_exact(8)
+                    .map(|x| f64::from_le_bytes(x.try_into().unwrap())),
+            ),
+            (DOUBLE, 10, Field::Fixed64(x)) => values.push(f64::from_bits(*x)),
+            (FLOAT | DOUBLE, _, _) => {}
+            _ => {
+                return Err(format!(
+                    "tensor '{}' is neither float nor double",
+                    string(tensor, 8)
+                ))
+            }
+        }
+    }
+    let size = dims
+        .iter()
+        .try_fold(1u64, |size, &dim| size.checked_mul(dim));
+    if size != Some(values.len() as u64) {
+        return Err(format!(
+            "tensor '{}' does not match its dimensions",
+            string(tensor, 8)
+        ));
+    }
+    Ok((dims, values))
+}
+
+/// A protobuf message being written.
+struct Message {
+    bytes: Vec<u8>,
+}
+
+impl Message {
+    fn new() -> Message {
+        Message { bytes: Vec::new() }
+    }
+
+    fn key(&mut self, number: u64, wire_type: u64) {
+        put_varint(&mut self.bytes, number << 3 | wire_type);
+    }
+
+    fn varint(&mut self, number: u64, value: u64) {
+        self.key(number, 0);
+        put_varint(&mut self.bytes, value);
+    }
+
+    fn fixed32(&mut self, number: u64, value: u32) {
+        self.key(number, 5);
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    fn bytes_field(&mut self, number: u64, value: &[u8]) {
+        self.key(number, 2);
+        put_varint(&mut self.bytes, value.len() as u64);
+        self.bytes.extend(value);
+    }
+
+    fn string(&mut self, number: u64, value: &str) {
+        self.bytes_field(number, value.as_bytes());
+    }
+
+    fn message(&mut self, number: u64, value: &Message) {
+        self.bytes_field(number, &value.bytes);
+    }
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+#[derive(Debug, Clone)]
+enum Field<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = *self.bytes.get(self.position).ok_or("truncated varint")?;
+            self.position += 1;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                return Ok(value);
+            }
+        }
+        Err("varint is too long".to_owned())
+    }
+
+    fn take(&mut self, amount: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .position
+            .checked_add(amount)
+            .filter(|&end| end <= self.bytes.len());
+        let bytes = &self.bytes[self.position..end.ok_or("truncated field")?];
+        self.position += amount;
+        Ok(bytes)
+    }
+}
+
+/// Splits a message into its fields, sub messages are left as bytes.
+fn fields(bytes: &[u8]) -> Result<Vec<(u64, Field<'_>)>, String> {
+    let mut reader = Reader { bytes, position: 0 };
+    let mut fields = Vec::new();
+    while reader.position < bytes.len() {
+        let key = reader.varint()?;
+        let field = match key & 7 {
+            0 => Field::Varint(reader.varint()?),
+            1 => Field::Fixed64(u64::from_le_bytes(reader.take(8)?.try_into().unwrap())),
+            2 => {
+                let length = reader.varint()? as usize;
+                Field::Bytes(reader.take(length)?)
+            }
+            5 => Field::Fixed32(u32::from_le_bytes(reader.take(4)?.try_into().unwrap())),
+            other => return Err(format!("unsupported wire type {other}")),
+        };
+        fields.push((key >> 3, field));
+    }
+    Ok(fields)
+}
+
+fn messages<'a>(
+    fields_of: &[(u64, Field<'a>)],
+    number: u64,
+) -> Result<Vec<Vec<(u64, Field<'a>)>>, String> {
+    fields_of
+        .iter()
+        .filter(|(n, _)| *n == number)
+        .map(|(_, field)| match field {
+            Field::Bytes(bytes) => fields(bytes),
+            _ => Err(format!("field {number} is not a message")),
+        })
+        .collect()
+}
+
+fn strings(fields_of: &[(u64, Field)], number: u64) -> Vec<String> {
+    fields_of
+        .iter()
+        .filter_map(|(n, field)| match field {
+            Field::Bytes(bytes) if *n == number => {
+                Some(String::from_utf8_lossy(bytes).into_owned())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn string(fields_of: &[(u64, Field)], number: u64) -> String {
+    strings(fields_of, number).pop().unwrap_or_default()
+}
+
+fn varint(fields_of: &[(u64, Field)], number: u64) -> Option<u64> {
+    fields_of.iter().rev().find_map(|(n, field)| match field {
+        Field::Varint(value) if *n == number => Some(*value),
+        _ => None,
+    })
+}
+
+fn fixed32(fields_of: &[(u64, Field)], number: u64) -> Option<u32> {
+    fields_of.iter().rev().find_map(|(n, field)| match field {
+        Field::Fixed32(value) if *n == number => Some(*value),
+        _ => None,
+    })
+}
+
+fn packed_varints(bytes: &[u8]) -> Result<Vec<u64>, String> {
+    let mut reader = Reader { bytes, position: 0 };
+    let mut values = Vec::new();
+    while reader.position < bytes.len() {
+        values.push(reader.varint()?);
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::{
+        ml::{BorderPatrolAgent, HyperParameters},
+        rng,
+    };
+
+    const ACTIVATIONS: [Activation; 5] = [
+        Activation::Sigmoid,
+        Activation::Tanh,
+        Activation::Relu,
+        Activation::LeakyRelu,
+        Activation::Linear,
+    ];
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("borderpatrol-{}-{name}", std::process::id()));
+        path.to_str().unwrap().to_owned()
+    }
+
+    fn import_bytes(name: &str, bytes: &[u8]) -> Result<(ModelMetadata, Network), ModelError> {
+        let path = temp_path(name);
+        fs::write(&path, bytes).unwrap();
+        let result = import(&path);
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    fn assert_close(read: &Network, network: &Network) {
+        assert_eq!(read.shape(), network.shape());
+        assert_eq!(read.activations(), network.activations());
+        for (read, layer) in read.dense_layers().iter().zip(network.dense_layers()) {
+            let read_values = read.weights.iter().chain(&read.bias);
+            for (x, y) in read_values.zip(layer.weights.iter().chain(&layer.bias)) {
+                assert!((x - y).abs() <= 1e-6 * y.abs().max(1.0), "{x} is not {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn export_import_round_trip() {
+        for hidden in ACTIVATIONS {
+            for output in ACTIVATIONS {
+                let hyper_parms = HyperParameters {
+                    seed: 4,
+                    ..Default::default()
+                };
+                let agent = BorderPatrolAgent::new_with_activations(
+                    &[100, 3, 200],
+                    hidden,
+                    output,
+                    hyper_parms,
+                );
+                let metadata = agent.metadata();
+                let path = temp_path("round-trip.onnx");
+                export(&path, &metadata, &agent.nn).unwrap();
+                let (read_metadata, network) = import(&path).unwrap();
+                fs::remove_file(&path).unwrap();
+
+                assert_close(&network, &agent.nn);
+                assert_eq!(read_metadata.to_header(), metadata.to_header());
+            }
+        }
+    }
+
+    #[test]
+    fn import_rejects_truncated_and_garbage_bytes() {
+        let agent = BorderPatrolAgent::new(&[100, 3, 200]);
+        let path = temp_path("truncated.onnx");
+        export(&path, &agent.metadata(), &agent.nn).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // Cutting the metadata props off leaves a complete network, anything else fails
+        for end in 0..bytes.len() {
+            match import_bytes("truncated.onnx", &bytes[..end]) {
+                Ok((_, network)) => assert_close(&network, &agent.nn),
+                Err(ModelError::Format(_)) => {}
+                Err(err) => panic!("unexpected error for {end} bytes: {err}"),
+            }
+        }
+
+        let mut rng = rng::seeded(0, rng::EVALUATION);
+        for _ in 0..200 {
+            let length = rng.gen_range(0..256);
+            let garbage: Vec<u8> = (0..length).map(|_| rng.gen()).collect();
+            assert!(matches!(
+                import_bytes("garbage.onnx", &garbage),
+                Err(ModelError::Format(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,351 @@
+#![allow(dead_code)]
+/*
+    Recorded games and solver labelled positions, both plain text.
+
+    A game record lists the lines in the order they were drawn:
+
+        borderpatrol-game 1
+        player_one = advanced
+        player_two = random
+        score = 54-46
+        moves
+        12
+        57
+        ...
+
+    Games played on a clock can end early, `lost_on_time = player_one` (or player_two) before
+    the moves names the player who ran out of time and lost whatever the score.
+
+    A positions file holds one position per line, given by the lines drawn from the start,
+    followed by the exact value of every legal line for the player to move. Values are the
+    boxes the player to move wins minus the boxes the opponent wins for the rest of the game:
+
+        borderpatrol-positions 1
+        12 57 33 ...;14=3 15=-1 ...
+*/
+use std::{
+    cmp::Ordering,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    borderpatrol::BorderPatrol,
+    model::{parse_value, ModelError},
+};
+
+const GAME_MAGIC: &str = "borderpatrol-game";
+const POSITIONS_MAGIC: &str = "borderpatrol-positions";
+const FORMAT_VERSION: u32 = 1;
+const MOVES_MARKER: &str = "moves";
+
+#[derive(Debug, Clone, Default)]
+pub struct GameRecord {
+    pub player_one: String,
+    pub player_two: String,
+    pub score: [u8; 2],
+    pub moves: Vec<usize>,
+    /// The player who lost on time, 0 for player one and 1 for player two.
+    pub lost_on_time: Option<usize>,
+}
+
+const PLAYER_KEYS: [&str; 2] = ["player_one", "player_two"];
+
+impl GameRecord {
+    pub fn write(&self, file_path: &Path) -> Result<(), ModelError> {
+        let io_err = |err| ModelError::Io(file_path.display().to_string(), err);
+        let mut file = BufWriter::new(File::create(file_path).map_err(io_err)?);
+        let mut content = format!("{GAME_MAGIC} {FORMAT_VERSION}\n");
+        content.push_str(&format!("player_one = {}\n", self.player_one));
+        content.push_str(&format!("player_two = {}\n", self.player_two));
+        content.push_str(&format!("score = {}-{}\n", self.score[0], self.score[1]));
+        if let Some(player) = self.lost_on_time {
+            content.push_str(&format!("lost_on_time = {}\n", PLAYER_KEYS[player]));
+        }
+        content.push_str(&format!("{MOVES_MARKER}\n"));
+        for line in &self.moves {
+            content.push_str(&format!("{line}\n"));
+        }
+        file.write_all(content.as_bytes())
+            .and_then(|_| file.flush())
+            .map_err(io_err)
+    }
+
+    pub fn read(file_path: &Path) -> Result<GameRecord, ModelError> {
+        let name = file_path.display().to_string();
+        let content =
+            fs::read_to_string(file_path).map_err(|err| ModelError::Io(name.clone(), err))?;
+        let mut lines = content.lines();
+        check_magic(lines.next(), GAME_MAGIC, &name)?;
+
+        let mut record = GameRecord::default();
+        for line in lines.by_ref() {
+            if line.trim() == MOVES_MARKER {
+                break;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => {
+                    return Err(ModelError::Format(format!(
+                        "unexpected line '{line}' in {name}"
+                    )))
+                }
+            };
+            match key {
+                "player_one" => record.player_one = value.to_owned(),
+                "player_two" => record.player_two = value.to_owned(),
+                "score" => {
+                    let (one, two) = value.split_once('-').ok_or_else(|| {
+                        ModelError::Format(format!("invalid score '{value}' in {name}"))
+                    })?;
+                    record.score = [parse_value(key, one)?, parse_value(key, two)?];
+                }
+                "lost_on_time" => {
+                    let player = PLAYER_KEYS.iter().position(|&player| player == value);
+                    record.lost_on_time = Some(player.ok_or_else(|| {
+                        ModelError::Format(format!("invalid player '{value}' in {name}"))
+                    })?);
+                }
+                _ => {}
+            }
+        }
+
+        record.moves = lines
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| parse_value("move", line))
+            .collect::<Result<_, _>>()?;
+        replay(&record.moves).map_err(|msg| ModelError::Format(format!("{msg} in {name}")))?;
+        Ok(record)
+    }
+
+    /// How the game went for `side`, 0 for player one: a loss on time counts over the score.
+    pub fn outcome(&self, side: usize) -> Ordering {
+        match self.lost_on_time {
+            Some(loser) if loser == side => Ordering::Less,
+            Some(_) => Ordering::Greater,
+            None => self.score[side].cmp(&self.score[1 - side]),
+        }
+    }
+}
+
+/// A position with the exact value of every legal line in it.
+#[derive(Debug, Clone, Default)]
+pub struct LabelledPosition {
+    pub moves: Vec<usize>,
+    pub values: Vec<(usize, i32)>,
+}
+
+pub fn write_positions(file_path: &Path, positions: &[LabelledPosition]) -> Result<(), ModelError> {
+    let io_err = |err| ModelError::Io(file_path.display().to_string(), err);
+    let mut file = BufWriter::new(File::create(file_path).map_err(io_err)?);
+    writeln!(file, "{POSITIONS_MAGIC} {FORMAT_VERSION}").map_err(io_err)?;
+    for position in positions {
+        let moves: Vec<String> = position.moves.iter().map(|line| line.to_string()).collect();
+        let values: Vec<String> = position
+            .values
+            .iter()
+            .map(|(line, value)| format!("{line}={value}"))
+            .collect();
+        writeln!(file, "{};{}", moves.join(" "), values.join(" ")).map_err(io_err)?;
+    }
+    file.flush().map_err(io_err)
+}
+
+pub fn read_positions(file_path: &Path) -> Result<Vec<LabelledPosition>, ModelError> {
+    let name = file_path.display().to_string();
+    let content = fs::read_to_string(file_path).map_err(|err| ModelError::Io(name.clone(), err))?;
+    let mut lines = content.lines();
+    check_magic(lines.next(), POSITIONS_MAGIC, &name)?;
+
+    let mut positions = Vec::new();
+    for line in lines.filter(|line| !line.trim().is_empty()) {
+        let (moves, values) = line
+            .split_once(';')
+            .ok_or_else(|| ModelError::Format(format!("invalid position '{line}' in {name}")))?;
+        let moves: Vec<usize> = moves
+            .split_whitespace()
+            .map(|line| parse_value("move", line))
+            .collect::<Result<_, _>>()?;
+        let values: Vec<(usize, i32)> = values
+            .split_whitespace()
+            .map(|entry| match entry.split_once('=') {
+                Some((line, value)) => {
+                    Ok((parse_value("line", line)?, parse_value("value", value)?))
+                }
+                None => Err(ModelError::Format(format!(
+                    "invalid value '{entry}' in {name}"
+                ))),
+            })
+            .collect::<Result<_, _>>()?;
+        let env = replay(&moves).map_err(|msg| ModelError::Format(format!("{msg} in {name}")))?;
+        if let Some((line, _)) = values
+            .iter()
+            .find(|&&(line, _)| line >= 200 || !env.is_possible(line))
+        {
+            return Err(ModelError::Format(format!(
+                "line {line} can not be drawn in {name}"
+            )));
+        }
+        positions.push(LabelledPosition { moves, values });
+    }
+    Ok(positions)
+}
+
+/// Reads every `.game` and `.positions` file in `dir`.
+pub fn load_dir(dir: &str) -> Result<(Vec<GameRecord>, Vec<LabelledPosition>), ModelError> {
+    let entries = fs::read_dir(dir).map_err(|err| ModelError::Io(dir.to_owned(), err))?;
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .collect();
+    paths.sort();
+
+    let (mut games, mut positions) = (Vec::new(), Vec::new());
+    for path in paths {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("game") => games.push(GameRecord::read(&path)?),
+            Some("positions") => positions.extend(read_positions(&path)?),
+            _ => {}
+        }
+    }
+    Ok((games, positions))
+}
+
+/// Plays `moves` from the start, failing on lines that are already drawn.
+pub fn replay(moves: &[usize]) -> Result<BorderPatrol, String> {
+    let mut env = BorderPatrol::new();
+    for &line in moves {
+        if line >= 200 || !env.is_possible(line) {
+            return Err(format!("line {line} can not be drawn"));
+        }
+        env.set_line(line, env.game_info.turn);
+    }
+    Ok(env)
+}
+
+fn check_magic(line: Option<&str>, magic: &str, name: &str) -> Result<(), ModelError> {
+    let version: u32 = match line.and_then(|l| l.strip_prefix(magic)) {
+        Some(version) => parse_value("version", version)?,
+        None => {
+            return Err(ModelError::Format(format!(
+                "{name} does not start with '{magic}'"
+            )))
+        }
+    };
+    if version > FORMAT_VERSION {
+        return Err(ModelError::UnsupportedVersion(version));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("borderpatrol-{}-{name}", std::process::id()))
+    }
+
+    fn read_game(name: &str, content: &str) -> Result<GameRecord, ModelError> {
+        let path = temp_path(name);
+        fs::write(&path, content).unwrap();
+        let result = GameRecord::read(&path);
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    fn read_positions_from(name: &str, content: &str) -> Result<Vec<LabelledPosition>, ModelError> {
+        let path = temp_path(name);
+        fs::write(&path, content).unwrap();
+        let result = read_positions(&path);
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn game_round_trip() {
+        let record = GameRecord {
+            player_one: "advanced".to_owned(),
+            player_two: "random".to_owned(),
+            score: [54, 46],
+            moves: vec![12, 57, 33],
+            lost_on_time: Some(1),
+        };
+        let path = temp_path("round-trip.game");
+        record.write(&path).unwrap();
+        let read = GameRecord::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.player_one, record.player_one);
+        assert_eq!(read.player_two, record.player_two);
+        assert_eq!(read.score, record.score);
+        assert_eq!(read.moves, record.moves);
+        assert_eq!(read.lost_on_time, record.lost_on_time);
+    }
+
+    #[test]
+    fn game_read_rejects_invalid_records() {
+        let cases = [
+            ("magic", "borderpatrol-positions 1\nmoves\n"),
+            ("version", "borderpatrol-game 2\nmoves\n"),
+            ("header", "borderpatrol-game 1\nscore\nmoves\n"),
+            ("score", "borderpatrol-game 1\nscore = 54:46\nmoves\n"),
+            (
+                "player",
+                "borderpatrol-game 1\nlost_on_time = nobody\nmoves\n",
+            ),
+            ("move", "borderpatrol-game 1\nmoves\ntwelve\n"),
+            ("repeated", "borderpatrol-game 1\nmoves\n12\n12\n"),
+            ("range", "borderpatrol-game 1\nmoves\n200\n"),
+        ];
+        for (name, content) in cases {
+            assert!(
+                read_game(&format!("invalid-{name}.game"), content).is_err(),
+                "accepted an invalid {name}"
+            );
+        }
+    }
+
+    #[test]
+    fn positions_round_trip() {
+        let positions = vec![
+            LabelledPosition {
+                moves: vec![],
+                values: vec![(14, 3), (15, -1)],
+            },
+            LabelledPosition {
+                moves: vec![12, 57],
+                values: vec![(0, 0)],
+            },
+        ];
+        let path = temp_path("round-trip.positions");
+        write_positions(&path, &positions).unwrap();
+        let read = read_positions(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.len(), positions.len());
+        for (read, position) in read.iter().zip(&positions) {
+            assert_eq!(read.moves, position.moves);
+            assert_eq!(read.values, position.values);
+        }
+    }
+
+    #[test]
+    fn positions_read_rejects_invalid_files() {
+        let cases = [
+            ("magic", "borderpatrol-game 1\n12;14=3\n"),
+            ("separator", "borderpatrol-positions 1\n12 14=3\n"),
+            ("value", "borderpatrol-positions 1\n12;14:3\n"),
+            ("repeated", "borderpatrol-positions 1\n12 12;14=3\n"),
+            ("drawn", "borderpatrol-positions 1\n12 14;14=3\n"),
+            ("range", "borderpatrol-positions 1\n12;200=3\n"),
+        ];
+        for (name, content) in cases {
+            assert!(
+                read_positions_from(&format!("invalid-{name}.positions"), content).is_err(),
+                "accepted an invalid {name}"
+            );
+        }
+    }
+}
@@ -0,0 +1,170 @@
+#![allow(dead_code)]
+/*
+    Reward shaping for Q-learning. A move is rewarded for the boxes it takes and, if it decides
+    the game, for the result and the final margin. A game that stops with a player at exactly
+    half of the boxes is not decided yet and gets no result reward. The player who did not move
+    gets the loss, draw and margin rewards of a decided game on its pending move, and training
+    subtracts a share of the mover's reward from it.
+
+    Configurations are written as comma separated `name=value` entries, entries that are left
+    out keep their default, e.g. `win=1,loss=-1,margin=0.5`. The defaults reproduce the
+    rewards models were trained with before they could be configured.
+*/
+use std::{fmt, str::FromStr};
+
+use crate::{ai, borderpatrol::BorderPatrol};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RewardConfig {
+    /// For every box a move takes.
+    pub per_box: f64,
+    pub win: f64,
+    pub draw: f64,
+    pub loss: f64,
+    /// Times the final margin divided by the number of boxes, from -1 to 1.
+    pub margin: f64,
+    /// For a move after which the opponent can neither take a box nor draw a line that
+    /// does not hand one out, so it has to open a chain.
+    pub chain_control: f64,
+    /// Share of the mover's reward subtracted from the opponent's pending move.
+    pub opponent_share: f64,
+}
+
+impl Default for RewardConfig {
+    fn default() -> Self {
+        RewardConfig {
+            per_box: 0.05,
+            win: 1.0,
+            draw: 0.0,
+            loss: 0.0,
+            margin: 0.0,
+            chain_control: 0.0,
+            opponent_share: 0.1,
+        }
+    }
+}
+
+impl RewardConfig {
+    /// Rewards of the move that led from `before` to `after`, for the mover and for its
+    /// opponent.
+    pub fn rewards(&self, before: &BorderPatrol, after: &BorderPatrol) -> (f64, f64) {
+        let mover = before.get_turn();
+        let [mine, theirs] = [
+            after.game_info.score[mover],
+            after.game_info.score[1 - mover],
+        ];
+        let taken = mine - before.game_info.score[mover];
+
+        let mut reward = (self.per_box * taken as f64, 0.0);
+        if after.game_info.is_decided() {
+            let margin = self.margin * (f64::from(mine) - f64::from(theirs)) / 100.0;
+            let (result, opponent_result) = match mine.cmp(&theirs) {
+                std::cmp::Ordering::Greater => (self.win, self.loss),
+                std::cmp::Ordering::Equal => (self.draw, self.draw),
+                std::cmp::Ordering::Less => (self.loss, self.win),
+            };
+            reward.0 += result + margin;
+            reward.1 += opponent_result - margin;
+        } else if self.chain_control != 0.0
+            && !after.game_info.finished
+            && after.get_turn() != mover
+            && forces_chain(after)
+        {
+            reward.0 += self.chain_control;
+        }
+        reward
+    }
+}
+
+/// Whether the player to move has lines left but all of them hand out a box.
+fn forces_chain(env: &BorderPatrol) -> bool {
+    ai::completing_line(env).is_none()
+        && ai::safe_lines(env).next().is_none()
+        && ai::legal_lines(env).next().is_some()
+}
+
+impl FromStr for RewardConfig {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut config = RewardConfig::default();
+        for entry in spec.split(',').filter(|e| !e.trim().is_empty()) {
+            let (name, value) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Expected name=reward but got '{entry}'"))?;
+            let value: f64 = value
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid reward '{}'", value.trim()))?;
+            match name.trim() {
+                "per-box" => config.per_box = value,
+                "win" => config.win = value,
+                "draw" => config.draw = value,
+                "loss" => config.loss = value,
+                "margin" => config.margin = value,
+                "chain-control" => config.chain_control = value,
+                "opponent-share" => config.opponent_share = value,
+                other => return Err(format!("Unknown reward '{other}'")),
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl fmt::Display for RewardConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "per-box={},win={},draw={},loss={},margin={},chain-control={},opponent-share={}",
+            self.per_box,
+            self.win,
+            self.draw,
+            self.loss,
+            self.margin,
+            self.chain_control,
+            self.opponent_share
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_its_display() {
+        let config: RewardConfig = "win=1,loss=-1, margin=0.5".parse().unwrap();
+        assert_eq!(
+            config,
+            RewardConfig {
+                loss: -1.0,
+                margin: 0.5,
+                ..RewardConfig::default()
+            }
+        );
+        assert_eq!(config.to_string().parse(), Ok(config));
+        assert_eq!("".parse(), Ok(RewardConfig::default()));
+    }
+
+    #[test]
+    fn parse_rejects_invalid_specs() {
+        for spec in ["win", "win=high", "bonus=1", "win=1;loss=-1"] {
+            assert!(spec.parse::<RewardConfig>().is_err(), "accepted '{spec}'");
+        }
+    }
+
+    #[test]
+    fn default_rewards_boxes_and_decided_wins() {
+        let config = RewardConfig::default();
+        let mut before = BorderPatrol::new();
+        before.game_info.score = [50, 30];
+        let mut after = before.clone();
+        after.game_info.score = [51, 30];
+        assert_eq!(config.rewards(&before, &after), (0.05 + 1.0, 0.0));
+
+        // A player at exactly half of the boxes has not decided the game yet
+        after.game_info.score = [50, 31];
+        before.game_info.score = [49, 31];
+        assert_eq!(config.rewards(&before, &after), (0.05, 0.0));
+    }
+}
@@ -0,0 +1,32 @@
+#![allow(dead_code)]
+/*
+    Every random decision is drawn from a seeded rng so a run can be reproduced from its seed.
+    Independent parts of a run get their own stream of the same seed, that way e.g. taking
+    more evaluation games does not change how the network explores during training.
+*/
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+pub type SeededRng = ChaCha8Rng;
+
+pub const EXPLORATION: u64 = 0;
+pub const NETWORK_INIT: u64 = 1;
+pub const EVALUATION: u64 = 2;
+pub const PLAYER: u64 = 3;
+pub const SUPERVISED: u64 = 4;
+pub const SOLVER: u64 = 5;
+pub const SWEEP: u64 = 6;
+pub const BOOK: u64 = 7;
+/// Self-play worker `i` uses stream `WORKER + i`.
+pub const WORKER: u64 = 16;
+
+pub fn seeded(seed: u64, stream: u64) -> SeededRng {
+    let mut rng = SeededRng::seed_from_u64(seed);
+    rng.set_stream(stream);
+    rng
+}
+
+/// Picks a fresh seed for runs that were not given one.
+pub fn random_seed() -> u64 {
+    SeededRng::from_entropy().gen()
+}
@@ -0,0 +1,654 @@
+#![allow(dead_code)]
+/*
+    The scenes of the terminal game and how they lead into each other:
+
+        main menu       new game, settings or quit
+        game            the board, escape pauses it
+        pause           over the stopped game: resume, settings, back to the main menu or quit
+        settings        theme, time control and explain, used from the next game on
+        game over       over the finished board: play again, review, main menu or quit
+        review          steps through the lines of the finished game
+
+    Every game of a session is set up the same way: player two is a new computer player of
+    the same kind or a human, and the settings give the theme, the clock and the explain
+    overlay. The settings are shared by all scenes, a theme chosen in the settings is stored
+    like one chosen with `--theme`.
+*/
+use std::{
+    cell::{Cell, RefCell},
+    mem,
+    rc::Rc,
+    time::Duration,
+};
+
+use crate::{
+    clock::{Clock, TimeControl},
+    engine::{MouseEvent, MouseKind, Scene, Timer, Transition},
+    keys::Action,
+    screen::{Screen, Style},
+    terminal_borderpatrol::display::{Player, TerminalBorderPatrol},
+    theme::{Theme, THEMES},
+};
+
+/// How long the finished board is shown before the game over menu covers it.
+const GAME_OVER_DELAY: Duration = Duration::from_millis(1500);
+/// Time controls offered in the settings besides an untimed game and the one of `--time`.
+const TIME_CONTROLS: [&str; 4] = ["1m", "5m", "5m+3s", "10m/30s"];
+
+/// Sets up a new computer player for every game.
+pub type NewPlayer = Box<dyn Fn() -> Box<dyn Player>>;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    pub theme: Theme,
+    pub time: Option<TimeControl>,
+    pub explain: bool,
+}
+
+/// What the scenes of a run of the terminal game share.
+pub struct Session {
+    settings: RefCell<Settings>,
+    /// The name of player two and how to set them up, `None` for games between two humans.
+    opponent: Option<(String, NewPlayer)>,
+}
+
+impl Session {
+    pub fn new(settings: Settings, opponent: Option<(String, NewPlayer)>) -> Rc<Session> {
+        Rc::new(Session {
+            settings: RefCell::new(settings),
+            opponent,
+        })
+    }
+
+    fn settings(&self) -> Settings {
+        *self.settings.borrow()
+    }
+
+    fn new_game(&self) -> TerminalBorderPatrol {
+        let mut game = match &self.opponent {
+            Some((name, new_player)) => {
+                let mut game = TerminalBorderPatrol::with_player_two(new_player());
+                game.names[1] = name.clone();
+                game
+            }
+            None => TerminalBorderPatrol::new(),
+        };
+        let settings = self.settings();
+        game.theme = settings.theme;
+        game.clock = settings.time.map(Clock::new);
+        game.explain = settings.explain;
+        game
+    }
+}
+
+/// A framed list of items in the middle of the screen, chosen with the cursor keys and place
+/// or with the mouse.
+struct Menu {
+    title: String,
+    items: Vec<String>,
+    selected: usize,
+    /// Row and column of the first item and the width of the items as last rendered.
+    placed: Cell<Option<(usize, usize, usize)>>,
+}
+
+impl Menu {
+    fn new(title: impl Into<String>, items: &[&str]) -> Menu {
+        Menu {
+            title: title.into(),
+            items: items.iter().map(|&item| item.to_owned()).collect(),
+            selected: 0,
+            placed: Cell::new(None),
+        }
+    }
+
+    /// The item chosen by `action`, if any.
+    fn react(&mut self, action: Action) -> Option<usize> {
+        let count = self.items.len();
+        match action {
+            Action::Up => self.selected = (self.selected + count - 1) % count,
+            Action::Down => self.selected = (self.selected + 1) % count,
+            Action::Place => return Some(self.selected),
+            _ => (),
+        }
+        None
+    }
+
+    /// The item clicked, the pointer selects the item it is over.
+    fn mouse(&mut self, event: MouseEvent) -> Option<usize> {
+        let (row, column, width) = self.placed.get()?;
+        let item = event.row.checked_sub(row).filter(|&item| {
+            item < self.items.len() && (column..column + width).contains(&event.column)
+        })?;
+        self.selected = item;
+        (event.kind == MouseKind::Click).then_some(item)
+    }
+
+    fn render(&self, screen: &mut Screen, theme: &Theme) {
+        let width = self
+            .items
+            .iter()
+            .chain([&self.title])
+            .map(|text| text.chars().count() + 6)
+            .max()
+            .unwrap_or(0);
+        let (rows, columns) = screen.size();
+        let (top, left) = (
+            rows.saturating_sub(self.items.len() + 4) / 2,
+            columns.saturating_sub(width + 2) / 2,
+        );
+        let (frame, border) = (&theme.frame, theme.frame_style.bold());
+        let horizontal = frame.horizontal.to_string().repeat(width);
+        let vertical = frame.vertical.to_string();
+
+        let mut row = top;
+        screen.print(
+            row,
+            left,
+            &format!("{}{horizontal}{}", frame.top_left, frame.top_right),
+            border,
+        );
+        let title = format!("{:^width$}", self.title);
+        for (text, style) in [
+            (title, Style::PLAIN.bold()),
+            (" ".repeat(width), Style::PLAIN),
+        ] {
+            row += 1;
+            let column = screen.print(row, left, &vertical, border);
+            let column = screen.print(row, column, &text, style);
+            screen.print(row, column, &vertical, border);
+        }
+        self.placed.set(Some((row + 1, left + 1, width)));
+        for (i, item) in self.items.iter().enumerate() {
+            row += 1;
+            let (marker, style) = match i == self.selected {
+                true => (theme.cursor, theme.hover),
+                false => (' ', Style::PLAIN),
+            };
+            let text = format!(" {marker} {item:<item_width$}", item_width = width - 3);
+            let column = screen.print(row, left, &vertical, border);
+            let column = screen.print(row, column, &text, style);
+            screen.print(row, column, &vertical, border);
+        }
+        row += 1;
+        screen.print(
+            row,
+            left,
+            &format!("{}{horizontal}{}", frame.bottom_left, frame.bottom_right),
+            border,
+        );
+    }
+}
+
+pub struct MainMenu {
+    menu: Menu,
+    session: Rc<Session>,
+}
+
+impl MainMenu {
+    pub fn new(session: Rc<Session>) -> MainMenu {
+        MainMenu {
+            menu: Menu::new("Borderpatrol", &["New game", "Settings", "Quit"]),
+            session,
+        }
+    }
+
+    fn choose(&self, item: usize) -> Transition {
+        match item {
+            0 => Transition::Push(Box::new(Match::new(self.session.clone()))),
+            1 => Transition::Push(Box::new(SettingsMenu::new(self.session.clone()))),
+            _ => Transition::Quit,
+        }
+    }
+}
+
+impl Scene for MainMenu {
+    fn update(&mut self) -> Transition {
+        Transition::Stay
+    }
+
+    fn wait_for_input(&self) -> bool {
+        true
+    }
+
+    fn render(&self, screen: &mut Screen) {
+        self.menu.render(screen, &self.session.settings().theme);
+    }
+
+    fn react(&mut self, action: Action) -> Transition {
+        match self.menu.react(action) {
+            Some(item) => self.choose(item),
+            None => Transition::Stay,
+        }
+    }
+
+    fn mouse(&mut self, event: MouseEvent) -> Transition {
+        match self.menu.mouse(event) {
+            Some(item) => self.choose(item),
+            None => Transition::Stay,
+        }
+    }
+}
+
+/// A game being played, it pauses on back and ends in the game over menu.
+struct Match {
+    game: TerminalBorderPatrol,
+    session: Rc<Session>,
+    /// Started once the game is over.
+    over: Option<Timer>,
+}
+
+impl Match {
+    fn new(session: Rc<Session>) -> Match {
+        Match {
+            game: session.new_game(),
+            session,
+            over: None,
+        }
+    }
+}
+
+impl Scene for Match {
+    fn update(&mut self) -> Transition {
+        Transition::Stay
+    }
+
+    fn wait_for_input(&self) -> bool {
+        true
+    }
+
+    fn render(&self, screen: &mut Screen) {
+        self.game.render(screen);
+    }
+
+    fn react(&mut self, action: Action) -> Transition {
+        match action {
+            Action::Back => Transition::Push(Box::new(PauseMenu::new(self.session.clone()))),
+            action => self.game.react(action),
+        }
+    }
+
+    fn mouse(&mut self, event: MouseEvent) -> Transition {
+        self.game.mouse(event)
+    }
+
+    fn tick(&mut self) -> Transition {
+        self.game.tick();
+        if self.game.check_victory().is_none() {
+            return Transition::Stay;
+        }
+        if !self
+            .over
+            .get_or_insert_with(|| Timer::start(GAME_OVER_DELAY))
+            .done()
+        {
+            return Transition::Stay;
+        }
+        let game = mem::replace(&mut self.game, TerminalBorderPatrol::new());
+        Transition::Switch(Box::new(GameOver::new(game, self.session.clone())))
+    }
+
+    fn pause(&mut self) {
+        self.game.pause();
+    }
+
+    /// The game continues with the theme and explain overlay of the settings.
+    fn resume(&mut self) {
+        let settings = self.session.settings();
+        self.game.theme = settings.theme;
+        self.game.explain = settings.explain;
+        self.game.resume();
+    }
+}
+
+struct PauseMenu {
+    menu: Menu,
+    session: Rc<Session>,
+}
+
+impl PauseMenu {
+    fn new(session: Rc<Session>) -> PauseMenu {
+        PauseMenu {
+            menu: Menu::new("Paused", &["Resume", "Settings", "Main menu", "Quit"]),
+            session,
+        }
+    }
+
+    fn choose(&self, item: usize) -> Transition {
+        match item {
+            0 => Transition::Pop,
+            1 => Transition::Push(Box::new(SettingsMenu::new(self.session.clone()))),
+            2 => Transition::Home,
+            _ => Transition::Quit,
+        }
+    }
+}
+
+impl Scene for PauseMenu {
+    fn update(&mut self) -> Transition {
+        Transition::Stay
+    }
+
+    fn wait_for_input(&self) -> bool {
+        true
+    }
+
+    fn render(&self, screen: &mut Screen) {
+        self.menu.render(screen, &self.session.settings().theme);
+    }
+
+    fn react(&mut self, action: Action) -> Transition {
+        match (action, self.menu.react(action)) {
+            (Action::Back, _) => Transition::Pop,
+            (_, Some(item)) => self.choose(item),
+            (_, None) => Transition::Stay,
+        }
+    }
+
+    fn mouse(&mut self, event: MouseEvent) -> Transition {
+        match self.menu.mouse(event) {
+            Some(item) => self.choose(item),
+            None => Transition::Stay,
+        }
+    }
+
+    fn overlay(&self) -> bool {
+        true
+    }
+}
+
+/// Changes the settings, left and right or place go through the choices of an item.
+struct SettingsMenu {
+    menu: Menu,
+    session: Rc<Session>,
+    /// The time controls to choose from, `None` for untimed games.
+    times: Vec<Option<TimeControl>>,
+}
+
+impl SettingsMenu {
+    fn new(session: Rc<Session>) -> SettingsMenu {
+        let mut times = vec![None];
+        times.extend(TIME_CONTROLS.map(|control| control.parse().ok()));
+        let time = session.settings().time;
+        if !times.contains(&time) {
+            times.push(time);
+        }
+        let mut settings = SettingsMenu {
+            menu: Menu::new("Settings", &[]),
+            session,
+            times,
+        };
+        settings.menu.items = settings.items();
+        settings
+    }
+
+    fn items(&self) -> Vec<String> {
+        let settings = self.session.settings();
+        let time = settings
+            .time
+            .map_or("untimed".to_owned(), |time| time.to_string());
+        let explain = if settings.explain { "on" } else { "off" };
+        vec![
+            format!("Theme     {}", settings.theme),
+            format!("Clock     {time}"),
+            format!("Explain   {explain}"),
+            "Back".to_owned(),
+        ]
+    }
+
+    /// Goes `step` choices on from the current one of `item`, back to the menu below for the
+    /// last item.
+    fn change(&mut self, item: usize, step: isize) -> Transition {
+        let next = |count: usize, current: usize| {
+            (current as isize + step).rem_euclid(count as isize) as usize
+        };
+        let mut settings = self.session.settings();
+        match item {
+            0 => {
+                let current = THEMES
+                    .iter()
+                    .position(|theme| theme.name == settings.theme.name)
+                    .unwrap_or(0);
+                settings.theme = THEMES[next(THEMES.len(), current)];
+                // The theme is used for this session even if it can not be stored
+                settings.theme.save().ok();
+            }
+            1 => {
+                let current = self
+                    .times
+                    .iter()
+                    .position(|&time| time == settings.time)
+                    .unwrap_or(0);
+                settings.time = self.times[next(self.times.len(), current)];
+            }
+            2 => settings.explain = !settings.explain,
+            _ => return Transition::Pop,
+        }
+        *self.session.settings.borrow_mut() = settings;
+        self.menu.items = self.items();
+        Transition::Stay
+    }
+}
+
+impl Scene for SettingsMenu {
+    fn update(&mut self) -> Transition {
+        Transition::Stay
+    }
+
+    fn wait_for_input(&self) -> bool {
+        true
+    }
+
+    fn render(&self, screen: &mut Screen) {
+        self.menu.render(screen, &self.session.settings().theme);
+    }
+
+    fn react(&mut self, action: Action) -> Transition {
+        let selected = self.menu.selected;
+        match action {
+            Action::Back => Transition::Pop,
+            Action::Left => self.change(selected, -1),
+            Action::Right => self.change(selected, 1),
+            action => match self.menu.react(action) {
+                Some(item) => self.change(item, 1),
+                None => Transition::Stay,
+            },
+        }
+    }
+
+    fn mouse(&mut self, event: MouseEvent) -> Transition {
+        match self.menu.mouse(event) {
+            Some(item) => self.change(item, 1),
+            None => Transition::Stay,
+        }
+    }
+
+    fn overlay(&self) -> bool {
+        true
+    }
+}
+
+/// The finished board with its result and what to do next.
+struct GameOver {
+    game: TerminalBorderPatrol,
+    menu: Menu,
+    session: Rc<Session>,
+}
+
+impl GameOver {
+    fn new(game: TerminalBorderPatrol, session: Rc<Session>) -> GameOver {
+        let result = game.check_victory().unwrap_or_default();
+        GameOver {
+            game,
+            menu: Menu::new(result, &["Play again", "Review", "Main menu", "Quit"]),
+            session,
+        }
+    }
+
+    fn choose(&self, item: usize) -> Transition {
+        match item {
+            0 => Transition::Switch(Box::new(Match::new(self.session.clone()))),
+            1 => Transition::Push(Box::new(Review {
+                game: self.game.review(),
+            })),
+            2 => Transition::Home,
+            _ => Transition::Quit,
+        }
+    }
+}
+
+impl Scene for GameOver {
+    fn update(&mut self) -> Transition {
+        Transition::Stay
+    }
+
+    fn wait_for_input(&self) -> bool {
+        true
+    }
+
+    fn render(&self, screen: &mut Screen) {
+        self.game.render(screen);
+        self.menu.render(screen, &self.session.settings().theme);
+    }
+
+    fn react(&mut self, action: Action) -> Transition {
+        match (action, self.menu.react(action)) {
+            (Action::Back, _) => Transition::Home,
+            (_, Some(item)) => self.choose(item),
+            (_, None) => Transition::Stay,
+        }
+    }
+
+    fn mouse(&mut self, event: MouseEvent) -> Transition {
+        match self.menu.mouse(event) {
+            Some(item) => self.choose(item),
+            None => Transition::Stay,
+        }
+    }
+}
+
+/// Steps through the lines of a finished game, back or place return to the game over menu.
+struct Review {
+    game: TerminalBorderPatrol,
+}
+
+impl Scene for Review {
+    fn update(&mut self) -> Transition {
+        Transition::Stay
+    }
+
+    fn wait_for_input(&self) -> bool {
+        true
+    }
+
+    fn render(&self, screen: &mut Screen) {
+        self.game.render(screen);
+    }
+
+    fn react(&mut self, action: Action) -> Transition {
+        match action {
+            Action::Back | Action::Place => Transition::Pop,
+            action => self.game.react(action),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn session(time: Option<&str>) -> Rc<Session> {
+        let settings = Settings {
+            theme: Theme::default(),
+            time: time.map(|time| time.parse().unwrap()),
+            explain: false,
+        };
+        Session::new(settings, None)
+    }
+
+    /// The first row the scene renders with `text` in it, trimmed.
+    fn shown(scene: &dyn Scene, text: &str) -> Option<String> {
+        let mut screen = Screen::sized((60, 100));
+        scene.render(&mut screen);
+        (0..screen.height()).find_map(|row| {
+            let line: String = (0..100)
+                .map(|column| screen.cell(row, column).map_or(' ', |cell| cell.glyph))
+                .collect();
+            line.contains(text).then(|| line.trim().to_owned())
+        })
+    }
+
+    #[test]
+    fn pause_stops_the_clock_and_resume_applies_the_settings() {
+        let session = session(Some("5m"));
+        let mut game = Match::new(session.clone());
+        game.tick();
+        let Transition::Push(mut pause) = game.react(Action::Back) else {
+            panic!("back does not pause the game");
+        };
+        game.pause();
+        assert!(pause.overlay());
+
+        let clock = game.game.clock.clone().unwrap();
+        let left = clock.main_left(0);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(clock.main_left(0), left);
+
+        session.settings.borrow_mut().explain = true;
+        assert!(matches!(pause.react(Action::Back), Transition::Pop));
+        assert!(matches!(pause.react(Action::Place), Transition::Pop));
+        game.resume();
+        assert!(game.game.explain);
+        let clock = game.game.clock.as_ref().unwrap();
+        assert_eq!(clock.running(), Some(0));
+        assert!(left - clock.main_left(0) < Duration::from_millis(20));
+    }
+
+    #[test]
+    fn game_over_leads_to_the_review() {
+        let session = session(None);
+        let mut game = Match::new(session);
+        // Draws lines until the game is over, the bottom lines first and then the right lines
+        for orientation in 0..2 {
+            for _ in 0..90 {
+                game.react(Action::Place);
+                game.react(Action::Right);
+            }
+            for _ in 0..89 {
+                game.react(Action::Left);
+            }
+            if orientation == 0 {
+                game.react(Action::Flip);
+            }
+        }
+        let result = game.game.check_victory().expect("the game is not over");
+
+        assert!(matches!(game.tick(), Transition::Stay));
+        game.over = Some(Timer::start(Duration::ZERO));
+        let Transition::Switch(mut over) = game.tick() else {
+            panic!("the finished game does not end");
+        };
+        assert!(shown(over.as_ref(), &result).is_some());
+
+        over.react(Action::Down);
+        let Transition::Push(mut review) = over.react(Action::Place) else {
+            panic!("the game over menu does not review");
+        };
+        let status = shown(review.as_ref(), "Reviewing").unwrap();
+        let lines = status
+            .strip_prefix("Reviewing line ")
+            .and_then(|status| status.split_once(" of "))
+            .map(|(shown, _)| shown.parse::<usize>().unwrap())
+            .unwrap();
+        assert_eq!(
+            status,
+            format!("Reviewing line {lines} of {lines}, {result}")
+        );
+        review.react(Action::Left);
+        let status = shown(review.as_ref(), "Reviewing").unwrap();
+        assert_eq!(status, format!("Reviewing line {} of {lines}", lines - 1));
+        assert!(matches!(review.react(Action::Back), Transition::Pop));
+        assert!(matches!(over.react(Action::Back), Transition::Home));
+    }
+}
@@ -0,0 +1,222 @@
+#![allow(dead_code)]
+/*
+    Schedules move a training parameter from its start value as episodes go by. They are
+    written as the name followed by colon separated arguments:
+
+        constant
+        exponential:<decay>[:<min>]       start * decay^episode, not below min
+        linear:<end>:<episodes>           straight to end, then stays there
+        cosine:<end>:<episodes>           half a cosine wave to end, then stays there
+        step:<factor>:<every>[:<min>]     times factor every so many episodes, not below min
+*/
+use std::{f64::consts::PI, fmt, str::FromStr};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Schedule {
+    Constant,
+    Exponential { decay: f64, min: f64 },
+    Linear { end: f64, episodes: usize },
+    Cosine { end: f64, episodes: usize },
+    Step { factor: f64, every: usize, min: f64 },
+}
+
+impl Schedule {
+    /// The value after `episode` episodes of a parameter that starts at `start`.
+    pub fn value(&self, start: f64, episode: usize) -> f64 {
+        match *self {
+            Schedule::Constant => start,
+            Schedule::Exponential { decay, min } => (start * decay.powf(episode as f64)).max(min),
+            Schedule::Linear { end, episodes } => {
+                let progress = (episode as f64 / episodes.max(1) as f64).min(1.0);
+                start + (end - start) * progress
+            }
+            Schedule::Cosine { end, episodes } => {
+                let progress = (episode as f64 / episodes.max(1) as f64).min(1.0);
+                end + (start - end) * 0.5 * (1.0 + (PI * progress).cos())
+            }
+            Schedule::Step { factor, every, min } => {
+                (start * factor.powf((episode / every.max(1)) as f64)).max(min)
+            }
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut parts = spec.trim().split(':');
+        let name = parts.next().unwrap_or_default();
+        let args: Vec<&str> = parts.collect();
+        let invalid = || format!("Invalid schedule '{spec}'");
+        let float = |i: usize| -> Result<f64, String> {
+            args.get(i)
+                .and_then(|x| x.trim().parse().ok())
+                .ok_or_else(invalid)
+        };
+        let count = |i: usize| -> Result<usize, String> {
+            args.get(i)
+                .and_then(|x| x.trim().parse().ok())
+                .ok_or_else(invalid)
+        };
+        let optional = |i: usize| if args.len() > i { float(i) } else { Ok(0.0) };
+
+        let (schedule, max_args) = match name {
+            "constant" => (Schedule::Constant, 0),
+            "exponential" => (
+                Schedule::Exponential {
+                    decay: float(0)?,
+                    min: optional(1)?,
+                },
+                2,
+            ),
+            "linear" => (
+                Schedule::Linear {
+                    end: float(0)?,
+                    episodes: count(1)?,
+                },
+                2,
+            ),
+            "cosine" => (
+                Schedule::Cosine {
+                    end: float(0)?,
+                    episodes: count(1)?,
+                },
+                2,
+            ),
+            "step" => (
+                Schedule::Step {
+                    factor: float(0)?,
+                    every: count(1)?,
+                    min: optional(2)?,
+                },
+                3,
+            ),
+            other => return Err(format!("Unknown schedule '{other}'")),
+        };
+        if args.len() > max_args {
+            return Err(invalid());
+        }
+        Ok(schedule)
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Schedule::Constant => write!(f, "constant"),
+            Schedule::Exponential { decay, min } => write!(f, "exponential:{decay}:{min}"),
+            Schedule::Linear { end, episodes } => write!(f, "linear:{end}:{episodes}"),
+            Schedule::Cosine { end, episodes } => write!(f, "cosine:{end}:{episodes}"),
+            Schedule::Step { factor, every, min } => write!(f, "step:{factor}:{every}:{min}"),
+        }
+    }
+}
+
+/// How training picks the lines it explores with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exploration {
+    /// A random line with probability epsilon, the best line otherwise.
+    EpsilonGreedy,
+    /// Lines drawn with probability proportional to exp(q / temperature), epsilon and its
+    /// schedule give the temperature.
+    Boltzmann,
+}
+
+impl FromStr for Exploration {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "epsilon-greedy" => Ok(Exploration::EpsilonGreedy),
+            "boltzmann" => Ok(Exploration::Boltzmann),
+            other => Err(format!("Unknown exploration '{other}'")),
+        }
+    }
+}
+
+impl fmt::Display for Exploration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Exploration::EpsilonGreedy => write!(f, "epsilon-greedy"),
+            Exploration::Boltzmann => write!(f, "boltzmann"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_its_display() {
+        for schedule in [
+            Schedule::Constant,
+            Schedule::Exponential {
+                decay: 0.999,
+                min: 0.05,
+            },
+            Schedule::Linear {
+                end: 0.1,
+                episodes: 1000,
+            },
+            Schedule::Cosine {
+                end: 0.0,
+                episodes: 500,
+            },
+            Schedule::Step {
+                factor: 0.5,
+                every: 100,
+                min: 0.01,
+            },
+        ] {
+            assert_eq!(schedule.to_string().parse(), Ok(schedule));
+        }
+        assert_eq!(
+            "exponential:0.99".parse(),
+            Ok(Schedule::Exponential {
+                decay: 0.99,
+                min: 0.0
+            })
+        );
+        for exploration in [Exploration::EpsilonGreedy, Exploration::Boltzmann] {
+            assert_eq!(exploration.to_string().parse(), Ok(exploration));
+        }
+    }
+
+    #[test]
+    fn parse_rejects_invalid_schedules() {
+        for spec in [
+            "",
+            "sawtooth",
+            "constant:1",
+            "exponential",
+            "exponential:fast",
+            "linear:0.1",
+            "linear:0.1:-5",
+            "step:0.5:100:0:1",
+        ] {
+            assert!(spec.parse::<Schedule>().is_err(), "accepted '{spec}'");
+        }
+        assert!("greedy".parse::<Exploration>().is_err());
+    }
+
+    #[test]
+    fn values_follow_the_schedule() {
+        let linear = Schedule::Linear {
+            end: 0.0,
+            episodes: 10,
+        };
+        assert_eq!(linear.value(1.0, 5), 0.5);
+        assert_eq!(linear.value(1.0, 20), 0.0);
+
+        let step = Schedule::Step {
+            factor: 0.5,
+            every: 10,
+            min: 0.2,
+        };
+        assert_eq!(step.value(1.0, 9), 1.0);
+        assert_eq!(step.value(1.0, 10), 0.5);
+        assert_eq!(step.value(1.0, 30), 0.2);
+    }
+}
@@ -0,0 +1,257 @@
+#![allow(dead_code)]
+/*
+    The screen games render into: a grid of cells, each a glyph with a foreground and
+    background colour and a bold flag. Games draw the board first and put cursors, hints and
+    highlights on top as further layers, by printing over cells or restyling them.
+
+    The grid grows with what is drawn into it. Cells that were never drawn are blank. The
+    screen knows the size of the terminal it is drawn for, so games can lay themselves out to
+    fit it; what is drawn outside of it is cut off.
+*/
+use std::fmt::Write as _;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    /// One of the 256 palette colours, 0 to 15 are the basic terminal colours.
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// SGR parameters selecting the colour, `base` is 38 for the foreground and 48 for the
+    /// background.
+    fn parameters(&self, base: u8) -> String {
+        match *self {
+            Color::Indexed(index) => format!("{base};5;{index}"),
+            Color::Rgb(r, g, b) => format!("{base};2;{r};{g};{b}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Style {
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+    pub bold: bool,
+}
+
+impl Style {
+    pub const PLAIN: Style = Style {
+        foreground: None,
+        background: None,
+        bold: false,
+    };
+
+    pub const fn foreground(self, color: Color) -> Style {
+        Style {
+            foreground: Some(color),
+            ..self
+        }
+    }
+
+    pub const fn background(self, color: Color) -> Style {
+        Style {
+            background: Some(color),
+            ..self
+        }
+    }
+
+    pub const fn bold(self) -> Style {
+        Style { bold: true, ..self }
+    }
+
+    fn sequence(&self) -> String {
+        let mut sequence = "\x1B[0".to_owned();
+        if self.bold {
+            sequence.push_str(";1");
+        }
+        if let Some(color) = self.foreground {
+            sequence.push(';');
+            sequence.push_str(&color.parameters(38));
+        }
+        if let Some(color) = self.background {
+            sequence.push(';');
+            sequence.push_str(&color.parameters(48));
+        }
+        sequence.push('m');
+        sequence
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub glyph: char,
+    pub style: Style,
+}
+
+impl Cell {
+    pub const BLANK: Cell = Cell {
+        glyph: ' ',
+        style: Style::PLAIN,
+    };
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Screen {
+    rows: Vec<Vec<Cell>>,
+    /// Rows and columns of the terminal.
+    size: (usize, usize),
+}
+
+impl Screen {
+    pub fn new() -> Screen {
+        Screen::default()
+    }
+
+    /// An empty screen for a terminal of `size` rows and columns.
+    pub fn sized(size: (u16, u16)) -> Screen {
+        Screen {
+            rows: vec![],
+            size: (size.0 as usize, size.1 as usize),
+        }
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        self.size
+    }
+
+    pub fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn cell(&self, row: usize, column: usize) -> Option<&Cell> {
+        self.rows.get(row).and_then(|cells| cells.get(column))
+    }
+
+    /// The cell at `row` and `column`, growing the grid up to it.
+    pub fn cell_mut(&mut self, row: usize, column: usize) -> &mut Cell {
+        if self.rows.len() <= row {
+            self.rows.resize(row + 1, vec![]);
+        }
+        let cells = &mut self.rows[row];
+        if cells.len() <= column {
+            cells.resize(column + 1, Cell::BLANK);
+        }
+        &mut cells[column]
+    }
+
+    /// Writes `text` from `column` on, one cell per char, and returns the column after it.
+    pub fn print(&mut self, row: usize, column: usize, text: &str, style: Style) -> usize {
+        let mut column = column;
+        for glyph in text.chars() {
+            *self.cell_mut(row, column) = Cell { glyph, style };
+            column += 1;
+        }
+        column
+    }
+
+    /// Changes the style of `width` cells and keeps their glyphs.
+    pub fn restyle(
+        &mut self,
+        row: usize,
+        column: usize,
+        width: usize,
+        restyle: impl Fn(Style) -> Style,
+    ) {
+        for column in column..column + width {
+            let cell = self.cell_mut(row, column);
+            cell.style = restyle(cell.style);
+        }
+    }
+
+    /// Writes the cells that differ from `previous` to `output` as runs behind cursor
+    /// positioning sequences, leaving out everything outside of a terminal of `size` rows
+    /// and columns.
+    pub fn diff(&self, previous: &Screen, size: (u16, u16), output: &mut String) {
+        let (height, width) = (size.0 as usize, size.1 as usize);
+        let mut style = Style::PLAIN;
+        for row in 0..self.rows.len().max(previous.rows.len()).min(height) {
+            let columns = self.rows.get(row).map_or(0, Vec::len);
+            let previous_columns = previous.rows.get(row).map_or(0, Vec::len);
+            let mut cursor = None;
+            for column in 0..columns.max(previous_columns).min(width) {
+                let cell = self.cell(row, column).unwrap_or(&Cell::BLANK);
+                if previous.cell(row, column).unwrap_or(&Cell::BLANK) == cell {
+                    continue;
+                }
+                if cursor != Some(column) {
+                    let _ = write!(output, "\x1B[{};{}H", row + 1, column + 1);
+                }
+                if cell.style != style {
+                    output.push_str(&cell.style.sequence());
+                    style = cell.style;
+                }
+                output.push(cell.glyph);
+                cursor = Some(column + 1);
+            }
+        }
+        if style != Style::PLAIN {
+            output.push_str("\x1B[0m");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: (u16, u16) = (10, 20);
+
+    fn diff(screen: &Screen, previous: &Screen) -> String {
+        let mut output = String::new();
+        screen.diff(previous, SIZE, &mut output);
+        output
+    }
+
+    #[test]
+    fn diff_writes_runs_of_changed_cells() {
+        let mut screen = Screen::sized(SIZE);
+        screen.print(0, 2, "ab", Style::PLAIN);
+        screen.print(1, 0, "c", Style::PLAIN);
+        assert_eq!(diff(&screen, &Screen::new()), "\x1B[1;3Hab\x1B[2;1Hc");
+        assert_eq!(diff(&screen, &screen), "");
+
+        let mut next = screen.clone();
+        next.print(0, 3, "x", Style::PLAIN);
+        next.print(0, 5, "yz", Style::PLAIN);
+        // The unchanged cell between the runs is skipped by moving the cursor
+        assert_eq!(diff(&next, &screen), "\x1B[1;4Hx\x1B[1;6Hyz");
+    }
+
+    #[test]
+    fn diff_blanks_cells_that_are_gone() {
+        let mut previous = Screen::sized(SIZE);
+        previous.print(0, 0, "abc", Style::PLAIN);
+        previous.print(2, 1, "d", Style::PLAIN);
+        let mut screen = Screen::sized(SIZE);
+        screen.print(0, 0, "a", Style::PLAIN);
+        assert_eq!(diff(&screen, &previous), "\x1B[1;2H  \x1B[3;2H ");
+    }
+
+    #[test]
+    fn diff_switches_styles_and_resets_them() {
+        let bold = Style::PLAIN.bold();
+        let red = Style::PLAIN.foreground(Color::Indexed(1));
+        let mut screen = Screen::sized(SIZE);
+        screen.print(0, 0, "ab", bold);
+        screen.print(0, 2, "c", red.background(Color::Rgb(1, 2, 3)));
+        screen.print(0, 3, "d", Style::PLAIN);
+        assert_eq!(
+            diff(&screen, &Screen::new()),
+            "\x1B[1;1H\x1B[0;1mab\x1B[0;38;5;1;48;2;1;2;3mc\x1B[0md"
+        );
+
+        // Only a restyled cell is written, and the style is reset at the end
+        let mut next = screen.clone();
+        next.restyle(0, 3, 1, |_| bold);
+        assert_eq!(diff(&next, &screen), "\x1B[1;4H\x1B[0;1md\x1B[0m");
+    }
+
+    #[test]
+    fn diff_cuts_off_cells_outside_the_terminal() {
+        let mut screen = Screen::sized(SIZE);
+        screen.print(0, 18, "abcd", Style::PLAIN);
+        screen.print(10, 0, "e", Style::PLAIN);
+        assert_eq!(diff(&screen, &Screen::new()), "\x1B[1;19Hab");
+    }
+}
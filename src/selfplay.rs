@@ -0,0 +1,237 @@
+#![allow(dead_code)]
+/*
+    Parallel self-play. Worker threads play games against themselves with a read-only
+    snapshot of the network and send the moves of every game to the learner, which trains
+    on them and publishes a new snapshot every few updates.
+
+    The workers also compute the q value targets of their moves with the snapshot, so it
+    doubles as the target network and the learner is left with a single backpropagation
+    per move.
+
+    Games are played in rounds of one game per worker against the same snapshot, the learner
+    trains on the games of a round in worker order and only publishes new weights between
+    rounds. Every worker draws from its own stream, so like single threaded training a run is
+    reproduced exactly by its seed, as long as the number of workers stays the same.
+*/
+use std::{
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread,
+};
+
+use rand::Rng;
+
+use crate::{
+    ml::{
+        self, BorderPatrolAgent, EpisodeLearner, EpisodeStats, HyperParameters, TrainingObserver,
+        TrainingState,
+    },
+    model::ModelError,
+    network::Network,
+    rng::{self, SeededRng},
+};
+
+/// What the workers play a round with.
+struct Snapshot {
+    nn: Arc<Network>,
+    eps: f64,
+    hyper_parms: HyperParameters,
+}
+
+/// The moves of a game with their targets.
+struct Game {
+    moves: Vec<(Vec<f64>, usize, f64)>,
+    length: usize,
+}
+
+pub struct SelfPlayPool {
+    pub workers: usize,
+    /// Publish the learner's weights to the workers once this many updates were made since
+    /// the last time, checked after every round.
+    pub refresh_every: usize,
+}
+
+impl SelfPlayPool {
+    pub fn new(workers: usize) -> SelfPlayPool {
+        SelfPlayPool {
+            workers,
+            refresh_every: 100,
+        }
+    }
+
+    /// Trains until `training.episode` reaches the configured number of episodes, like
+    /// `BorderPatrolAgent::train_from` does, but with the games played by the workers.
+    pub fn train(
+        &self,
+        agent: &mut BorderPatrolAgent,
+        training: &mut TrainingState,
+        observers: &mut [&mut dyn TrainingObserver],
+    ) -> Result<(), ModelError> {
+        let rngs: Vec<SeededRng> = (0..self.workers.max(1))
+            .map(|worker| rng::seeded(training.rng.gen(), rng::WORKER + worker as u64))
+            .collect();
+
+        thread::scope(|scope| {
+            let (requests, replies): (Vec<_>, Vec<_>) = rngs
+                .into_iter()
+                .map(|mut rng| {
+                    let (request_sender, request_receiver) = mpsc::channel::<Arc<Snapshot>>();
+                    let (reply_sender, reply_receiver) = mpsc::channel();
+                    scope.spawn(move || {
+                        for snapshot in request_receiver {
+                            if reply_sender.send(play_game(&snapshot, &mut rng)).is_err() {
+                                return;
+                            }
+                        }
+                    });
+                    (request_sender, reply_receiver)
+                })
+                .collect();
+
+            // Workers stop once the request senders are dropped at the end of the scope
+            self.learn(agent, training, observers, &requests, &replies)
+        })
+    }
+
+    fn learn(
+        &self,
+        agent: &mut BorderPatrolAgent,
+        training: &mut TrainingState,
+        observers: &mut [&mut dyn TrainingObserver],
+        requests: &[Sender<Arc<Snapshot>>],
+        replies: &[Receiver<Game>],
+    ) -> Result<(), ModelError> {
+        let mut nn = Arc::new(agent.nn.clone());
+        let mut updates = 0;
+        agent.schedule_rate(training.episode);
+
+        while training.episode < agent.hyper_parms.episodes {
+            let snapshot = Arc::new(Snapshot {
+                nn: nn.clone(),
+                eps: agent.eps_at(training.episode),
+                hyper_parms: agent.hyper_parms.clone(),
+            });
+            let games = requests
+                .len()
+                .min(agent.hyper_parms.episodes - training.episode);
+            for request in &requests[..games] {
+                // Only fails if the worker died, its panic is reported by the scope
+                if request.send(snapshot.clone()).is_err() {
+                    return Ok(());
+                }
+            }
+
+            for reply in &replies[..games] {
+                let Ok(game) = reply.recv() else {
+                    return Ok(());
+                };
+                let mut stats = EpisodeStats {
+                    epsilon: snapshot.eps,
+                    length: game.length,
+                    ..Default::default()
+                };
+                for (state, action, target) in game.moves {
+                    stats.add_update(agent.td_update(
+                        &mut training.replay,
+                        &mut training.rng,
+                        state,
+                        action,
+                        target,
+                    ));
+                }
+                stats.average_updates();
+                updates += stats.updates;
+
+                training.episode += 1;
+                agent.trained_episodes += 1;
+                stats.episode = training.episode;
+                agent.schedule_rate(training.episode);
+
+                for observer in observers.iter_mut() {
+                    observer.after_episode(agent, training, &stats)?;
+                }
+            }
+
+            if updates >= self.refresh_every.max(1) {
+                nn = Arc::new(agent.nn.clone());
+                updates = 0;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Plays one exploring game against itself with the snapshot.
+fn play_game(snapshot: &Snapshot, rng: &mut SeededRng) -> Game {
+    let mut recorder = Recorder {
+        nn: &snapshot.nn,
+        moves: Vec::new(),
+    };
+    let length = ml::self_play_episode(&mut recorder, &snapshot.hyper_parms, snapshot.eps, rng);
+    Game {
+        moves: recorder.moves,
+        length,
+    }
+}
+
+/// Keeps the moves of a game for the learner instead of training on them.
+struct Recorder<'a> {
+    nn: &'a Network,
+    moves: Vec<(Vec<f64>, usize, f64)>,
+}
+
+impl EpisodeLearner for Recorder<'_> {
+    fn q_values(&self, state: &[f64]) -> Vec<f64> {
+        self.nn.run(state)
+    }
+
+    fn learn(&mut self, _: &mut SeededRng, state: Vec<f64>, action: usize, target: f64) {
+        self.moves.push((state, action, target));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn train(workers: usize) -> (Vec<u8>, Vec<f64>) {
+        let hyper_parms = HyperParameters {
+            episodes: 5,
+            seed: 3,
+            ..Default::default()
+        };
+        let mut agent = BorderPatrolAgent::new_with(&[100, 4, 200], hyper_parms);
+        let mut training = TrainingState::new(rng::seeded(3, rng::EXPLORATION), 0);
+        let pool = SelfPlayPool {
+            refresh_every: 1,
+            ..SelfPlayPool::new(workers)
+        };
+        let mut errors = ErrorLog(Vec::new());
+        pool.train(&mut agent, &mut training, &mut [&mut errors])
+            .unwrap();
+        assert_eq!(training.episode, 5);
+        (agent.nn.to_bytes(false), errors.0)
+    }
+
+    struct ErrorLog(Vec<f64>);
+
+    impl TrainingObserver for ErrorLog {
+        fn after_episode(
+            &mut self,
+            _: &mut BorderPatrolAgent,
+            _: &mut TrainingState,
+            stats: &EpisodeStats,
+        ) -> Result<(), ModelError> {
+            self.0.push(stats.td_error);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn runs_are_reproduced_by_seed_and_workers() {
+        assert_eq!(train(3), train(3));
+        assert_ne!(train(3).0, train(2).0);
+    }
+}
@@ -0,0 +1,190 @@
+#![allow(dead_code)]
+/*
+    Exact endgame solver. Searches every order of the remaining lines with negamax and
+    remembers the value of each set of drawn lines, which is enough to identify a position
+    since the owners of the boxes do not matter for the rest of the game.
+
+    Values count boxes, not the result: the value of a position is the boxes the player to
+    move wins minus the boxes the opponent wins until the board is full. Games that are
+    already decided are searched to the end all the same.
+*/
+use std::{cell::RefCell, collections::HashMap};
+
+use rand::{seq::SliceRandom, Rng};
+
+use crate::{
+    ai::{self, AdvancedPlayer},
+    borderpatrol::BorderPatrol,
+    records::LabelledPosition,
+    terminal_borderpatrol::display::Player,
+};
+
+/// Positions with more lines left than this are not solved by default.
+pub const DEFAULT_MAX_LINES: usize = 16;
+/// The search is exponential in the lines left, beyond this it does not finish in time.
+pub const MAX_LINES: usize = 24;
+
+/// Solves the positions that can be reached from one root position. The memo only stays
+/// valid for positions below the root.
+pub struct EndgameSolver {
+    lines: Vec<usize>,
+    memo: HashMap<u64, i32>,
+}
+
+impl EndgameSolver {
+    /// Returns `None` if more than `max_lines` lines are left in `root`.
+    pub fn new(root: &BorderPatrol, max_lines: usize) -> Option<EndgameSolver> {
+        let lines: Vec<usize> = ai::legal_lines(root).collect();
+        if lines.len() > max_lines.min(MAX_LINES) {
+            return None;
+        }
+        Some(EndgameSolver {
+            lines,
+            memo: HashMap::new(),
+        })
+    }
+
+    /// Exact value of every legal line in `env`, which has to be the root or follow from it.
+    pub fn values(&mut self, env: &BorderPatrol) -> Vec<(usize, i32)> {
+        let drawn = self.drawn(env);
+        self.lines
+            .clone()
+            .into_iter()
+            .enumerate()
+            .filter(|&(bit, _)| drawn & 1 << bit == 0)
+            .map(|(bit, line)| (line, self.line_value(env, drawn, bit)))
+            .collect()
+    }
+
+    /// Exact value of `env` for the player to move.
+    pub fn value(&mut self, env: &BorderPatrol) -> i32 {
+        let drawn = self.drawn(env);
+        self.search(env, drawn)
+    }
+
+    /// A line with the highest value, `None` once the board is full.
+    pub fn best_line(&mut self, env: &BorderPatrol) -> Option<usize> {
+        self.values(env)
+            .into_iter()
+            .reduce(|acc, x| if acc.1 >= x.1 { acc } else { x })
+            .map(|(line, _)| line)
+    }
+
+    fn drawn(&self, env: &BorderPatrol) -> u64 {
+        self.lines
+            .iter()
+            .enumerate()
+            .filter(|&(_, &line)| !env.is_possible(line))
+            .fold(0, |drawn, (bit, _)| drawn | 1 << bit)
+    }
+
+    fn search(&mut self, env: &BorderPatrol, drawn: u64) -> i32 {
+        if let Some(&value) = self.memo.get(&drawn) {
+            return value;
+        }
+        let value = (0..self.lines.len())
+            .filter(|&bit| drawn & 1 << bit == 0)
+            .map(|bit| self.line_value(env, drawn, bit))
+            .max()
+            .unwrap_or(0);
+        self.memo.insert(drawn, value);
+        value
+    }
+
+    fn line_value(&mut self, env: &BorderPatrol, drawn: u64, bit: usize) -> i32 {
+        let mover = env.get_turn();
+        let mut next = env.clone();
+        next.set_line(self.lines[bit], next.game_info.turn);
+        let gained = i32::from(next.game_info.score[mover]) - i32::from(env.game_info.score[mover]);
+        let rest = self.search(&next, drawn | 1 << bit);
+        if next.get_turn() == mover {
+            gained + rest
+        } else {
+            gained - rest
+        }
+    }
+}
+
+/// Plays like the advanced player until few enough lines are left, then perfectly.
+pub struct SolverPlayer {
+    advanced: AdvancedPlayer,
+    pub max_lines: usize,
+    solver: RefCell<Option<EndgameSolver>>,
+}
+
+impl SolverPlayer {
+    pub fn new() -> Self {
+        SolverPlayer {
+            advanced: AdvancedPlayer::new(),
+            max_lines: DEFAULT_MAX_LINES,
+            solver: RefCell::new(None),
+        }
+    }
+}
+
+impl Player for SolverPlayer {
+    fn init(&mut self) {
+        *self.solver.get_mut() = None;
+    }
+
+    fn choose_line(&self, border_patrol: &BorderPatrol) -> usize {
+        let mut solver = self.solver.borrow_mut();
+        // A fresh game has more lines left than any endgame, so a stale solver is dropped
+        // as soon as it is asked about a position it has not seen
+        let remaining = ai::legal_lines(border_patrol).count();
+        if solver.as_ref().is_none_or(|s| s.lines.len() < remaining) {
+            *solver = EndgameSolver::new(border_patrol, self.max_lines);
+        }
+        match solver.as_mut().and_then(|s| s.best_line(border_patrol)) {
+            Some(line) => line,
+            None => self.advanced.choose_line(border_patrol),
+        }
+    }
+}
+
+/// Plays `count` games up to an endgame of at most `max_lines` lines and labels every
+/// position from there to the end with exact values. The games take every box they can and
+/// otherwise prefer random lines that do not hand out a box, so the endgames look like the
+/// ones real players reach, and the endgames themselves are played with random lines.
+pub fn endgame_positions(
+    count: usize,
+    max_lines: usize,
+    rng: &mut impl Rng,
+) -> Vec<LabelledPosition> {
+    let mut positions = Vec::new();
+    for _ in 0..count {
+        let mut env = BorderPatrol::new();
+        let mut moves = Vec::new();
+        while ai::legal_lines(&env).count() > max_lines {
+            let line = opening_line(&env, rng);
+            env.set_line(line, env.game_info.turn);
+            moves.push(line);
+        }
+
+        let mut solver = EndgameSolver::new(&env, max_lines).unwrap();
+        loop {
+            let values = solver.values(&env);
+            let Some(&(line, _)) = values.choose(rng) else {
+                break;
+            };
+            positions.push(LabelledPosition {
+                moves: moves.clone(),
+                values,
+            });
+            env.set_line(line, env.game_info.turn);
+            moves.push(line);
+        }
+    }
+    positions
+}
+
+fn opening_line(env: &BorderPatrol, rng: &mut impl Rng) -> usize {
+    if let Some(line) = ai::completing_line(env) {
+        return line;
+    }
+    let safe: Vec<usize> = ai::safe_lines(env).collect();
+    match safe.choose(rng) {
+        Some(&line) => line,
+        None => env.random_action(rng),
+    }
+}
@@ -0,0 +1,272 @@
+#![allow(dead_code)]
+/*
+    Hyperparameter sweeps. A spec file lists training options of `train` with the values to
+    try, separated by whitespace:
+
+        # comments and blank lines are ignored
+        mode = random
+        samples = 12
+        episodes = 2000
+        discount = 0.9 0.95 0.99
+        hidden = 300,300 100
+        learning-rate = 0.05..0.5
+
+    Grid mode trains every combination of the values, random mode trains `samples`
+    configurations and draws every option from its values or uniformly from a `low..high`
+    range, which gives integers if both bounds are integers. Ranges are only allowed in
+    random mode.
+*/
+use std::{cmp::Ordering, fs};
+
+use rand::{seq::SliceRandom, Rng};
+
+use crate::arena::MatchResult;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SweepMode {
+    Grid,
+    Random { samples: usize },
+}
+
+#[derive(Debug, Clone)]
+enum Values {
+    List(Vec<String>),
+    Range { low: f64, high: f64, integer: bool },
+}
+
+#[derive(Debug, Clone)]
+pub struct SweepSpec {
+    pub mode: SweepMode,
+    options: Vec<(String, Values)>,
+}
+
+/// Training options of one configuration, as option name and value.
+pub type Configuration = Vec<(String, String)>;
+
+impl SweepSpec {
+    pub fn read(file_path: &str) -> Result<SweepSpec, String> {
+        let content = fs::read_to_string(file_path)
+            .map_err(|err| format!("Could not read {file_path}: {err}"))?;
+        SweepSpec::parse(&content)
+    }
+
+    pub fn parse(spec: &str) -> Result<SweepSpec, String> {
+        let mut grid = true;
+        let mut samples = 10;
+        let mut options = Vec::new();
+
+        for line in spec.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .ok_or_else(|| format!("Expected option = values but got '{line}'"))?;
+            match key {
+                "mode" => {
+                    grid = match value {
+                        "grid" => true,
+                        "random" => false,
+                        other => return Err(format!("Unknown sweep mode '{other}'")),
+                    }
+                }
+                "samples" => {
+                    samples = value
+                        .parse()
+                        .map_err(|_| format!("Invalid samples '{value}'"))?
+                }
+                _ => options.push((key.to_owned(), parse_values(key, value)?)),
+            }
+        }
+
+        let mode = if grid {
+            if let Some((key, _)) = options
+                .iter()
+                .find(|(_, v)| matches!(v, Values::Range { .. }))
+            {
+                return Err(format!("Range for {key} is only allowed in random mode"));
+            }
+            SweepMode::Grid
+        } else {
+            SweepMode::Random { samples }
+        };
+        Ok(SweepSpec { mode, options })
+    }
+
+    /// Every combination in grid mode, `samples` random draws in random mode.
+    pub fn configurations(&self, rng: &mut impl Rng) -> Vec<Configuration> {
+        match self.mode {
+            SweepMode::Grid => {
+                let mut configurations: Vec<Configuration> = vec![vec![]];
+                for (key, values) in &self.options {
+                    let Values::List(values) = values else {
+                        unreachable!("ranges are rejected in grid mode")
+                    };
+                    configurations = configurations
+                        .into_iter()
+                        .flat_map(|config| {
+                            values.iter().map(move |value| {
+                                let mut config = config.clone();
+                                config.push((key.clone(), value.clone()));
+                                config
+                            })
+                        })
+                        .collect();
+                }
+                configurations
+            }
+            SweepMode::Random { samples } => (0..samples)
+                .map(|_| {
+                    self.options
+                        .iter()
+                        .map(|(key, values)| (key.clone(), sample(values, rng)))
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+}
+
+fn parse_values(key: &str, value: &str) -> Result<Values, String> {
+    if let Some((low, high)) = value.split_once("..") {
+        let bound = |x: &str| {
+            x.trim()
+                .parse::<f64>()
+                .map_err(|_| format!("Invalid range '{value}' for {key}"))
+        };
+        let integer = [low, high].iter().all(|x| x.trim().parse::<i64>().is_ok());
+        let (low, high) = (bound(low)?, bound(high)?);
+        if low > high {
+            return Err(format!("Empty range '{value}' for {key}"));
+        }
+        return Ok(Values::Range { low, high, integer });
+    }
+
+    let values: Vec<String> = value.split_whitespace().map(str::to_owned).collect();
+    if values.is_empty() {
+        return Err(format!("No values for {key}"));
+    }
+    Ok(Values::List(values))
+}
+
+fn sample(values: &Values, rng: &mut impl Rng) -> String {
+    match *values {
+        Values::List(ref values) => values.choose(rng).unwrap().clone(),
+        Values::Range {
+            low,
+            high,
+            integer: true,
+        } => rng.gen_range(low as i64..=high as i64).to_string(),
+        Values::Range { low, high, .. } if low == high => low.to_string(),
+        Values::Range { low, high, .. } => rng.gen_range(low..high).to_string(),
+    }
+}
+
+/// A trained configuration and how it did against the baseline.
+#[derive(Debug, Clone)]
+pub struct SweepResult {
+    /// Position of the configuration in the sweep, starting at 1.
+    pub id: usize,
+    pub configuration: Configuration,
+    pub model: String,
+    pub result: MatchResult,
+}
+
+/// Sorts the results best first, by score and then by average margin, and formats them as
+/// a table.
+pub fn ranking(results: &mut [SweepResult]) -> String {
+    let margin = |r: &SweepResult| r.result.margin as f64 / r.result.games().max(1) as f64;
+    results.sort_by(|a, b| {
+        b.result
+            .score()
+            .partial_cmp(&a.result.score())
+            .unwrap_or(Ordering::Equal)
+            .then(margin(b).partial_cmp(&margin(a)).unwrap_or(Ordering::Equal))
+            .then(a.id.cmp(&b.id))
+    });
+
+    let mut table = format!(
+        "{:>4}  {:>6}  {:>5}  {:>4}  {:>5}  {:>6}  {:>6}  {}\n",
+        "rank", "config", "score", "wins", "draws", "losses", "margin", "options"
+    );
+    for (rank, result) in results.iter().enumerate() {
+        let options: Vec<String> = result
+            .configuration
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect();
+        table.push_str(&format!(
+            "{:>4}  {:>6}  {:>5.3}  {:>4}  {:>5}  {:>6}  {:>+6.1}  {}\n",
+            rank + 1,
+            result.id,
+            result.result.score(),
+            result.result.wins,
+            result.result.draws,
+            result.result.losses,
+            margin(result),
+            options.join(" ")
+        ));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng;
+
+    #[test]
+    fn grid_trains_every_combination() {
+        let spec = SweepSpec::parse(
+            "# a comment\n\nepisodes = 2000\ndiscount = 0.9 0.95 0.99\nhidden = 300,300 100 # two\n",
+        )
+        .unwrap();
+        assert_eq!(spec.mode, SweepMode::Grid);
+
+        let configurations = spec.configurations(&mut rng::seeded(0, rng::SWEEP));
+        assert_eq!(configurations.len(), 6);
+        assert_eq!(
+            configurations[0],
+            [
+                ("episodes".to_owned(), "2000".to_owned()),
+                ("discount".to_owned(), "0.9".to_owned()),
+                ("hidden".to_owned(), "300,300".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn random_draws_from_ranges() {
+        let spec = SweepSpec::parse(
+            "mode = random\nsamples = 20\nlearning-rate = 0.05..0.5\nbatch-size = 1..64\n",
+        )
+        .unwrap();
+        assert_eq!(spec.mode, SweepMode::Random { samples: 20 });
+
+        let configurations = spec.configurations(&mut rng::seeded(0, rng::SWEEP));
+        assert_eq!(configurations.len(), 20);
+        for configuration in configurations {
+            let rate: f64 = configuration[0].1.parse().unwrap();
+            let batch: i64 = configuration[1].1.parse().unwrap();
+            assert!((0.05..0.5).contains(&rate));
+            assert!((1..=64).contains(&batch));
+        }
+    }
+
+    #[test]
+    fn parse_rejects_invalid_specs() {
+        for spec in [
+            "discount",
+            "mode = exhaustive",
+            "mode = random\nsamples = many",
+            "discount =",
+            "learning-rate = 0.05..0.5",
+            "mode = random\nlearning-rate = 0.5..0.05",
+            "mode = random\nlearning-rate = low..0.5",
+        ] {
+            assert!(SweepSpec::parse(spec).is_err(), "accepted '{spec}'");
+        }
+    }
+}
@@ -12,52 +12,351 @@ pub mod display {
 
                 ╣  ║  ╗  ╝  ╚  ╔  ╩ ╦ ╠ ═ ╬             ┣  ┫  ┛  ┳  ┻  ╋  ┃  ┗  ┏  ┓ ━
     */
+    use std::{
+        cell::Cell,
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    };
+
     use crate::{
-        borderpatrol::{BorderPatrol, BOTTOM, PLAYER_ONE, PLAYER_TWO, RIGHT},
-        engine::Game,
+        borderpatrol::{BorderPatrol, BOARD_SIZE, BOTTOM, PLAYER_ONE, PLAYER_TWO, RIGHT},
+        clock::Clock,
+        engine::{MouseEvent, MouseKind, Scene, Timer, Transition},
+        keys::Action,
+        screen::{Color, Screen, Style},
+        theme::Theme,
     };
 
-    const LINE_OFFSET: u8 = 4;
-    const LINES: [&str; 12] = [
-        "────",
-        "│",
-        "────",
-        "│",
-        "════",
-        "║",
-        "════",
-        "║",
-        "━━━━",
-        "┃",
-        "━━━━",
-        "┃",
-    ];
-    const BOXES: [&str; 2] = ["██", "░░"];
-    pub trait Player {
+    /// Rows and columns a box takes on the screen, the largest that fits the terminal is used.
+    const BOX_SIZES: [(usize, usize); 3] = [(4, 9), (3, 7), (2, 5)];
+    /// Rows above the board for the title and the score.
+    const HEADER_ROWS: usize = 3;
+    /// Rows below the board for the player to move, the latest events of the log, the
+    /// explain legend and the help hint of the engine. The log gets fewer rows before the
+    /// boxes get smaller, and none if need be.
+    const STATUS_ROWS: usize = 2;
+    const LOG_ROWS: usize = 4;
+    const LEGEND_ROWS: usize = 6;
+    const HINT_ROWS: usize = 2;
+    /// Events kept in the log.
+    const LOG_CAPACITY: usize = 100;
+    const PLAYER_NAMES: [&str; 2] = ["PlayerOne", "PlayerTwo"];
+    /// How long a new line takes to be drawn across its cells.
+    const LINE_ANIMATION: Duration = Duration::from_millis(200);
+    /// How long the spinner shows each of its frames while a computer player thinks, it
+    /// appears once the player took longer than the delay.
+    const SPINNER_FRAME: Duration = Duration::from_millis(80);
+    const SPINNER_DELAY: Duration = Duration::from_millis(150);
+
+    pub trait Player: Send {
         fn init(&mut self);
-        fn make_move(&self, border_patrol: &mut BorderPatrol);
+        fn choose_line(&self, border_patrol: &BorderPatrol) -> usize;
+
+        /// Chooses a line before `deadline` when playing on a clock. Players that can trade
+        /// strength for time stop thinking early, the others ignore the deadline.
+        fn choose_line_by(&self, border_patrol: &BorderPatrol, _deadline: Instant) -> usize {
+            self.choose_line(border_patrol)
+        }
+
+        /// A value for every line that shows how the player rates it, the higher the better.
+        /// Players that can not explain their moves return `None`.
+        fn explain(&self, _border_patrol: &BorderPatrol) -> Option<Vec<f64>> {
+            None
+        }
+
+        fn make_move(&self, border_patrol: &mut BorderPatrol) {
+            let line = self.choose_line(border_patrol);
+            border_patrol.set_line(line, border_patrol.game_info.turn);
+        }
     }
 
     pub struct TerminalBorderPatrol {
         pub border_patrol: BorderPatrol,
-        pub horizontal_indices: Vec<usize>,
-        pub vertical_indices: Vec<usize>,
         pub orientation: usize,
         pub pos: usize,
         player_one: Option<Box<dyn Player>>,
         player_two: Option<Box<dyn Player>>,
+        /// Show how the computer players rated the lines of their last move.
+        pub explain: bool,
+        explanation: Option<Explanation>,
+        /// The free line under the mouse pointer.
+        hover: Option<usize>,
+        pub theme: Theme,
+        /// The layout of the last render, which mouse events are mapped back through.
+        layout: Cell<Option<Layout>>,
+        thinking: Option<Thinking>,
+        /// The line drawn last while it is still being animated.
+        animation: Option<(usize, Timer)>,
+        /// The clocks of a timed game. They start on the first tick, after that the clock of
+        /// the player to move starts as soon as the line before is drawn.
+        pub clock: Option<Clock>,
+        /// Who plays player one and player two, `human` or the computer player.
+        pub names: [String; 2],
+        last_move: Option<usize>,
+        /// Every line drawn so far, in order.
+        moves: Vec<usize>,
+        /// All lines of a finished game that is reviewed, `moves` are the ones shown.
+        review: Option<Vec<usize>>,
+        /// The player who lost the reviewed game on time, the result once all its lines are
+        /// shown.
+        lost_on_time: Option<usize>,
+        log: Vec<String>,
+    }
+
+    /// A computer player handed back with the line it chose and its explain values.
+    type Choice = (Box<dyn Player>, usize, Option<Vec<f64>>);
+
+    /// A computer player choosing its line on a thread of its own, so the screen keeps
+    /// updating.
+    struct Thinking {
+        handle: JoinHandle<Choice>,
+        timer: Timer,
+    }
+
+    /// The values a player gave the legal lines of its last move, best first.
+    struct Explanation {
+        values: Vec<(usize, f64)>,
+        chosen: usize,
+    }
+
+    impl Explanation {
+        fn new(values: Vec<f64>, border_patrol: &BorderPatrol, chosen: usize) -> Explanation {
+            let mut values: Vec<(usize, f64)> = values
+                .into_iter()
+                .enumerate()
+                .filter(|&(line, _)| border_patrol.is_possible(line))
+                .collect();
+            values.sort_by(|a, b| b.1.total_cmp(&a.1));
+            Explanation { values, chosen }
+        }
+
+        fn value(&self, line: usize) -> Option<f64> {
+            self.values
+                .iter()
+                .find(|&&(l, _)| l == line)
+                .map(|&(_, value)| value)
+        }
+
+        /// Style of `line` on the heatmap of `theme`, scaled between the lowest and highest
+        /// value.
+        fn style(&self, line: usize, theme: &Theme) -> Option<Style> {
+            if line == self.chosen {
+                return Some(theme.chosen);
+            }
+            let value = self.value(line)?;
+            let (high, low) = (self.values.first()?.1, self.values.last()?.1);
+            let scaled = if high > low {
+                (value - low) / (high - low)
+            } else {
+                1.0
+            };
+            let color = theme
+                .heat
+                .get((scaled * (theme.heat.len().max(1) - 1) as f64).round() as usize)?;
+            Some(Style::PLAIN.foreground(Color::Indexed(*color)))
+        }
+    }
+
+    /// Names a line by the box it belongs to, e.g. `row 3, column 4, right`.
+    pub fn describe_line(line: usize) -> String {
+        let side = if (line % 2) as u8 == RIGHT {
+            "right"
+        } else {
+            "bottom"
+        };
+        format!(
+            "row {}, column {}, {side}",
+            line / 20 + 1,
+            (line / 2) % 10 + 1
+        )
+    }
+
+    /// A rectangle of cells on the screen.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Area {
+        row: usize,
+        column: usize,
+        height: usize,
+        width: usize,
+    }
+
+    impl Area {
+        fn contains(&self, row: usize, column: usize) -> bool {
+            (self.row..self.row + self.height).contains(&row)
+                && (self.column..self.column + self.width).contains(&column)
+        }
+
+        fn fill(&self, screen: &mut Screen, glyph: char, style: Style) {
+            let text = glyph.to_string().repeat(self.width);
+            for row in self.row..self.row + self.height {
+                screen.print(row, self.column, &text, style);
+            }
+        }
+
+        fn restyle(&self, screen: &mut Screen, restyle: impl Fn(Style) -> Style) {
+            for row in self.row..self.row + self.height {
+                screen.restyle(row, self.column, self.width, &restyle);
+            }
+        }
+    }
+
+    /// Where the board goes on a terminal: centred, with boxes as large as fit.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Layout {
+        /// Top left corner of the frame.
+        top: usize,
+        left: usize,
+        box_height: usize,
+        box_width: usize,
+        log_rows: usize,
+    }
+
+    impl Layout {
+        /// The layout for a terminal of `size` rows and columns, `None` if even the smallest
+        /// boxes do not fit.
+        fn fit(size: (usize, usize), legend: bool) -> Option<Layout> {
+            let (rows, columns) = size;
+            let fits = |(box_height, box_width), log_rows| {
+                let (height, width) = Layout::needed(box_height, box_width, legend, log_rows);
+                height <= rows && width <= columns
+            };
+            let smallest = BOX_SIZES[BOX_SIZES.len() - 1];
+            let candidates = BOX_SIZES.into_iter().map(|box_size| (box_size, LOG_ROWS));
+            let fewer_log_rows = (0..LOG_ROWS).rev().map(|log_rows| (smallest, log_rows));
+            let ((box_height, box_width), log_rows) = candidates
+                .chain(fewer_log_rows)
+                .find(|&(box_size, log_rows)| fits(box_size, log_rows))?;
+            let (height, width) = Layout::needed(box_height, box_width, legend, log_rows);
+            Some(Layout {
+                top: (rows - height) / 2 + HEADER_ROWS,
+                left: (columns - width) / 2,
+                box_height,
+                box_width,
+                log_rows,
+            })
+        }
+
+        /// Rows and columns of everything drawn around boxes of the given size.
+        fn needed(
+            box_height: usize,
+            box_width: usize,
+            legend: bool,
+            log_rows: usize,
+        ) -> (usize, usize) {
+            let footer = STATUS_ROWS
+                + log_rows
+                + if legend {
+                    LEGEND_ROWS + HINT_ROWS
+                } else {
+                    HINT_ROWS
+                };
+            (
+                HEADER_ROWS + box_height * BOARD_SIZE + 1 + footer,
+                box_width * BOARD_SIZE + 1,
+            )
+        }
+
+        /// The row that names the player to move, the log follows below.
+        fn status_row(&self) -> usize {
+            self.bottom() + STATUS_ROWS
+        }
+
+        /// The first row of the explain legend, below the log and an empty row.
+        fn legend_row(&self) -> usize {
+            self.status_row() + self.log_rows + 2
+        }
+
+        fn width(&self) -> usize {
+            self.box_width * BOARD_SIZE + 1
+        }
+
+        fn bottom(&self) -> usize {
+            self.top + self.box_height * BOARD_SIZE
+        }
+
+        /// The cells inside a box without the lines around it and a column of margin.
+        fn box_area(&self, row: usize, column: usize) -> Area {
+            Area {
+                row: self.top + self.box_height * row + 1,
+                column: self.left + self.box_width * column + 2,
+                height: self.box_height - 1,
+                width: self.box_width - 3,
+            }
+        }
+
+        /// The cells of a line of the board, without the crossings at its ends.
+        fn line_area(&self, row: usize, column: usize, side: u8) -> Area {
+            let (top, left) = (
+                self.top + self.box_height * row,
+                self.left + self.box_width * column,
+            );
+            if side == BOTTOM {
+                Area {
+                    row: top + self.box_height,
+                    column: left + 1,
+                    height: 1,
+                    width: self.box_width - 1,
+                }
+            } else {
+                Area {
+                    row: top + 1,
+                    column: left + self.box_width,
+                    height: self.box_height - 1,
+                    width: 1,
+                }
+            }
+        }
+
+        /// The line drawn at a cell of the screen, the inverse of `line_area`. Border lines
+        /// and the crossings between lines belong to no line.
+        fn line_at(&self, screen_row: usize, screen_column: usize) -> Option<usize> {
+            (0..200).find(|&line| {
+                let (row, column, side) = (line / 20, (line / 2) % 10, (line % 2) as u8);
+                let inside = if side == BOTTOM { row < 9 } else { column < 9 };
+                inside
+                    && self
+                        .line_area(row, column, side)
+                        .contains(screen_row, screen_column)
+            })
+        }
+
+        /// Where the cursor goes on a line, the middle of it without its ends.
+        fn cursor_area(&self, row: usize, column: usize, side: u8) -> Area {
+            let area = self.line_area(row, column, side);
+            if side == BOTTOM {
+                Area {
+                    column: area.column + 1,
+                    width: area.width - 2,
+                    ..area
+                }
+            } else {
+                area
+            }
+        }
     }
 
     impl TerminalBorderPatrol {
         pub fn new() -> Self {
             TerminalBorderPatrol {
                 border_patrol: BorderPatrol::new(),
-                horizontal_indices: vec![],
-                vertical_indices: vec![],
                 orientation: 0,
                 pos: 0,
                 player_one: None,
                 player_two: None,
+                explain: false,
+                explanation: None,
+                hover: None,
+                theme: Theme::default(),
+                layout: Cell::new(None),
+                thinking: None,
+                animation: None,
+                clock: None,
+                names: ["human".to_owned(), "human".to_owned()],
+                last_move: None,
+                moves: Vec::new(),
+                review: None,
+                lost_on_time: None,
+                log: Vec::new(),
             }
         }
 
@@ -82,177 +381,624 @@ pub mod display {
     }
 
     impl TerminalBorderPatrol {
-        fn get_line_display(&self, row: usize, column: usize, side: u8) -> &str {
-            let player_one_line = LINES[(4 + side + LINE_OFFSET) as usize];
-            let player_two_line = LINES[(side + LINE_OFFSET) as usize];
-            let line = LINES[side as usize];
-
+        fn get_line_display(&self, row: usize, column: usize, side: u8) -> (char, Style) {
+            let glyphs = &self.theme.lines;
             if self.border_patrol.board.get_bit(row, column, side + 4) {
-                return player_one_line;
+                return (glyphs[1][side as usize], self.theme.players[0]);
             }
             if self.border_patrol.board.get_bit(row, column, side) {
-                return player_two_line;
+                return (glyphs[2][side as usize], self.theme.players[1]);
             }
 
-            line
+            (glyphs[0][side as usize], self.theme.frame_style)
         }
 
-        fn get_box_display(&self, row: usize, column: usize) -> &str {
+        fn get_box_display(&self, row: usize, column: usize) -> (char, Style) {
             if self.border_patrol.board.get_bit(row, column, PLAYER_ONE) {
-                return BOXES[0];
+                return (self.theme.boxes[0], self.theme.players[0]);
             }
             if self.border_patrol.board.get_bit(row, column, PLAYER_TWO) {
-                return BOXES[1];
+                return (self.theme.boxes[1], self.theme.players[1]);
             }
 
-            "  "
+            (' ', Style::PLAIN)
         }
 
-        fn determine_indices(&mut self) {
-            let content = self.to_string();
+        /// The board with its frame, the boxes and the lines drawn so far. The border lines
+        /// belong to both players and are drawn as part of the frame.
+        fn render_board(&self, screen: &mut Screen, layout: &Layout) {
+            let (frame, style) = (&self.theme.frame, self.theme.frame_style);
+            let bottom = layout.bottom();
+            let edge = |left: char, middle: char, right: char| {
+                let segment: String = frame.horizontal.to_string().repeat(layout.box_width - 1);
+                let inner = [segment.as_str(); BOARD_SIZE].join(&middle.to_string());
+                format!("{left}{inner}{right}")
+            };
+            screen.print(
+                layout.top,
+                layout.left,
+                &edge(frame.top_left, frame.top, frame.top_right),
+                style,
+            );
+            screen.print(
+                bottom,
+                layout.left,
+                &edge(frame.bottom_left, frame.bottom, frame.bottom_right),
+                style,
+            );
+            for r in 0..BOARD_SIZE {
+                let line_row = layout.top + layout.box_height * (r + 1);
+                let border = Area {
+                    row: layout.top + layout.box_height * r + 1,
+                    column: layout.left,
+                    height: layout.box_height - 1,
+                    width: 1,
+                };
+                border.fill(screen, frame.vertical, style);
+                let right_border = Area {
+                    column: layout.left + layout.width() - 1,
+                    ..border
+                };
+                right_border.fill(screen, frame.vertical, style);
+                if line_row < bottom {
+                    screen.print(line_row, layout.left, &frame.left.to_string(), style);
+                    screen.print(
+                        line_row,
+                        layout.left + layout.width() - 1,
+                        &frame.right.to_string(),
+                        style,
+                    );
+                }
+                for c in 0..BOARD_SIZE {
+                    let (glyph, box_style) = self.get_box_display(r, c);
+                    layout.box_area(r, c).fill(screen, glyph, box_style);
+                    if c < BOARD_SIZE - 1 {
+                        let (glyph, line_style) = self.get_line_display(r, c, RIGHT);
+                        layout
+                            .line_area(r, c, RIGHT)
+                            .fill(screen, glyph, line_style);
+                    }
+                    if line_row < bottom {
+                        let (glyph, line_style) = self.get_line_display(r, c, BOTTOM);
+                        layout
+                            .line_area(r, c, BOTTOM)
+                            .fill(screen, glyph, line_style);
+                        if c > 0 {
+                            screen.print(
+                                line_row,
+                                layout.left + layout.box_width * c,
+                                &frame.cross.to_string(),
+                                style,
+                            );
+                        }
+                    }
+                }
+            }
+        }
 
-            self.horizontal_indices = Vec::new();
-            self.vertical_indices = Vec::new();
+        /// Colours the lines by the explain overlay.
+        fn render_explanation(&self, screen: &mut Screen, layout: &Layout) {
+            let Some(explanation) = &self.explanation else {
+                return;
+            };
+            for line in 0..200 {
+                let Some(heat) = explanation.style(line, &self.theme) else {
+                    continue;
+                };
+                let area = layout.line_area(line / 20, (line / 2) % 10, (line % 2) as u8);
+                area.restyle(screen, |style| Style {
+                    bold: style.bold,
+                    ..heat
+                });
+            }
+        }
 
-            for (i, j) in vec![0, 4, 8].into_iter().zip(vec![5, 9, 1]) {
-                content
-                    .match_indices(LINES[i])
-                    .for_each(|(index, _)| self.horizontal_indices.push(index));
-                content
-                    .match_indices(LINES[j])
-                    .for_each(|(index, _)| self.vertical_indices.push(index));
+        /// The lines below the board that list the chosen move and the best alternatives.
+        fn render_legend(&self, screen: &mut Screen, layout: &Layout) {
+            let Some(explanation) = &self.explanation else {
+                return;
+            };
+            let (mut row, left) = (layout.legend_row(), layout.left);
+            let plain = Style::PLAIN;
+            if self.theme.heat.is_empty() {
+                screen.print(row, left, "Values of the last computer move", plain);
+            } else {
+                let column =
+                    screen.print(row, left, "Values of the last computer move, low ", plain);
+                for (i, &color) in self.theme.heat.iter().enumerate() {
+                    screen.print(
+                        row,
+                        column + i,
+                        "■",
+                        plain.foreground(Color::Indexed(color)),
+                    );
+                }
+                screen.print(row, column + self.theme.heat.len(), " high", plain);
             }
 
-            self.horizontal_indices.sort();
-            self.horizontal_indices.splice(0..10, None);
-            let mut cnt = -1;
-            self.vertical_indices.sort();
-            self.vertical_indices = self
-                .vertical_indices
+            let value = |line| match explanation.value(line) {
+                Some(value) => format!("{value:.4}"),
+                None => "-".to_owned(),
+            };
+            row += 1;
+            let column = screen.print(row, left, "Chosen:", self.theme.chosen);
+            let text = format!(
+                " {}  value {}",
+                describe_line(explanation.chosen),
+                value(explanation.chosen)
+            );
+            screen.print(row, column, &text, plain);
+
+            let alternatives = explanation
+                .values
                 .iter()
-                .filter(|_| {
-                    cnt += 1;
-                    if cnt != 0 && (cnt - 1) % 11 < 9 {
-                        return true;
-                    }
-                    return false;
-                })
-                .map(|a| *a)
-                .collect();
+                .filter(|&&(line, _)| line != explanation.chosen)
+                .take(3);
+            for (rank, &(line, _)) in alternatives.enumerate() {
+                row += 1;
+                let text = format!(
+                    "{}.      {}  value {}",
+                    rank + 2,
+                    describe_line(line),
+                    value(line)
+                );
+                screen.print(row, left, &text, plain);
+            }
         }
 
-        pub fn to_string(&self) -> String {
-            let mut content: String = format!(
-                "\n\n\x1B[1m{}   Borderpatrol\n\n{}        PlayerOne     {} - {}      PlayerTwo\n",
-                "\t".repeat(8),
-                "\t".repeat(6),
-                self.border_patrol.game_info.get_player_one_points(),
-                self.border_patrol.game_info.get_player_two_points()
-            );
+        /// Marks the line under the cursor.
+        fn render_cursor(&self, screen: &mut Screen, layout: &Layout) {
+            let (row, column) = self.cursor_line();
+            let style = self.theme.players[self.border_patrol.get_turn()];
+            layout
+                .cursor_area(row, column, self.orientation as u8)
+                .fill(screen, self.theme.cursor, style);
+        }
+
+        /// Highlights the free line under the mouse pointer.
+        fn render_hover(&self, screen: &mut Screen, layout: &Layout) {
+            if let Some(line) = self.hover {
+                let hover = self.theme.hover;
+                layout
+                    .line_area(line / 20, (line / 2) % 10, (line % 2) as u8)
+                    .restyle(screen, |_| hover);
+            }
+        }
+
+        /// Draws the part of the last line that is not drawn yet as a free line.
+        fn render_animation(&self, screen: &mut Screen, layout: &Layout) {
+            let Some((line, timer)) = self.animation.filter(|(_, timer)| !timer.done()) else {
+                return;
+            };
+            let side = (line % 2) as u8;
+            let area = layout.line_area(line / 20, (line / 2) % 10, side);
+            let glyph = self.theme.lines[0][side as usize];
+            let length = area.width.max(area.height);
+            let drawn = (timer.progress() * length as f64).ceil() as usize;
+            let rest = if side == BOTTOM {
+                Area {
+                    column: area.column + drawn,
+                    width: length - drawn,
+                    ..area
+                }
+            } else {
+                Area {
+                    row: area.row + drawn,
+                    height: length - drawn,
+                    ..area
+                }
+            };
+            rest.fill(screen, glyph, self.theme.frame_style);
+        }
+
+        /// The player to move with a spinner while a computer player thinks, or the result once
+        /// the game is over, and below it the latest events, the newest last.
+        fn render_status(&self, screen: &mut Screen, layout: &Layout) {
+            let (row, left) = (layout.status_row(), layout.left);
+            let player = self.border_patrol.get_turn();
+            let name = format!("{} ({})", PLAYER_NAMES[player], self.names[player]);
+            let thinking = self.thinking.as_ref();
+            let (status, style) = match (self.check_victory(), &self.review) {
+                (result, Some(moves)) => {
+                    let result = result
+                        .map(|result| format!(", {result}"))
+                        .unwrap_or_default();
+                    let status = format!(
+                        "Reviewing line {} of {}{result}",
+                        self.moves.len(),
+                        moves.len()
+                    );
+                    (status, Style::PLAIN.bold())
+                }
+                (Some(result), None) => (result, Style::PLAIN.bold()),
+                (None, None) => {
+                    let status = match thinking.filter(|thinking| thinking.timer.done()) {
+                        Some(thinking) => {
+                            let spinner = self.theme.spinner;
+                            let glyph =
+                                spinner[thinking.timer.frame(SPINNER_FRAME) % spinner.len()];
+                            format!("{name} is thinking {glyph}")
+                        }
+                        None if thinking.is_some() => format!("{name} is thinking"),
+                        None => format!("{name} to move"),
+                    };
+                    (status, self.theme.players[player].bold())
+                }
+            };
+            screen.print(row, left, &status, style);
 
-            content.push_str(&("\t".repeat(6) + "┌" + &"────┬".repeat(9) + "────┐\n"));
-            let mut len = content.len();
-            for r in 0..10 {
-                let mut box_row: String = "\t".repeat(6) + "│ ";
-                let mut line_row: String = "\t".repeat(6) + "├";
-                for c in 0..10 {
-                    box_row.push_str(&(self.get_box_display(r, c).to_owned() + " "));
-                    box_row.push_str(&(self.get_line_display(r, c, RIGHT).to_owned() + " "));
-                    line_row.push_str(&(self.get_line_display(r, c, BOTTOM).to_owned() + "┼"));
+            let shown = self.log.len().min(layout.log_rows);
+            for (i, event) in self.log[self.log.len() - shown..].iter().enumerate() {
+                let style = if i + 1 == shown {
+                    Style::PLAIN
+                } else {
+                    self.theme.frame_style
+                };
+                screen.print(row + 1 + i, left, event, style);
+            }
+        }
+
+        /// Marks the last line drawn.
+        fn render_last_move(&self, screen: &mut Screen, layout: &Layout) {
+            if let Some(line) = self.last_move {
+                let last_move = self.theme.last_move;
+                layout
+                    .line_area(line / 20, (line / 2) % 10, (line % 2) as u8)
+                    .restyle(screen, |style| Style {
+                        background: last_move.background.or(style.background),
+                        bold: style.bold || last_move.bold,
+                        ..style
+                    });
+            }
+        }
+
+        /// Adds an event to the log, dropping the oldest once it is full.
+        fn log(&mut self, event: String) {
+            if self.log.len() == LOG_CAPACITY {
+                self.log.remove(0);
+            }
+            self.log.push(event);
+        }
+
+        /// Asks for a larger terminal instead of drawing a board that does not fit.
+        fn render_too_small(&self, screen: &mut Screen) {
+            let (rows, columns) = screen.size();
+            let (box_height, box_width) = BOX_SIZES[BOX_SIZES.len() - 1];
+            let (height, width) = Layout::needed(box_height, box_width, self.explain, 0);
+            let lines = [
+                "Terminal too small".to_owned(),
+                format!("{width}x{height} needed, {columns}x{rows} available"),
+            ];
+            let top = rows.saturating_sub(lines.len()) / 2;
+            for (i, line) in lines.iter().enumerate() {
+                let left = columns.saturating_sub(line.chars().count()) / 2;
+                screen.print(top + i, left, line, Style::PLAIN.bold());
+            }
+        }
+
+        /// Draws a line for the player to move, unless they ran out of time for it.
+        fn draw_line(&mut self, line: usize) {
+            if let Some(clock) = &mut self.clock {
+                if !clock.stop() {
+                    return;
                 }
-                box_row.pop();
-                box_row.pop();
-                line_row.pop();
-                content.push_str(&(box_row + "│\n"));
-                len = content.len();
-                content.push_str(&(line_row + "┤\n"));
-            }
-            content.truncate(len - 1);
-
-            content.push_str(
-                &("\n".to_owned()
-                    + &"\t".repeat(6)
-                    + "└"
-                    + &"────┴".repeat(9)
-                    + "────┘"
-                    + &"\n".repeat(9)),
+            }
+            let (player, score) = (
+                self.border_patrol.get_turn(),
+                self.border_patrol.game_info.score,
             );
+            self.border_patrol
+                .set_line(line, self.border_patrol.game_info.turn);
+            let over = self.check_victory().is_some();
+            if let Some(clock) = self.clock.as_mut().filter(|_| !over) {
+                clock.start(self.border_patrol.get_turn());
+            }
+            self.animation = Some((line, Timer::start(LINE_ANIMATION)));
+            self.last_move = Some(line);
+            self.moves.push(line);
 
-            content
+            let boxes = self.border_patrol.game_info.score[player] - score[player];
+            let mut event = format!(
+                "{:>3}. {} {}",
+                self.moves.len(),
+                PLAYER_NAMES[player],
+                describe_line(line)
+            );
+            match boxes {
+                0 => (),
+                1 => event.push_str(", completes a box and moves again"),
+                _ => event.push_str(&format!(", completes {boxes} boxes and moves again")),
+            }
+            self.log(event);
+        }
+
+        /// Draws a line for the human player to move.
+        fn draw_human_line(&mut self, line: usize) {
+            if !self.human_turn() || !self.border_patrol.is_possible(line) {
+                return;
+            }
+            // The overlay explains a position that no longer exists after this move
+            self.explanation = None;
+            self.draw_line(line);
         }
 
-        fn check_victory(&self) -> Option<String> {
+        /// The computer player to move, if it is not thinking already.
+        fn computer_player(&mut self) -> &mut Option<Box<dyn Player>> {
+            if self.border_patrol.game_info.turn == PLAYER_ONE {
+                &mut self.player_one
+            } else {
+                &mut self.player_two
+            }
+        }
+
+        fn human_turn(&self) -> bool {
+            let computer = if self.border_patrol.game_info.turn == PLAYER_ONE {
+                &self.player_one
+            } else {
+                &self.player_two
+            };
+            computer.is_none()
+                && self.thinking.is_none()
+                && self.review.is_none()
+                && self.check_victory().is_none()
+        }
+
+        /// The finished game with the same players' names and theme, to be stepped through
+        /// from its last line.
+        pub fn review(&self) -> TerminalBorderPatrol {
+            let mut review = TerminalBorderPatrol::new();
+            review.names = self.names.clone();
+            review.theme = self.theme;
+            review.review = Some(self.moves.clone());
+            review.lost_on_time = self.clock.as_ref().and_then(Clock::flagged);
+            review.replay(self.moves.len());
+            review
+        }
+
+        /// Shows the reviewed game after its first `lines` lines.
+        fn replay(&mut self, lines: usize) {
+            let Some(moves) = self.review.take() else {
+                return;
+            };
+            self.border_patrol = BorderPatrol::new();
+            (self.moves, self.log, self.last_move) = (Vec::new(), Vec::new(), None);
+            for &line in moves.iter().take(lines) {
+                self.draw_line(line);
+            }
+            self.animation = None;
+            self.review = Some(moves);
+        }
+
+        /// Lets the computer player to move choose its line on a thread.
+        fn start_thinking(&mut self) {
+            let Some(player) = self.computer_player().take() else {
+                return;
+            };
+            let (border_patrol, explain) = (self.border_patrol.clone(), self.explain);
+            let deadline = self
+                .clock
+                .as_ref()
+                .map(|clock| clock.deadline(border_patrol.get_turn()));
+            let handle = thread::spawn(move || {
+                let values = if explain {
+                    player.explain(&border_patrol)
+                } else {
+                    None
+                };
+                let line = match deadline {
+                    Some(deadline) => player.choose_line_by(&border_patrol, deadline),
+                    None => player.choose_line(&border_patrol),
+                };
+                (player, line, values)
+            });
+            self.thinking = Some(Thinking {
+                handle,
+                timer: Timer::start(SPINNER_DELAY),
+            });
+        }
+
+        /// Draws the line of the computer player once it has chosen one.
+        fn finish_thinking(&mut self) {
+            let Some(thinking) = self
+                .thinking
+                .take_if(|thinking| thinking.handle.is_finished())
+            else {
+                return;
+            };
+            let (player, line, values) = thinking.handle.join().expect("Computer player failed");
+            *self.computer_player() = Some(player);
+            if self.check_victory().is_some() {
+                return;
+            }
+            self.explanation =
+                values.map(|values| Explanation::new(values, &self.border_patrol, line));
+            self.draw_line(line);
+        }
+
+        /// The line under the cursor, the position counts the lines of its orientation.
+        fn cursor_line(&self) -> (usize, usize) {
+            let correct_pos = if self.orientation == 1 {
+                self.pos + self.pos / 9
+            } else {
+                self.pos
+            };
+            (correct_pos / 10, correct_pos % 10)
+        }
+
+        pub fn check_victory(&self) -> Option<String> {
+            let flagged = match &self.review {
+                Some(moves) if moves.len() == self.moves.len() => self.lost_on_time,
+                Some(_) => None,
+                None => self.clock.as_ref().and_then(Clock::flagged),
+            };
+            match flagged {
+                Some(0) => return Some("Player one lost on time!".to_owned()),
+                Some(_) => return Some("Player two lost on time!".to_owned()),
+                None => (),
+            }
             if self.border_patrol.game_info.get_player_one_points() > 50 {
-                return Some("\n".repeat(20) + &"\t".repeat(8) + "Player one won!");
+                return Some("Player one won!".to_owned());
             }
             if self.border_patrol.game_info.get_player_two_points() > 50 {
-                return Some("\n".repeat(20) + &"\t".repeat(8) + "Player two won!");
+                return Some("Player two won!".to_owned());
             }
 
             if self.border_patrol.game_info.get_player_one_points() == 50
                 && self.border_patrol.game_info.get_player_two_points() == 50
             {
-                return Some("\n".repeat(20) + &"\t".repeat(8) + "Draw!");
+                return Some("Draw!".to_owned());
             }
 
             None
         }
     }
 
-    impl Game for TerminalBorderPatrol {
-        fn update(&mut self, content: &mut String) -> bool {
-            if let Some(msg) = self.check_victory() {
-                *content = msg;
-                return false;
-            }
+    impl Scene for TerminalBorderPatrol {
+        /// Computer players make their moves on ticks.
+        fn update(&mut self) -> Transition {
+            Transition::Stay
+        }
 
-            if self.border_patrol.game_info.turn == PLAYER_ONE {
-                if let Some(player) = &self.player_one {
-                    player.make_move(&mut self.border_patrol);
-                }
-                *content = self.init_screen();
-                return true;
-            }
+        fn wait_for_input(&self) -> bool {
+            true
+        }
 
-            if let Some(player) = &self.player_two {
-                player.make_move(&mut self.border_patrol);
+        fn tick(&mut self) -> Transition {
+            if self.check_victory().is_some() {
+                return Transition::Stay;
             }
-            *content = self.init_screen();
-            true
+            self.finish_thinking();
+            let turn = self.border_patrol.get_turn();
+            if let Some(clock) = self
+                .clock
+                .as_mut()
+                .filter(|clock| clock.running().is_none())
+            {
+                clock.start(turn);
+            }
+            // Computer players wait for the line before to be drawn
+            if self.animation.is_some_and(|(_, timer)| !timer.done()) {
+                return Transition::Stay;
+            }
+            if self.thinking.is_none() {
+                self.start_thinking();
+            }
+            Transition::Stay
         }
 
-        fn wait_for_input(&self) -> bool {
-            if self.player_one.is_some() && self.border_patrol.game_info.turn == PLAYER_ONE {
-                return false;
+        fn render(&self, screen: &mut Screen) {
+            let layout = Layout::fit(screen.size(), self.explain);
+            self.layout.set(layout);
+            let Some(layout) = layout else {
+                self.render_too_small(screen);
+                return;
+            };
+
+            let centre = |text: &str| {
+                layout.left + (layout.width().saturating_sub(text.chars().count())) / 2
+            };
+            let title = "Borderpatrol";
+            screen.print(layout.top - 3, centre(title), title, Style::PLAIN.bold());
+            let score = format!(
+                "     {} - {}      ",
+                self.border_patrol.game_info.get_player_one_points(),
+                self.border_patrol.game_info.get_player_two_points()
+            );
+            // The running clock is bold
+            let clocks = self.clock.as_ref().map(|clock| {
+                let style = |player| {
+                    if clock.running() == Some(player) {
+                        Style::PLAIN.bold()
+                    } else {
+                        Style::PLAIN
+                    }
+                };
+                [
+                    (format!("  {}", clock.display(0)), style(0)),
+                    (format!("{}  ", clock.display(1)), style(1)),
+                ]
+            });
+            let [clock_one, clock_two] = clocks.unwrap_or_default();
+            let [one, two] = PLAYER_NAMES;
+            let header = format!("{one}{}{score}{}{two}", clock_one.0, clock_two.0);
+            let row = layout.top - 1;
+            let column = screen.print(row, centre(&header), one, self.theme.players[0]);
+            let column = screen.print(row, column, &clock_one.0, clock_one.1);
+            let column = screen.print(row, column, &score, Style::PLAIN);
+            let column = screen.print(row, column, &clock_two.0, clock_two.1);
+            screen.print(row, column, two, self.theme.players[1]);
+
+            self.render_board(screen, &layout);
+            self.render_animation(screen, &layout);
+            self.render_last_move(screen, &layout);
+            self.render_explanation(screen, &layout);
+            self.render_status(screen, &layout);
+            self.render_legend(screen, &layout);
+            if self.human_turn() {
+                self.render_cursor(screen, &layout);
+                self.render_hover(screen, &layout);
             }
+        }
 
-            if self.player_two.is_some() && self.border_patrol.game_info.turn == PLAYER_TWO {
-                return false;
+        /// Stops the clock while the game is covered by a menu.
+        fn pause(&mut self) {
+            if let Some(clock) = &mut self.clock {
+                clock.pause();
             }
+        }
 
-            true
+        fn resume(&mut self) {
+            if let Some(clock) = &mut self.clock {
+                clock.resume();
+            }
         }
 
-        fn init_screen(&mut self) -> String {
-            self.determine_indices();
-            self.to_string()
+        fn mouse(&mut self, event: MouseEvent) -> Transition {
+            let line = self
+                .layout
+                .get()
+                .and_then(|layout| layout.line_at(event.row, event.column))
+                .filter(|&line| self.border_patrol.is_possible(line) && self.human_turn());
+            self.hover = line;
+            let Some(line) = line.filter(|_| event.kind == MouseKind::Click) else {
+                return Transition::Stay;
+            };
+            let (row, column, side) = (line / 20, (line / 2) % 10, (line % 2) as u8);
+            // The keyboard cursor follows the click
+            self.orientation = side as usize;
+            self.pos = if side == BOTTOM {
+                row * 10 + column
+            } else {
+                row * 9 + column
+            };
+            self.hover = None;
+            self.draw_human_line(line);
+            Transition::Stay
         }
 
-        fn react(&mut self, content: &mut String, key: char) -> bool {
-            match key {
-                'h' if self.pos > 0 => {
+        fn react(&mut self, action: Action) -> Transition {
+            if self.review.is_some() {
+                match action {
+                    Action::Left | Action::Up => self.replay(self.moves.len().saturating_sub(1)),
+                    Action::Right | Action::Down => self.replay(self.moves.len() + 1),
+                    _ => (),
+                }
+                return Transition::Stay;
+            }
+            match action {
+                Action::Left if self.pos > 0 => {
                     self.pos -= 1;
                 }
-                'j' if self.pos < 80 => {
+                Action::Down if self.pos < 80 => {
                     self.pos += 10 - self.orientation;
                 }
-                'k' if self.pos > 9 => {
+                Action::Up if self.pos > 9 => {
                     self.pos -= 10 - self.orientation;
                 }
-                'l' if self.pos < 89 => {
+                Action::Right if self.pos < 89 => {
                     self.pos += 1;
                 }
-                'f' => {
+                Action::Flip => {
                     if self.orientation == 1 {
                         self.pos += self.pos / 9;
                         if self.pos > 89 {
@@ -263,38 +1009,173 @@ pub mod display {
                     }
                     self.orientation = 1 - self.orientation
                 }
+                Action::Place => {
+                    let (row, column) = self.cursor_line();
+                    self.draw_human_line(row * 20 + column * 2 + self.orientation);
+                }
                 _ => (),
             };
+            Transition::Stay
+        }
+    }
 
-            let index = if self.orientation == 0 {
-                self.horizontal_indices[self.pos]
-            } else {
-                self.vertical_indices[self.pos]
-            };
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::clock::TimeControl;
 
-            if key == ' ' {
-                let correct_pos = if self.orientation == 1 {
-                    self.pos + self.pos / 9
-                } else {
-                    self.pos
-                };
+        const LAYOUT: Layout = Layout {
+            top: 3,
+            left: 2,
+            box_height: 3,
+            box_width: 7,
+            log_rows: LOG_ROWS,
+        };
 
-                self.border_patrol.set_line_by(
-                    correct_pos / 10,
-                    correct_pos % 10,
-                    self.orientation as u8,
-                    self.border_patrol.game_info.turn,
-                );
-                content.replace_range(0..content.len(), &self.init_screen());
-                return true;
+        /// The text of a row of the screen, without trailing blanks.
+        fn row_text(screen: &Screen, row: usize) -> String {
+            let text: String = (0..screen.size().1)
+                .map(|column| screen.cell(row, column).map_or(' ', |cell| cell.glyph))
+                .collect();
+            text.trim_end().to_owned()
+        }
+
+        #[test]
+        fn log_drops_the_oldest_events_and_shows_the_newest() {
+            let mut game = TerminalBorderPatrol::new();
+            for i in 0..LOG_CAPACITY + 5 {
+                game.log(format!("event {i}"));
             }
+            assert_eq!(game.log.len(), LOG_CAPACITY);
+            assert_eq!(game.log[0], "event 5");
+            assert_eq!(
+                game.log[LOG_CAPACITY - 1],
+                format!("event {}", LOG_CAPACITY + 4)
+            );
 
-            if self.orientation == 0 {
-                content.replace_range((index + 3)..(index + 9), "○○");
-            } else {
-                content.replace_range(index..(index + 3), "○");
+            // A terminal that fits the smallest boxes with two log rows
+            let mut screen = Screen::sized((30, 51));
+            game.render(&mut screen);
+            let layout = game.layout.get().unwrap();
+            assert_eq!(layout.log_rows, 2);
+            let row = layout.status_row();
+            assert_eq!(row_text(&screen, row), "PlayerOne (human) to move");
+            assert_eq!(row_text(&screen, row + 1), "event 103");
+            assert_eq!(row_text(&screen, row + 2), "event 104");
+        }
+
+        #[test]
+        fn the_clock_of_the_next_player_starts_when_the_line_is_drawn() {
+            let mut game = TerminalBorderPatrol::new();
+            game.clock = Some(Clock::new("5m".parse().unwrap()));
+            game.tick();
+            assert_eq!(game.clock.as_ref().unwrap().running(), Some(0));
+            game.react(Action::Place);
+            // Without waiting for the line to be animated
+            assert!(game.animation.is_some_and(|(_, timer)| !timer.done()));
+            assert_eq!(game.clock.as_ref().unwrap().running(), Some(1));
+        }
+
+        #[test]
+        fn review_shows_the_loss_on_time_at_the_end() {
+            let mut game = TerminalBorderPatrol::new();
+            for action in [Action::Place, Action::Right, Action::Place, Action::Right] {
+                game.react(action);
+            }
+            game.clock = Some(Clock::new(TimeControl {
+                main: Duration::from_millis(1),
+                ..Default::default()
+            }));
+            game.tick();
+            thread::sleep(Duration::from_millis(5));
+            game.react(Action::Place);
+            let lost = Some("Player one lost on time!".to_owned());
+            assert_eq!(game.check_victory(), lost);
+            assert_eq!(game.moves, [0, 2]);
+
+            let mut review = game.review();
+            assert_eq!(review.check_victory(), lost);
+            review.react(Action::Left);
+            assert_eq!(review.check_victory(), None);
+            review.react(Action::Right);
+            assert_eq!(review.check_victory(), lost);
+        }
+
+        fn fitted(size: (usize, usize), legend: bool) -> Option<(usize, usize, usize)> {
+            Layout::fit(size, legend)
+                .map(|layout| (layout.box_height, layout.box_width, layout.log_rows))
+        }
+
+        #[test]
+        fn fit_takes_the_largest_boxes_and_centres_them() {
+            assert_eq!(fitted((52, 91), false), Some((4, 9, LOG_ROWS)));
+            assert_eq!(fitted((51, 91), false), Some((3, 7, LOG_ROWS)));
+            assert_eq!(fitted((52, 90), false), Some((3, 7, LOG_ROWS)));
+            assert_eq!(fitted((42, 71), false), Some((3, 7, LOG_ROWS)));
+            assert_eq!(fitted((41, 71), false), Some((2, 5, LOG_ROWS)));
+
+            let layout = Layout::fit((60, 101), false).unwrap();
+            assert_eq!((layout.top, layout.left), (4 + HEADER_ROWS, 5));
+            assert_eq!(
+                layout.bottom() + 1 + STATUS_ROWS + LOG_ROWS + HINT_ROWS,
+                60 - 4
+            );
+        }
+
+        #[test]
+        fn fit_drops_log_rows_down_to_the_minimum_size() {
+            for log_rows in 0..=LOG_ROWS {
+                assert_eq!(fitted((28 + log_rows, 51), false), Some((2, 5, log_rows)));
+                assert_eq!(fitted((34 + log_rows, 51), true), Some((2, 5, log_rows)));
             }
-            false
+            let smallest = Layout::fit((28, 51), false).unwrap();
+            assert_eq!((smallest.top, smallest.left), (HEADER_ROWS, 0));
+
+            assert_eq!(fitted((27, 51), false), None);
+            assert_eq!(fitted((28, 50), false), None);
+            assert_eq!(fitted((33, 51), true), None);
+            assert_eq!(fitted((0, 0), false), None);
+        }
+
+        #[test]
+        fn line_at_finds_every_cell_of_the_inner_lines() {
+            for line in 0..200 {
+                let (row, column, side) = (line / 20, (line / 2) % 10, (line % 2) as u8);
+                if (side == BOTTOM && row == 9) || (side == RIGHT && column == 9) {
+                    continue;
+                }
+                let area = LAYOUT.line_area(row, column, side);
+                for screen_row in area.row..area.row + area.height {
+                    for screen_column in area.column..area.column + area.width {
+                        assert_eq!(LAYOUT.line_at(screen_row, screen_column), Some(line));
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn line_at_ends_at_crossings_and_the_frame() {
+            // The lines around the top left box
+            assert_eq!(LAYOUT.line_at(6, 3), Some(0));
+            assert_eq!(LAYOUT.line_at(6, 8), Some(0));
+            assert_eq!(LAYOUT.line_at(4, 9), Some(1));
+            assert_eq!(LAYOUT.line_at(5, 9), Some(1));
+            assert_eq!(LAYOUT.line_at(6, 9), None);
+            assert_eq!(LAYOUT.line_at(4, 4), None);
+            // The frame and its corners
+            assert_eq!(LAYOUT.line_at(3, 2), None);
+            assert_eq!(LAYOUT.line_at(3, 9), None);
+            assert_eq!(LAYOUT.line_at(6, 2), None);
+            assert_eq!(LAYOUT.line_at(33, 72), None);
+            // The lines around the bottom right box
+            assert_eq!(LAYOUT.line_at(30, 71), Some(178));
+            assert_eq!(LAYOUT.line_at(30, 72), None);
+            assert_eq!(LAYOUT.line_at(32, 65), Some(197));
+            assert_eq!(LAYOUT.line_at(33, 65), None);
+            assert_eq!(LAYOUT.line_at(32, 71), None);
+            // Outside of the board
+            assert_eq!(LAYOUT.line_at(0, 0), None);
+            assert_eq!(LAYOUT.line_at(100, 100), None);
         }
     }
 }
@@ -0,0 +1,274 @@
+#![allow(dead_code)]
+/*
+    Colour themes of the terminal game. A theme gives the glyphs of the board and the styles
+    of the players, the frame and the overlays. The chosen theme is stored by name in the
+    `theme` file of the config directory.
+
+        classic         box drawing, players told apart by their line and box glyphs
+        color           classic glyphs with a blue and a red player
+        colorblind      blue and orange from the Okabe-Ito palette, safe for all common
+                        kinds of colour blindness
+        high-contrast   bold bright colours on the default background
+        mono            plain ASCII without any colour, for terminals without Unicode
+*/
+use std::{fmt, fs, path::Path, str::FromStr};
+
+use crate::{
+    config,
+    screen::{Color, Style},
+};
+
+const THEME_FILE: &str = "theme";
+
+/// The glyphs the frame of the board is drawn with.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameGlyphs {
+    pub top_left: char,
+    pub top: char,
+    pub top_right: char,
+    pub left: char,
+    pub cross: char,
+    pub right: char,
+    pub bottom_left: char,
+    pub bottom: char,
+    pub bottom_right: char,
+    pub horizontal: char,
+    pub vertical: char,
+}
+
+const BOX_DRAWING: FrameGlyphs = FrameGlyphs {
+    top_left: '┌',
+    top: '┬',
+    top_right: '┐',
+    left: '├',
+    cross: '┼',
+    right: '┤',
+    bottom_left: '└',
+    bottom: '┴',
+    bottom_right: '┘',
+    horizontal: '─',
+    vertical: '│',
+};
+
+const ASCII: FrameGlyphs = FrameGlyphs {
+    top_left: '+',
+    top: '+',
+    top_right: '+',
+    left: '+',
+    cross: '+',
+    right: '+',
+    bottom_left: '+',
+    bottom: '+',
+    bottom_right: '+',
+    horizontal: '-',
+    vertical: '|',
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+    pub name: &'static str,
+    pub frame: FrameGlyphs,
+    /// Free lines, then the lines of player one and player two, each horizontal and vertical.
+    /// Lines and boxes are drawn by repeating their glyph over the cells they cover.
+    pub lines: [[char; 2]; 3],
+    /// Boxes of player one and player two.
+    pub boxes: [char; 2],
+    /// The glyph of the cursor on a line.
+    pub cursor: char,
+    /// Frames of the spinner shown while a computer player thinks.
+    pub spinner: &'static [char],
+    pub frame_style: Style,
+    /// Lines, boxes and names of player one and player two.
+    pub players: [Style; 2],
+    pub hover: Style,
+    /// The line the explained player chose.
+    pub chosen: Style,
+    /// Added to the style of the line drawn last.
+    pub last_move: Style,
+    /// Colours of the explain heatmap from low to high values, empty for no heatmap.
+    pub heat: &'static [u8],
+}
+
+const CLASSIC_LINES: [[char; 2]; 3] = [['─', '│'], ['━', '┃'], ['═', '║']];
+const CLASSIC_BOXES: [char; 2] = ['█', '░'];
+const BRAILLE_SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+/// 256 colour palette from cold to hot.
+const HEAT: [u8; 7] = [21, 33, 51, 46, 226, 208, 196];
+
+pub const THEMES: [Theme; 5] = [
+    Theme {
+        name: "classic",
+        frame: BOX_DRAWING,
+        lines: CLASSIC_LINES,
+        boxes: CLASSIC_BOXES,
+        cursor: '○',
+        spinner: &BRAILLE_SPINNER,
+        frame_style: Style::PLAIN,
+        players: [Style::PLAIN, Style::PLAIN],
+        hover: Style::PLAIN.foreground(Color::Indexed(226)).bold(),
+        chosen: Style::PLAIN.foreground(Color::Indexed(201)),
+        last_move: Style::PLAIN.background(Color::Indexed(238)),
+        heat: &HEAT,
+    },
+    Theme {
+        name: "color",
+        frame: BOX_DRAWING,
+        lines: CLASSIC_LINES,
+        boxes: CLASSIC_BOXES,
+        cursor: '○',
+        spinner: &BRAILLE_SPINNER,
+        frame_style: Style::PLAIN.foreground(Color::Indexed(245)),
+        players: [
+            Style::PLAIN.foreground(Color::Indexed(39)),
+            Style::PLAIN.foreground(Color::Indexed(203)),
+        ],
+        hover: Style::PLAIN.foreground(Color::Indexed(226)).bold(),
+        chosen: Style::PLAIN.foreground(Color::Indexed(201)),
+        last_move: Style::PLAIN.background(Color::Indexed(238)),
+        heat: &HEAT,
+    },
+    Theme {
+        name: "colorblind",
+        frame: BOX_DRAWING,
+        lines: CLASSIC_LINES,
+        boxes: CLASSIC_BOXES,
+        cursor: '○',
+        spinner: &BRAILLE_SPINNER,
+        frame_style: Style::PLAIN.foreground(Color::Indexed(245)),
+        players: [
+            Style::PLAIN.foreground(Color::Rgb(0, 114, 178)),
+            Style::PLAIN.foreground(Color::Rgb(230, 159, 0)),
+        ],
+        hover: Style::PLAIN.foreground(Color::Rgb(86, 180, 233)).bold(),
+        chosen: Style::PLAIN.foreground(Color::Rgb(204, 121, 167)),
+        last_move: Style::PLAIN.background(Color::Indexed(238)),
+        // Blue to yellow, readable without telling red from green
+        heat: &[17, 19, 27, 33, 74, 144, 184, 226],
+    },
+    Theme {
+        name: "high-contrast",
+        frame: BOX_DRAWING,
+        lines: [['─', '│'], ['━', '┃'], ['━', '┃']],
+        boxes: ['█', '█'],
+        cursor: '●',
+        spinner: &BRAILLE_SPINNER,
+        frame_style: Style::PLAIN.foreground(Color::Indexed(15)).bold(),
+        players: [
+            Style::PLAIN.foreground(Color::Indexed(14)).bold(),
+            Style::PLAIN.foreground(Color::Indexed(11)).bold(),
+        ],
+        hover: Style::PLAIN
+            .foreground(Color::Indexed(0))
+            .background(Color::Indexed(15))
+            .bold(),
+        chosen: Style::PLAIN.foreground(Color::Indexed(13)).bold(),
+        last_move: Style::PLAIN.background(Color::Indexed(8)),
+        heat: &HEAT,
+    },
+    Theme {
+        name: "mono",
+        frame: ASCII,
+        lines: [[' ', ' '], ['=', 'H'], ['-', '|']],
+        boxes: ['#', '.'],
+        cursor: 'o',
+        spinner: &['|', '/', '-', '\\'],
+        frame_style: Style::PLAIN,
+        players: [Style::PLAIN, Style::PLAIN],
+        hover: Style::PLAIN.bold(),
+        chosen: Style::PLAIN.bold(),
+        last_move: Style::PLAIN.bold(),
+        heat: &[],
+    },
+];
+
+impl Default for Theme {
+    fn default() -> Self {
+        THEMES[1]
+    }
+}
+
+impl Theme {
+    /// The theme stored in the config directory, the default theme if none is stored.
+    pub fn load() -> Result<Theme, String> {
+        match config::config_file(THEME_FILE) {
+            Some(file_path) => Theme::read(&file_path),
+            None => Ok(Theme::default()),
+        }
+    }
+
+    /// The theme named in a file, the default theme if there is no such file.
+    pub fn read(file_path: &Path) -> Result<Theme, String> {
+        if !file_path.exists() {
+            return Ok(Theme::default());
+        }
+        let name = fs::read_to_string(file_path)
+            .map_err(|err| format!("Could not read {}: {err}", file_path.display()))?;
+        name.trim().parse()
+    }
+
+    /// Stores the theme in the config directory, so it is used from now on.
+    pub fn save(&self) -> Result<(), String> {
+        let dir = config::config_dir()
+            .ok_or("No config directory, neither XDG_CONFIG_HOME nor HOME is set")?;
+        let file_path = dir.join(THEME_FILE);
+        fs::create_dir_all(&dir)
+            .and_then(|_| fs::write(&file_path, format!("{}\n", self.name)))
+            .map_err(|err| format!("Could not write {}: {err}", file_path.display()))
+    }
+
+    /// Style of player one (0) or player two (1).
+    pub fn player(&self, player: usize) -> Style {
+        self.players[player]
+    }
+}
+
+impl FromStr for Theme {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        THEMES
+            .into_iter()
+            .find(|theme| theme.name == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = THEMES.iter().map(|theme| theme.name).collect();
+                format!("Unknown theme '{name}', choose one of {}", names.join(", "))
+            })
+    }
+}
+
+impl fmt::Display for Theme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_parses_the_name_and_falls_back_to_the_default() {
+        let file_path =
+            std::env::temp_dir().join(format!("borderpatrol-{}-theme", std::process::id()));
+        let _ = fs::remove_file(&file_path);
+        assert_eq!(Theme::read(&file_path).unwrap().name, Theme::default().name);
+
+        fs::write(&file_path, "  mono\n").unwrap();
+        assert_eq!(Theme::read(&file_path).unwrap().name, "mono");
+
+        fs::write(&file_path, "neon\n").unwrap();
+        let err = Theme::read(&file_path).unwrap_err();
+        fs::remove_file(&file_path).unwrap();
+        assert_eq!(
+            err,
+            "Unknown theme 'neon', choose one of classic, color, colorblind, high-contrast, mono"
+        );
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for theme in THEMES {
+            assert_eq!(theme.to_string().parse::<Theme>().unwrap().name, theme.name);
+        }
+    }
+}